@@ -1,8 +1,20 @@
 #[path = "cartridge/mod.rs"]
-mod cartridge;
+pub mod cartridge;
 use cartridge::Cartridge;
+use cartridge::game_db::GameDb;
+use crate::apu::APU;
+use crate::cdl::{self, CodeDataLog};
+use crate::cheats::Cheats;
+use crate::cpu::{Bus, IrqSource};
+use crate::heatmap::Heatmap;
+use cartridge::nsf::Nsf;
 const MEM_SIZE: usize = 2048;
+// FDS sound peaks at a bit under half of the 2A03's full output.
+const EXPANSION_AUDIO_LEVEL: f32 = 0.4;
+const DOTS_PER_SCANLINE: usize = 341;
+const SCANLINES_PER_FRAME: usize = 262;
 use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 // Memory
 // ========
@@ -19,11 +31,106 @@ use std::fs;
 // 0x10000  => PRG-ROM (Upper Bank)
 
 
+// Frame, scanline and dot the PPU is at after cpu_cycles CPU cycles. There is
+// no PPU yet: with rendering off it runs 3 dots per CPU cycle from power on.
+pub fn ppu_position(cpu_cycles: usize) -> (usize, usize, usize) {
+    let dots = cpu_cycles * 3;
+    let scanlines = dots / DOTS_PER_SCANLINE;
+    (scanlines / SCANLINES_PER_FRAME, scanlines % SCANLINES_PER_FRAME, dots % DOTS_PER_SCANLINE)
+}
+
+// What the 2KB of RAM holds after power_on. Real consoles come up with
+// something close to HardwareLike but it varies, and some games depend on it.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum RamInit {
+    Zeros,
+    Ones,
+    // Same seed, same contents.
+    Random(u64),
+    // $00 and $FF in alternating runs of 4 bytes.
+    HardwareLike,
+}
+
+impl RamInit {
+    // "zeros", "ff", "random", "random:SEED" or "hardware". Plain "random"
+    // takes its seed from the clock, so every run differs, give a seed to
+    // get the same RAM again.
+    pub fn parse(name: &str) -> Option<RamInit> {
+        match name {
+            "zeros" => Some(RamInit::Zeros),
+            "ff" => Some(RamInit::Ones),
+            "random" => {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+                Some(RamInit::Random(now.as_nanos() as u64))
+            }
+            "hardware" => Some(RamInit::HardwareLike),
+            _ => name.strip_prefix("random:")?.parse().ok().map(RamInit::Random),
+        }
+    }
+
+    pub fn fill(self, memory: &mut [u8]) {
+        match self {
+            RamInit::Zeros => memory.fill(0x00),
+            RamInit::Ones => memory.fill(0xFF),
+            RamInit::Random(seed) => {
+                // xorshift64, which can't start from 0.
+                let mut state = seed ^ 0x9E37_79B9_7F4A_7C15;
+                for byte in memory.iter_mut() {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    *byte = (state >> 32) as u8;
+                }
+            }
+            RamInit::HardwareLike => {
+                for (i, byte) in memory.iter_mut().enumerate() {
+                    *byte = if i & 4 == 0 { 0x00 } else { 0xFF };
+                }
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum AddressSpace {
+    Cpu,
+    Ppu,
+}
+
+// One read or write, as seen by BUS::accesses.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Access {
+    pub space: AddressSpace,
+    pub write: bool,
+    pub addr: u16,
+    pub data: u8,
+}
+
+#[allow(clippy::upper_case_acronyms)]
 pub struct BUS {
     
     pub memory: [u8;MEM_SIZE],
     pub cartridge: Option<Cartridge>,
+    pub apu: APU,
     pub system_clock_count: usize,
+    pub ram_init: RamInit,
+    // Every read and write goes in here while it is Some, for the debugger's watchpoints.
+    pub accesses: Option<Vec<Access>>,
+    // Code/data log of the cartridge's ROM while it is Some.
+    pub code_data_log: Option<CodeDataLog>,
+    // Access counts and uninitialized RAM reads while it is Some.
+    pub heatmap: Option<Heatmap>,
+    // Read cheats go over cartridge reads, Freeze ones into memory at the start of every frame.
+    pub cheats: Cheats,
+    // CPU cycles since power on, to tell when a frame starts.
+    cycles: usize,
+    // PPUADDR and PPUDATA, the PPU registers there are until the PPU is.
+    ppu_addr: u16,
+    // The next $2006 write is the low byte.
+    ppu_addr_low: bool,
+    // Added to ppu_addr by each $2007 access, 1 or 32 as $2000 sets it.
+    ppu_addr_increment: u16,
+    ppu_read_buffer: u8,
 }
 
 impl BUS {
@@ -31,40 +138,265 @@ impl BUS {
         BUS {
             memory: [0;MEM_SIZE],
             cartridge: None,
+            apu: APU::new(),
             system_clock_count: 0,
+            ram_init: RamInit::Zeros,
+            accesses: None,
+            code_data_log: None,
+            heatmap: None,
+            cheats: Cheats::new(),
+            cycles: 0,
+            ppu_addr: 0,
+            ppu_addr_low: false,
+            ppu_addr_increment: 1,
+            ppu_read_buffer: 0,
         }
     }
 
-    pub fn read(&mut self, addr: u16) -> u8 {
-        if self.cartridge.is_some() && self.cartridge.as_ref().unwrap().can_cpu_read(addr) {
-            // Cartridge Address Range
-            self.cartridge.as_ref().unwrap().cpu_read(addr)
+    // Patches (IPS/BPS/UPS) are applied in order to the ROM in memory, the file is left untouched.
+    // Its header is checked against game_db after they are.
+    pub fn load_cart(&mut self, name: String, patches: &[String], game_db: &GameDb) {
+        let mut rom = fs::read(name).expect("Error: Cannot read ROM");
+        for patch_name in patches {
+            let patch = fs::read(patch_name).expect("Error: Cannot read patch");
+            rom = cartridge::patch::apply(&rom, &patch);
+        }
+        self.cartridge = Some(Cartridge::with_game_db(&rom, game_db));
+    }
+
+    pub fn load_disk(&mut self, name: String, bios_name: String) {
+        let disk = fs::read(name).expect("Error: Cannot read disk image");
+        let bios = fs::read(bios_name).expect("Error: Cannot read FDS BIOS");
+        self.cartridge = Some(Cartridge::new_disk(&disk, &bios));
+    }
+
+    pub fn load_nsf(&mut self, nsf: &Nsf) {
+        self.cartridge = Some(Cartridge::new_nsf(nsf));
+    }
+
+    // PPU address space. Only the cartridge's $0000-$1FFF pattern tables are
+    // there until the PPU with its nametables and palette is.
+    // The PPU's own fetches, for rendering.
+    pub fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.log_chr(addr, cdl::RENDERED);
+        let data = self.ppu_peek(addr);
+        self.record(AddressSpace::Ppu, false, addr, data);
+        data
+    }
+
+    // What a CPU read of $2007 fetches.
+    pub fn ppu_read_port(&mut self, addr: u16) -> u8 {
+        self.log_chr(addr, cdl::CHR_READ);
+        let data = self.ppu_peek(addr);
+        self.record(AddressSpace::Ppu, false, addr, data);
+        data
+    }
+
+    pub fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.record(AddressSpace::Ppu, true, addr, data);
+        if let Some(cartridge) = self.cartridge.as_mut().filter(|c| c.can_ppu_read(addr)) {
+            cartridge.ppu_write(addr, data);
+        }
+    }
+
+    pub fn ppu_peek(&self, addr: u16) -> u8 {
+        match self.cartridge.as_ref().filter(|c| c.can_ppu_read(addr)) {
+            Some(cartridge) => cartridge.ppu_read(addr),
+            None => 0,
+        }
+    }
+
+    // A CPU read of $2007. Reads below the palette come out of a buffer, a
+    // read late.
+    fn read_ppu_data(&mut self) -> u8 {
+        let addr = self.ppu_addr & 0x3FFF;
+        self.ppu_addr = self.ppu_addr.wrapping_add(self.ppu_addr_increment);
+        let data = self.ppu_read_port(addr);
+        if addr >= 0x3F00 {
+            data
+        } else {
+            std::mem::replace(&mut self.ppu_read_buffer, data)
+        }
+    }
+
+    fn write_ppu_register(&mut self, addr: u16, data: u8) {
+        match addr & 0x0007 {
+            0x0000 => self.ppu_addr_increment = if data & 0x04 != 0 { 32 } else { 1 },
+            0x0006 => {
+                self.ppu_addr = if self.ppu_addr_low {
+                    (self.ppu_addr & 0xFF00) | data as u16
+                } else {
+                    (self.ppu_addr & 0x00FF) | ((data as u16 & 0x3F) << 8)
+                };
+                self.ppu_addr_low = !self.ppu_addr_low;
+            }
+            0x0007 => {
+                self.ppu_write(self.ppu_addr & 0x3FFF, data);
+                self.ppu_addr = self.ppu_addr.wrapping_add(self.ppu_addr_increment);
+            }
+            _ => {} //TODO: The rest of the PPU registers
+        }
+    }
+
+    fn log_chr(&mut self, addr: u16, flags: u8) {
+        if let Some(log) = self.code_data_log.as_mut() {
+            if let Some(offset) = self.cartridge.as_ref().and_then(|c| c.chr_rom_offset(addr)) {
+                log.log_chr(offset, flags);
+            }
+        }
+    }
+
+    fn record(&mut self, space: AddressSpace, write: bool, addr: u16, data: u8) {
+        if let Some(accesses) = self.accesses.as_mut() {
+            accesses.push(Access { space, write, addr, data });
+        }
+    }
+
+    // APU and cartridge expansion sound mixed together, in 0.0..=1.0
+    pub fn audio_output(&self) -> f32 {
+        let expansion = self.cartridge.as_ref().map_or(0.0, |c| c.audio_output());
+        (self.apu.output() + expansion * EXPANSION_AUDIO_LEVEL) / (1.0 + EXPANSION_AUDIO_LEVEL)
+    }
+}
+
+impl Default for BUS {
+    fn default() -> Self {
+        BUS::new()
+    }
+}
+
+impl Bus for BUS {
+    fn read(&mut self, addr: u16) -> u8 {
+        let data = if let Some(cartridge) = self.cartridge.as_mut().filter(|c| c.can_cpu_read(addr)) {
+            // Cartridge Address Range, through the cheats
+            let data = cartridge.cpu_read(addr);
+            self.cheats.read(addr, data)
         } else if addr <= 0x1FFF {
             // System RAM Address Range, mirrored every 2048
             self.memory[(addr & 0x07FF) as usize]
-        } else if addr >= 0x2000 && addr <= 0x3FFF {
+        } else if (0x2000..=0x3FFF).contains(&addr) {
             // PPU Address range, mirrored every 8
-            0 //TODO: Return ppu read data
+            match addr & 0x0007 {
+                // PPUSTATUS, which also resets the $2006 latch
+                0x0002 => {
+                    self.ppu_addr_low = false;
+                    0 //TODO: Return vblank and sprite flags
+                }
+                0x0007 => self.read_ppu_data(),
+                _ => 0,
+            }
+        } else if addr == 0x4015 {
+            self.apu.read_status()
         } else {
-            panic!("Reading at wrong address from bus! {:4x}", addr);
+            // APU/IO registers that can't be read, and cartridge space nothing answers to (open bus)
+            0
+        };
+        self.record(AddressSpace::Cpu, false, addr, data);
+        if let Some(heatmap) = self.heatmap.as_mut() {
+            heatmap.read(addr);
         }
+        data
     }
 
-
-    pub fn write(&mut self, addr: u16, data: u8) {
-        if addr <= 0x1FFF {
+    fn write(&mut self, addr: u16, data: u8) {
+        self.record(AddressSpace::Cpu, true, addr, data);
+        if let Some(heatmap) = self.heatmap.as_mut() {
+            heatmap.write(addr);
+        }
+        if let Some(cartridge) = self.cartridge.as_mut().filter(|c| c.can_cpu_write(addr)) {
+            // Cartridge Address Range
+            cartridge.cpu_write(addr, data);
+        } else if addr <= 0x1FFF {
             // System RAM Address Range, mirrored every 2048
             self.memory[(addr & 0x07FF) as usize] = data;
-        } else if addr >= 0x2000 && addr <= 0x3FFF {
+        } else if (0x2000..=0x3FFF).contains(&addr) {
             // PPU Address range, mirrored every 8
-            //TODO: Write data to ppu
+            self.write_ppu_register(addr, data);
+        } else if addr == 0x4014 || addr == 0x4016 {
+            //TODO: OAM DMA and controllers
+        } else if (0x4000..=0x4017).contains(&addr) {
+            // APU registers
+            self.apu.write(addr, data);
+        }
+    }
+
+    // Registers read as 0.
+    fn peek(&self, addr: u16) -> u8 {
+        if let Some(cartridge) = self.cartridge.as_ref().filter(|c| c.can_cpu_read(addr)) {
+            self.cheats.read(addr, cartridge.cpu_peek(addr))
+        } else if addr <= 0x1FFF {
+            self.memory[(addr & 0x07FF) as usize]
         } else {
-            panic!("Reading at wrong address from bus! {:4x}", addr);
+            0
+        }
+    }
+
+    fn power_on(&mut self) {
+        self.cycles = 0;
+        self.ram_init.fill(&mut self.memory);
+        if let Some(heatmap) = self.heatmap.as_mut() {
+            heatmap.power_on();
         }
     }
 
-    pub fn load_cart(&mut self, name: String) {
-        let rom = fs::read(name).expect("Error: Cannot read ROM");
-        self.cartridge = Some(Cartridge::new(&rom));
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        self.cartridge.as_ref()?.prg_rom_offset(addr)
+    }
+
+    fn code_data(&mut self, addr: u16, flags: u8) {
+        if let Some(log) = self.code_data_log.as_mut() {
+            if let Some(offset) = self.cartridge.as_ref().and_then(|c| c.prg_rom_offset(addr)) {
+                log.log_prg(offset, addr, flags);
+            }
+        }
+        if let Some(heatmap) = self.heatmap.as_mut().filter(|_| flags & (cdl::CODE | cdl::DATA) != 0) {
+            heatmap.used(addr);
+        }
     }
-}
\ No newline at end of file
+
+    fn execute(&mut self, addr: u16) {
+        if let Some(heatmap) = self.heatmap.as_mut() {
+            heatmap.execute(addr);
+        }
+    }
+
+    // Clocks everything that runs off the CPU clock.
+    fn tick(&mut self) {
+        self.cycles += 1;
+        if !self.cheats.is_empty() && ppu_position(self.cycles).0 != ppu_position(self.cycles - 1).0 {
+            let heatmap = &mut self.heatmap;
+            self.cheats.freeze(&mut self.memory, |addr| {
+                if let Some(heatmap) = heatmap.as_mut() {
+                    heatmap.write(addr);
+                }
+            });
+        }
+        self.apu.clock();
+        if let Some(addr) = self.apu.dmc_request() {
+            let data = self.read(addr);
+            self.code_data(addr, cdl::PCM);
+            self.apu.dmc_fill(data);
+        }
+        if let Some(cartridge) = self.cartridge.as_mut() {
+            cartridge.clock();
+        }
+    }
+
+    fn irq_lines(&self) -> u8 {
+        let mut lines = 0;
+        if self.apu.frame_irq() {
+            lines |= IrqSource::FrameCounter as u8;
+        }
+        if self.apu.dmc_irq() {
+            lines |= IrqSource::Dmc as u8;
+        }
+        if self.cartridge.as_ref().is_some_and(|c| c.irq()) {
+            lines |= IrqSource::Mapper as u8;
+        }
+        lines
+    }
+
+    fn nmi_line(&self) -> bool {
+        false //TODO: PPU vblank NMI
+    }
+}
@@ -8,10 +8,10 @@ pub struct CartridgeData {
 impl CartridgeData {
     pub fn new(prg_rom: Vec<u8>, prg_ram: Vec<u8>, chr_rom: Vec<u8>, chr_ram: Vec<u8>) -> Self {
         CartridgeData {
-            prg_rom: prg_rom,
-            prg_ram: prg_ram,
-            chr_rom: chr_rom,
-            chr_ram: chr_ram,
+            prg_rom,
+            prg_ram,
+            chr_rom,
+            chr_ram,
         }
     }
 }
\ No newline at end of file
@@ -10,19 +10,21 @@ const CHR_RAM_PAGE_SIZE: usize = 0x2000;
 pub struct CartridgeHeader {
     pub mapper_number: u8,
     pub mirroring: Mirroring,
+    pub battery: bool,
     pub prg_rom_pages: usize,
     pub prg_ram_pages: usize,
     pub chr_rom_pages: usize,
 }
 
 impl CartridgeHeader {
-    pub fn new(mapper: u8, mirroring: Mirroring, prg_rom_pages: usize, prg_ram_pages: usize, chr_rom_pages: usize) -> Self {
+    pub fn new(mapper: u8, mirroring: Mirroring, battery: bool, prg_rom_pages: usize, prg_ram_pages: usize, chr_rom_pages: usize) -> Self {
         CartridgeHeader {
             mapper_number: mapper,
-            mirroring: mirroring,
-            prg_rom_pages: prg_rom_pages,
-            prg_ram_pages: prg_ram_pages,
-            chr_rom_pages: chr_rom_pages,
+            mirroring,
+            battery,
+            prg_rom_pages,
+            prg_ram_pages,
+            chr_rom_pages,
         }
     }
 
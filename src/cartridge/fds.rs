@@ -0,0 +1,457 @@
+use super::Mapper;
+use super::MappedAddress;
+use super::Mirroring;
+use super::fds_audio::FdsAudio;
+
+// Famicom Disk System RAM adapter
+// ========
+// CPU Address Bus
+// 0x4020 -> 0x4026: Timer IRQ, I/O enable, write data, drive control, expansion output
+// 0x4030 -> 0x4033: Disk status, read data, drive status, expansion input
+// 0x4040 -> 0x4097: Sound (see fds_audio.rs)
+// 0x6000 -> 0xDFFF: 32KB PRG RAM
+// 0xE000 -> 0xFFFF: 8KB disk BIOS
+//
+// PPU Address Bus
+// 0x0000 -> 0x1FFF: 8KB CHR RAM
+//
+// Disk images (.fds/QD) only store the blocks. The drive reads a raw stream
+// with gaps, a 0x80 start mark before every block and a CRC after it, so the
+// sides are expanded to that layout on load.
+
+pub const FDS_MAGIC: &[u8; 4] = b"FDS\x1a";
+const FDS_HEADER_SIZE: usize = 16;
+const FDS_SIDE_SIZE: usize = 65500;
+const QD_SIDE_SIZE: usize = 0x10000;
+const BIOS_SIZE: usize = 0x2000;
+
+const LEAD_IN_GAP: usize = 28300 / 8;
+const BLOCK_GAP: usize = 976 / 8;
+// Roughly 96.4kHz bit rate => one byte every ~149 CPU cycles
+const BYTE_TRANSFER_CYCLES: u32 = 149;
+// Time for the head to go back to the start of the disk.
+const HEAD_RETURN_CYCLES: u32 = 50000;
+
+pub struct Fds {
+    sides: Vec<Vec<u8>>,
+    side: Option<usize>,
+
+    irq_reload: u16,
+    irq_counter: u16,
+    irq_repeat: bool,
+    irq_enabled: bool,
+    timer_irq: bool,
+
+    disk_enabled: bool,
+    sound_enabled: bool,
+
+    motor_on: bool,
+    reset_transfer: bool,
+    read_mode: bool,
+    crc_control: bool,
+    previous_crc_control: bool,
+    disk_ready: bool,
+    disk_irq_enabled: bool,
+    disk_irq: bool,
+    mirroring: Mirroring,
+
+    read_data: u8,
+    write_data: u8,
+    ext_write: u8,
+    transfer_complete: bool,
+    gap_ended: bool,
+    end_of_head: bool,
+    scanning: bool,
+    position: usize,
+    delay: u32,
+    crc: u16,
+
+    audio: FdsAudio,
+}
+
+impl Fds {
+    pub fn new(image: &[u8]) -> Self {
+        Fds {
+            sides: load_sides(image),
+            side: Some(0),
+
+            irq_reload: 0,
+            irq_counter: 0,
+            irq_repeat: false,
+            irq_enabled: false,
+            timer_irq: false,
+
+            disk_enabled: true,
+            sound_enabled: true,
+
+            motor_on: false,
+            reset_transfer: false,
+            read_mode: true,
+            crc_control: false,
+            previous_crc_control: false,
+            disk_ready: false,
+            disk_irq_enabled: false,
+            disk_irq: false,
+            mirroring: Mirroring::Horizontal,
+
+            read_data: 0,
+            write_data: 0,
+            ext_write: 0,
+            transfer_complete: false,
+            gap_ended: false,
+            end_of_head: true,
+            scanning: false,
+            position: 0,
+            delay: 0,
+            crc: 0,
+
+            audio: FdsAudio::new(),
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if self.irq_enabled {
+            if self.irq_counter == 0 {
+                self.timer_irq = true;
+                self.irq_counter = self.irq_reload;
+                if !self.irq_repeat {
+                    self.irq_enabled = false;
+                }
+            } else {
+                self.irq_counter -= 1;
+            }
+        }
+    }
+
+    fn clock_drive(&mut self) {
+        let side = match self.side {
+            Some(side) if self.motor_on => side,
+            _ => {
+                self.end_of_head = true;
+                self.scanning = false;
+                return;
+            }
+        };
+        if self.reset_transfer && !self.scanning {
+            return;
+        }
+        if self.end_of_head {
+            self.delay = HEAD_RETURN_CYCLES;
+            self.end_of_head = false;
+            self.position = 0;
+            self.gap_ended = false;
+            return;
+        }
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.scanning = true;
+        let mut needs_irq = self.disk_irq_enabled;
+        if self.read_mode {
+            let data = self.sides[side][self.position];
+            if !self.previous_crc_control {
+                self.update_crc(data);
+            }
+            if !self.disk_ready {
+                self.gap_ended = false;
+                self.crc = 0;
+            } else if data != 0 && !self.gap_ended {
+                // The 0x80 start mark ends the gap, it isn't handed to the CPU.
+                self.gap_ended = true;
+                needs_irq = false;
+            }
+            if self.gap_ended {
+                self.transfer_complete = true;
+                self.read_data = data;
+                if needs_irq {
+                    self.disk_irq = true;
+                }
+            }
+        } else {
+            let mut data = 0;
+            if !self.crc_control {
+                self.transfer_complete = true;
+                data = self.write_data;
+                if needs_irq {
+                    self.disk_irq = true;
+                }
+            }
+            if !self.disk_ready {
+                data = 0;
+            }
+            if !self.crc_control {
+                self.update_crc(data);
+            } else {
+                if !self.previous_crc_control {
+                    self.update_crc(0);
+                    self.update_crc(0);
+                }
+                data = self.crc as u8;
+                self.crc >>= 8;
+            }
+            self.sides[side][self.position] = data;
+            self.gap_ended = false;
+        }
+        self.previous_crc_control = self.crc_control;
+
+        self.position += 1;
+        if self.position >= self.sides[side].len() {
+            self.motor_on = false;
+        } else {
+            self.delay = BYTE_TRANSFER_CYCLES;
+        }
+    }
+
+    fn update_crc(&mut self, data: u8) {
+        self.crc = crc_step(self.crc, data);
+    }
+}
+
+impl Mapper for Fds {
+    fn map_prg_read(&self, address: u16) -> MappedAddress {
+        match address {
+            0x4020..=0x5FFF => MappedAddress::Register,
+            0x6000..=0xDFFF => MappedAddress::Ram((address - 0x6000) as usize),
+            0xE000..=0xFFFF => MappedAddress::Rom((address - 0xE000) as usize),
+            _ => MappedAddress::Unmapped,
+        }
+    }
+
+    fn map_prg_write(&mut self, address: u16) -> MappedAddress {
+        match address {
+            0x4020..=0x5FFF => MappedAddress::Register,
+            0x6000..=0xDFFF => MappedAddress::Ram((address - 0x6000) as usize),
+            _ => MappedAddress::Unmapped,
+        }
+    }
+
+    fn map_chr_read(&self, address: u16) -> MappedAddress {
+        MappedAddress::Ram((address & 0x1FFF) as usize)
+    }
+
+    fn map_chr_write(&mut self, address: u16) -> MappedAddress {
+        MappedAddress::Ram((address & 0x1FFF) as usize)
+    }
+
+    fn irq_flag(&self) -> bool {
+        self.timer_irq || self.disk_irq
+    }
+
+    fn can_cpu_read(&self, address: u16) -> bool {
+        address >= 0x4020
+    }
+
+    fn can_ppu_read(&self, address: u16) -> bool {
+        address <= 0x1FFF
+    }
+
+    fn read_register(&mut self, address: u16) -> u8 {
+        if self.sound_enabled && (0x4040..=0x4097).contains(&address) {
+            return self.audio.read(address);
+        }
+        if !self.disk_enabled {
+            return 0;
+        }
+        match address {
+            0x4030 => {
+                let mut value = 0;
+                if self.timer_irq { value |= 0x01; }
+                if self.transfer_complete { value |= 0x02; }
+                if self.end_of_head { value |= 0x40; }
+                self.transfer_complete = false;
+                self.timer_irq = false;
+                self.disk_irq = false;
+                value
+            }
+            0x4031 => {
+                self.transfer_complete = false;
+                self.disk_irq = false;
+                self.read_data
+            }
+            0x4032 => {
+                let mut value = 0x40;
+                if self.side.is_none() { value |= 0x01 | 0x04; }
+                if self.side.is_none() || !self.scanning { value |= 0x02; }
+                value
+            }
+            // Bit 7 is the battery status, always good.
+            0x4033 => (self.ext_write & 0x7F) | 0x80,
+            _ => 0,
+        }
+    }
+
+    fn write_register(&mut self, address: u16, data: u8) {
+        if !self.disk_enabled && (0x4024..=0x4026).contains(&address) {
+            return;
+        }
+        if (0x4040..=0x4097).contains(&address) {
+            if self.sound_enabled {
+                self.audio.write(address, data);
+            }
+            return;
+        }
+        match address {
+            0x4020 => self.irq_reload = (self.irq_reload & 0xFF00) | data as u16,
+            0x4021 => self.irq_reload = (self.irq_reload & 0x00FF) | ((data as u16) << 8),
+            0x4022 => {
+                self.irq_repeat = data & 0x01 != 0;
+                self.irq_enabled = data & 0x02 != 0 && self.disk_enabled;
+                if self.irq_enabled {
+                    self.irq_counter = self.irq_reload;
+                } else {
+                    self.timer_irq = false;
+                }
+            }
+            0x4023 => {
+                self.disk_enabled = data & 0x01 != 0;
+                self.sound_enabled = data & 0x02 != 0;
+                if !self.disk_enabled {
+                    self.irq_enabled = false;
+                    self.timer_irq = false;
+                    self.disk_irq = false;
+                }
+            }
+            0x4024 => {
+                self.write_data = data;
+                self.transfer_complete = false;
+                self.disk_irq = false;
+            }
+            0x4025 => {
+                self.motor_on = data & 0x01 != 0;
+                self.reset_transfer = data & 0x02 != 0;
+                self.read_mode = data & 0x04 != 0;
+                self.mirroring = if data & 0x08 != 0 { Mirroring::Horizontal } else { Mirroring::Vertical };
+                self.crc_control = data & 0x10 != 0;
+                self.disk_ready = data & 0x40 != 0;
+                self.disk_irq_enabled = data & 0x80 != 0;
+                self.disk_irq = false;
+            }
+            0x4026 => self.ext_write = data,
+            _ => {}
+        }
+    }
+
+    fn clock(&mut self) {
+        self.clock_timer();
+        self.audio.clock();
+        self.clock_drive();
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn disk_side_count(&self) -> usize {
+        self.sides.len()
+    }
+
+    fn disk_side(&self) -> Option<usize> {
+        self.side
+    }
+
+    fn insert_disk_side(&mut self, side: Option<usize>) {
+        if let Some(n) = side {
+            if n >= self.sides.len() {
+                panic!("Error: Disk has {} sides, cannot insert side {}", self.sides.len(), n);
+            }
+        }
+        self.side = side;
+        self.motor_on = false;
+        self.scanning = false;
+        self.end_of_head = true;
+    }
+}
+
+pub fn check_bios(bios: &[u8]) {
+    if bios.len() != BIOS_SIZE {
+        panic!("Error: FDS BIOS must be 8KB, got {} bytes", bios.len());
+    }
+}
+
+// Splits a .fds (with or without the 16 byte fwNES header) or QD image into raw disk sides.
+fn load_sides(image: &[u8]) -> Vec<Vec<u8>> {
+    let (body, side_size, has_crc) = if image.starts_with(FDS_MAGIC) {
+        if image.len() < FDS_HEADER_SIZE {
+            panic!("Error: fwNES header is too short, got {} bytes", image.len());
+        }
+        (&image[FDS_HEADER_SIZE..], FDS_SIDE_SIZE, false)
+    } else if !image.is_empty() && image.len().is_multiple_of(QD_SIDE_SIZE) {
+        (image, QD_SIDE_SIZE, true)
+    } else {
+        (image, FDS_SIDE_SIZE, false)
+    };
+
+    let sides: Vec<Vec<u8>> = body.chunks(side_size)
+        .filter(|side| side.len() == side_size)
+        .map(|side| expand_side(side, has_crc))
+        .collect();
+    if sides.is_empty() {
+        panic!("Error: Disk image has no complete sides");
+    }
+    sides
+}
+
+fn expand_side(side: &[u8], has_crc: bool) -> Vec<u8> {
+    let mut raw = vec![0u8; LEAD_IN_GAP];
+    let mut i = 0;
+    let mut file_size = 0usize;
+    while i < side.len() {
+        let length = match side[i] {
+            1 => 56,         // Disk info
+            2 => 2,          // File amount
+            3 => 16,         // File header
+            4 => 1 + file_size, // File data
+            _ => break,
+        };
+        if i + length > side.len() {
+            break;
+        }
+        let block = &side[i..i + length];
+        if block[0] == 3 {
+            file_size = block[13] as usize | (block[14] as usize) << 8;
+        }
+
+        raw.push(0x80);
+        raw.extend_from_slice(block);
+        let crc = block_crc(block);
+        raw.push(crc as u8);
+        raw.push((crc >> 8) as u8);
+        raw.extend(std::iter::repeat_n(0, BLOCK_GAP));
+
+        i += length;
+        if has_crc {
+            i += 2;
+        }
+    }
+    // Pad out to a full side so games can write new files after the last one.
+    if raw.len() < FDS_SIDE_SIZE + LEAD_IN_GAP {
+        raw.resize(FDS_SIDE_SIZE + LEAD_IN_GAP, 0);
+    }
+    raw
+}
+
+// CRC-16/KERMIT over the start mark and block, as the RAM adapter computes it.
+// Starting at 0x8000 is the same as having fed the 0x80 start mark.
+fn block_crc(block: &[u8]) -> u16 {
+    block.iter().chain([0u8, 0u8].iter()).fold(0x8000, |crc, &byte| crc_step(crc, byte))
+}
+
+fn crc_step(mut crc: u16, data: u8) -> u16 {
+    for bit in 0..8 {
+        let carry = crc & 1;
+        crc >>= 1;
+        if carry != 0 {
+            crc ^= 0x8408;
+        }
+        if data & (1 << bit) != 0 {
+            crc ^= 0x8000;
+        }
+    }
+    crc
+}
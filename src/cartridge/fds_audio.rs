@@ -0,0 +1,273 @@
+// FDS expansion sound: one 64 step wavetable channel with a volume envelope,
+// frequency modulated by a second 64 step table.
+// ========
+// 0x4040 -> 0x407F => Wavetable RAM (6 bit samples, writable while 0x4089.7 is set)
+// 0x4080           => Volume envelope (7: direct gain, 6: increase, 0-5: speed/gain)
+// 0x4082 / 0x4083  => Wave frequency low / high (7: halt wave, 6: halt envelopes)
+// 0x4084           => Mod envelope, same layout as 0x4080
+// 0x4085           => Mod counter (7 bit signed)
+// 0x4086 / 0x4087  => Mod frequency low / high (7: halt mod, table writable)
+// 0x4088           => Mod table write (3 bits, fills 2 entries)
+// 0x4089           => 7: wavetable write enable, 0-1: master volume
+// 0x408A           => Envelope speed multiplier
+// 0x4090 / 0x4092  => Read back volume / mod gain
+
+const MOD_ADJUST: [i32; 8] = [0, 1, 2, 4, 0, -4, -2, -1];
+const MOD_RESET: u8 = 4;
+// Master volume 2/2, 2/3, 2/4, 2/5 scaled so the peak output is 63.
+const MASTER_VOLUME: [u32; 4] = [36, 24, 17, 14];
+
+#[derive(Default)]
+struct Envelope {
+    speed: u8,
+    increase: bool,
+    off: bool,
+    gain: u8,
+    timer: u32,
+}
+
+impl Envelope {
+    fn write(&mut self, data: u8, master_speed: u8) {
+        self.speed = data & 0x3F;
+        self.increase = data & 0x40 != 0;
+        self.off = data & 0x80 != 0;
+        self.reset_timer(master_speed);
+        if self.off {
+            self.gain = self.speed;
+        }
+    }
+
+    fn reset_timer(&mut self, master_speed: u8) {
+        self.timer = 8 * (self.speed as u32 + 1) * master_speed as u32;
+    }
+
+    // Returns true when the gain changed.
+    fn tick(&mut self, master_speed: u8) -> bool {
+        if self.off || master_speed == 0 {
+            return false;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.reset_timer(master_speed);
+            if self.increase && self.gain < 32 {
+                self.gain += 1;
+            } else if !self.increase && self.gain > 0 {
+                self.gain -= 1;
+            }
+            return true;
+        }
+        false
+    }
+}
+
+pub struct FdsAudio {
+    wave_table: [u8; 64],
+    wave_write: bool,
+    wave_halt: bool,
+    wave_frequency: u16,
+    wave_accumulator: u16,
+    wave_position: usize,
+    envelopes_halt: bool,
+    master_volume: usize,
+    master_speed: u8,
+    volume: Envelope,
+
+    mod_envelope: Envelope,
+    mod_table: [u8; 64],
+    mod_position: usize,
+    mod_halt: bool,
+    mod_frequency: u16,
+    mod_accumulator: u16,
+    mod_counter: i32,
+    mod_output: i32,
+
+    output: u8,
+}
+
+impl FdsAudio {
+    pub fn new() -> Self {
+        FdsAudio {
+            wave_table: [0; 64],
+            wave_write: false,
+            wave_halt: true,
+            wave_frequency: 0,
+            wave_accumulator: 0,
+            wave_position: 0,
+            envelopes_halt: false,
+            master_volume: 0,
+            master_speed: 0xE8,
+            volume: Envelope::default(),
+
+            mod_envelope: Envelope::default(),
+            mod_table: [0; 64],
+            mod_position: 0,
+            mod_halt: true,
+            mod_frequency: 0,
+            mod_accumulator: 0,
+            mod_counter: 0,
+            mod_output: 0,
+
+            output: 0,
+        }
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            0x4040..=0x407F => self.wave_table[(address & 0x3F) as usize] | 0x40,
+            0x4090 => self.volume.gain | 0x40,
+            0x4092 => self.mod_envelope.gain | 0x40,
+            _ => 0,
+        }
+    }
+
+    pub fn write(&mut self, address: u16, data: u8) {
+        match address {
+            0x4040..=0x407F if self.wave_write => {
+                self.wave_table[(address & 0x3F) as usize] = data & 0x3F;
+            }
+            0x4080 => self.volume.write(data, self.master_speed),
+            0x4082 => {
+                self.wave_frequency = (self.wave_frequency & 0x0F00) | data as u16;
+                self.update_mod_output();
+            }
+            0x4083 => {
+                self.wave_frequency = (self.wave_frequency & 0x00FF) | (((data & 0x0F) as u16) << 8);
+                self.wave_halt = data & 0x80 != 0;
+                self.envelopes_halt = data & 0x40 != 0;
+                if self.wave_halt {
+                    self.wave_position = 0;
+                    self.wave_accumulator = 0;
+                }
+                if self.envelopes_halt {
+                    self.volume.reset_timer(self.master_speed);
+                    self.mod_envelope.reset_timer(self.master_speed);
+                }
+                self.update_mod_output();
+            }
+            0x4084 => {
+                self.mod_envelope.write(data, self.master_speed);
+                self.update_mod_output();
+            }
+            0x4085 => {
+                self.set_mod_counter((data & 0x7F) as i32);
+                self.update_mod_output();
+            }
+            0x4086 => self.mod_frequency = (self.mod_frequency & 0x0F00) | data as u16,
+            0x4087 => {
+                self.mod_frequency = (self.mod_frequency & 0x00FF) | (((data & 0x0F) as u16) << 8);
+                self.mod_halt = data & 0x80 != 0;
+                if self.mod_halt {
+                    self.mod_accumulator = 0;
+                }
+            }
+            // Table is only writable while the modulator is halted, each write fills 2 entries.
+            0x4088 if self.mod_halt => {
+                self.mod_table[self.mod_position] = data & 0x07;
+                self.mod_table[(self.mod_position + 1) & 0x3F] = data & 0x07;
+                self.mod_position = (self.mod_position + 2) & 0x3F;
+            }
+            0x4089 => {
+                self.master_volume = (data & 0x03) as usize;
+                self.wave_write = data & 0x80 != 0;
+            }
+            0x408A => {
+                self.master_speed = data;
+                self.volume.reset_timer(self.master_speed);
+                self.mod_envelope.reset_timer(self.master_speed);
+            }
+            _ => {}
+        }
+    }
+
+    // Called once per CPU cycle.
+    pub fn clock(&mut self) {
+        if !self.wave_halt && !self.envelopes_halt {
+            self.volume.tick(self.master_speed);
+            if self.mod_envelope.tick(self.master_speed) {
+                self.update_mod_output();
+            }
+        }
+
+        if !self.mod_halt && self.mod_frequency > 0 {
+            let (accumulator, overflow) = self.mod_accumulator.overflowing_add(self.mod_frequency);
+            self.mod_accumulator = accumulator;
+            if overflow {
+                let step = self.mod_table[self.mod_position];
+                if step == MOD_RESET {
+                    self.set_mod_counter(0);
+                } else {
+                    self.set_mod_counter(self.mod_counter + MOD_ADJUST[step as usize]);
+                }
+                self.mod_position = (self.mod_position + 1) & 0x3F;
+                self.update_mod_output();
+            }
+        }
+
+        if self.wave_halt {
+            self.wave_position = 0;
+        } else {
+            let pitch = self.wave_frequency as i32 + self.mod_output;
+            if pitch > 0 && !self.wave_write {
+                let (accumulator, overflow) = self.wave_accumulator.overflowing_add(pitch as u16);
+                self.wave_accumulator = accumulator;
+                if overflow {
+                    self.wave_position = (self.wave_position + 1) & 0x3F;
+                }
+            }
+        }
+        self.update_output();
+    }
+
+    // Output level in 0.0..=1.0
+    pub fn output(&self) -> f32 {
+        self.output as f32 / 63.0
+    }
+
+    fn update_output(&mut self) {
+        // The output is held while the wavetable is being written.
+        if self.wave_write {
+            return;
+        }
+        let gain = self.volume.gain.min(32) as u32;
+        let level = gain * MASTER_VOLUME[self.master_volume];
+        self.output = ((self.wave_table[self.wave_position] as u32 * level) / 1152) as u8;
+    }
+
+    fn set_mod_counter(&mut self, value: i32) {
+        // 7 bit signed, wraps within -64..=63
+        let mut value = value & 0x7F;
+        if value >= 64 {
+            value -= 128;
+        }
+        self.mod_counter = value;
+    }
+
+    fn update_mod_output(&mut self) {
+        // From the nesdev wiki:
+        // 1. multiply counter by gain, lose lowest 4 bits of result but "round" in a strange way
+        let mut temp = self.mod_counter * self.mod_envelope.gain as i32;
+        let remainder = temp & 0x0F;
+        temp >>= 4;
+        if remainder > 0 && (temp & 0x80) == 0 {
+            if self.mod_counter < 0 { temp -= 1 } else { temp += 2 };
+        }
+
+        // 2. wrap if a certain range is exceeded
+        if temp >= 192 {
+            temp -= 256;
+        } else if temp < -64 {
+            temp += 256;
+        }
+
+        // 3. multiply result by pitch, then round to nearest while dropping 6 bits
+        temp *= self.wave_frequency as i32;
+        let remainder = temp & 0x3F;
+        temp >>= 6;
+        if remainder >= 32 {
+            temp += 1;
+        }
+        self.mod_output = temp;
+    }
+}
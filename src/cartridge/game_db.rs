@@ -0,0 +1,162 @@
+use super::Mirroring;
+use super::CartridgeHeader;
+use super::hash::{Crc32, Sha1};
+use std::fs;
+
+const BUILTIN: &str = include_str!("gamedb.txt");
+const PRG_RAM_PAGE_KB: usize = 8;
+
+pub struct GameDbEntry {
+    pub crc32: u32,
+    pub sha1: Option<[u8; 20]>,
+    pub mapper: Option<u8>,
+    pub mirroring: Option<Mirroring>,
+    pub battery: Option<bool>,
+    pub prg_ram_pages: Option<usize>,
+    pub name: String,
+}
+
+// What the database changed in a cartridge's header.
+#[derive(Clone)]
+pub struct HeaderOverride {
+    pub name: String,
+    pub crc32: u32,
+    pub changes: Vec<String>,
+}
+
+pub struct GameDb {
+    entries: Vec<GameDbEntry>,
+}
+
+impl GameDb {
+    pub fn builtin() -> Self {
+        GameDb::parse(BUILTIN)
+    }
+
+    pub fn load(name: &str) -> Self {
+        let text = fs::read_to_string(name).expect("Error: Cannot read game database");
+        GameDb::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let entries = text.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+            .map(|(i, line)| parse_entry(line).unwrap_or_else(|e| panic!("Error: Game database line {}: {}", i + 1, e)))
+            .collect();
+        GameDb { entries }
+    }
+
+    pub fn extend(&mut self, other: GameDb) {
+        self.entries.extend(other.entries);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn lookup(&self, prg_rom: &[u8], chr_rom: &[u8]) -> Option<&GameDbEntry> {
+        let mut crc = Crc32::new();
+        crc.update(prg_rom);
+        crc.update(chr_rom);
+        let crc = crc.finish();
+
+        let mut candidates = self.entries.iter().filter(|e| e.crc32 == crc).peekable();
+        candidates.peek()?;
+        // Only hash with SHA-1 when some entry asks for it.
+        let mut sha1: Option<[u8; 20]> = None;
+        candidates.find(|entry| match entry.sha1 {
+            None => true,
+            Some(expected) => {
+                let digest = *sha1.get_or_insert_with(|| {
+                    let mut hasher = Sha1::new();
+                    hasher.update(prg_rom);
+                    hasher.update(chr_rom);
+                    hasher.finish()
+                });
+                digest == expected
+            }
+        })
+    }
+
+    // Applies the matching entry to header, returns what was changed.
+    pub fn apply(&self, header: &mut CartridgeHeader, prg_rom: &[u8], chr_rom: &[u8]) -> Option<HeaderOverride> {
+        let entry = self.lookup(prg_rom, chr_rom)?;
+        let mut changes = Vec::new();
+
+        if let Some(mapper) = entry.mapper.filter(|&m| m != header.mapper_number) {
+            changes.push(format!("mapper {} -> {}", header.mapper_number, mapper));
+            header.mapper_number = mapper;
+        }
+        if let Some(mirroring) = entry.mirroring.filter(|&m| m != header.mirroring) {
+            changes.push(format!("mirroring {:?} -> {:?}", header.mirroring, mirroring));
+            header.mirroring = mirroring;
+        }
+        if let Some(battery) = entry.battery.filter(|&b| b != header.battery) {
+            changes.push(format!("battery {} -> {}", header.battery, battery));
+            header.battery = battery;
+        }
+        if let Some(pages) = entry.prg_ram_pages.filter(|&p| p != header.prg_ram_pages) {
+            changes.push(format!("PRG-RAM {}KB -> {}KB", header.prg_ram_pages * PRG_RAM_PAGE_KB, pages * PRG_RAM_PAGE_KB));
+            header.prg_ram_pages = pages;
+        }
+
+        if changes.is_empty() {
+            None
+        } else {
+            Some(HeaderOverride { name: entry.name.clone(), crc32: entry.crc32, changes })
+        }
+    }
+}
+
+fn parse_entry(line: &str) -> Result<GameDbEntry, String> {
+    let fields: Vec<&str> = line.split(';').map(str::trim).collect();
+    if fields.len() != 7 {
+        return Err(format!("expected 7 fields, got {}", fields.len()));
+    }
+    let crc32 = u32::from_str_radix(fields[0], 16).map_err(|_| format!("bad CRC32 {}", fields[0]))?;
+    let sha1 = match fields[1] {
+        "" => None,
+        hex => Some(parse_sha1(hex)?),
+    };
+    let mapper = match fields[2] {
+        "" => None,
+        n => Some(n.parse().map_err(|_| format!("bad mapper {}", n))?),
+    };
+    let mirroring = match fields[3] {
+        "" => None,
+        "h" | "H" => Some(Mirroring::Horizontal),
+        "v" | "V" => Some(Mirroring::Vertical),
+        "4" => Some(Mirroring::FourScreen),
+        m => return Err(format!("bad mirroring {}", m)),
+    };
+    let battery = match fields[4] {
+        "" => None,
+        "0" => Some(false),
+        "1" => Some(true),
+        b => return Err(format!("bad battery flag {}", b)),
+    };
+    let prg_ram_pages = match fields[5] {
+        "" => None,
+        kb => {
+            let kb: usize = kb.parse().map_err(|_| format!("bad PRG-RAM size {}", kb))?;
+            Some(kb.div_ceil(PRG_RAM_PAGE_KB).max(1))
+        }
+    };
+    Ok(GameDbEntry { crc32, sha1, mapper, mirroring, battery, prg_ram_pages, name: fields[6].to_string() })
+}
+
+fn parse_sha1(hex: &str) -> Result<[u8; 20], String> {
+    if hex.len() != 40 {
+        return Err(format!("bad SHA-1 {}", hex));
+    }
+    let mut digest = [0u8; 20];
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| format!("bad SHA-1 {}", hex))?;
+    }
+    Ok(digest)
+}
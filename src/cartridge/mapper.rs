@@ -1,12 +1,58 @@
+use super::Mirroring;
+
+// Where an address from the CPU or PPU ends up on the cartridge.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum MappedAddress {
+    Rom(usize),
+    Ram(usize),
+    // Handled by the mapper itself through read_register/write_register.
+    Register,
+    Unmapped,
+}
+
 pub trait Mapper {
-    fn map_prg_read(&self, address: u16) -> u16;
-    fn map_prg_write(&mut self, address: u16) -> u16;
-    fn map_chr_read(&self, address: u16) -> u16;
-    fn map_chr_write(&mut self, address: u16) -> u16;
+    fn map_prg_read(&self, address: u16) -> MappedAddress;
+    fn map_prg_write(&mut self, address: u16) -> MappedAddress;
+    fn map_chr_read(&self, address: u16) -> MappedAddress;
+    fn map_chr_write(&mut self, address: u16) -> MappedAddress;
     fn irq_flag(&self) -> bool {
         false
     }
 
     fn can_cpu_read(&self, address:u16) -> bool;
     fn can_ppu_read(&self, address:u16) -> bool;
-}
\ No newline at end of file
+    fn can_cpu_write(&self, address:u16) -> bool {
+        self.can_cpu_read(address)
+    }
+
+    fn read_register(&mut self, _address: u16) -> u8 {
+        0
+    }
+    fn write_register(&mut self, _address: u16, _data: u8) {}
+    // Register value without the side effects of reading it, for debug output.
+    fn peek_register(&self, _address: u16) -> u8 {
+        0
+    }
+
+    // Called once per CPU cycle.
+    fn clock(&mut self) {}
+
+    // Mirroring set by the mapper at runtime, None means the header's one is used.
+    fn mirroring(&self) -> Option<Mirroring> {
+        None
+    }
+
+    // Expansion audio output in 0.0..=1.0.
+    fn audio_output(&self) -> f32 {
+        0.0
+    }
+
+    // Disk drive, only the FDS has one.
+    fn disk_side_count(&self) -> usize {
+        0
+    }
+    fn disk_side(&self) -> Option<usize> {
+        None
+    }
+    fn insert_disk_side(&mut self, _side: Option<usize>) {}
+}
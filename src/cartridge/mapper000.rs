@@ -1,4 +1,5 @@
 use super::Mapper;
+use super::MappedAddress;
 use super::CartridgeHeader;
 
 pub struct Mapper000 {
@@ -7,13 +8,13 @@ pub struct Mapper000 {
 impl Mapper000 {
     pub fn new(header: CartridgeHeader) -> Self {
        Mapper000 {
-        header: header
+        header
        }
     }
 }
 
 impl Mapper for Mapper000 {
-    fn map_prg_read(&self, address: u16) -> u16 {
+    fn map_prg_read(&self, address: u16) -> MappedAddress {
 	// if PRGROM is 16KB
 	//     CPU Address Bus                PRG ROM
 	//     0x8000 -> 0xBFFF: Mapped to    0x0000 -> 0x3FFF
@@ -21,32 +22,44 @@ impl Mapper for Mapper000 {
 	// if PRGROM is 32KB
 	//     CPU Address Bus                PRG ROM
     //     0x8000 -> 0xFFFF: Mapped to    0x0000 -> 0x7FFF
+    // 0x6000 -> 0x7FFF is PRG RAM (Family Basic)
     // Same with Write
     
-        let mapped_addr = address & ( if self.header.prg_rom_pages > 1 { 0x7FFF } else { 0x3FFF });
-        mapped_addr
+        if address < 0x8000 {
+            return MappedAddress::Ram((address & 0x1FFF) as usize);
+        }
+        MappedAddress::Rom((address & ( if self.header.prg_rom_pages > 1 { 0x7FFF } else { 0x3FFF })) as usize)
     }
 
-    fn map_prg_write(&mut self, address: u16) -> u16 {
-        let mapped_addr = address & ( if self.header.prg_rom_pages > 1 { 0x7FFF } else { 0x3FFF });
-        mapped_addr
+    fn map_prg_write(&mut self, address: u16) -> MappedAddress {
+        if address < 0x8000 {
+            MappedAddress::Ram((address & 0x1FFF) as usize)
+        } else {
+            // No registers, writes to ROM go nowhere.
+            MappedAddress::Unmapped
+        }
     }
-    fn map_chr_read(&self, address: u16) -> u16 {
+    fn map_chr_read(&self, address: u16) -> MappedAddress {
         // There is no mapping required for PPU
         // PPU Address Bus                CHR ROM
         // 0x0000 -> 0x1FFF: Mapped to    0x0000 -> 0x1FFF
         // Same with Write
         
         if address > 0x1FFF {panic!("Error: Attempted CHR read beyond 0x1FFF using Mapper000");}
-        address
+        if self.header.chr_rom_pages == 0 {
+            MappedAddress::Ram(address as usize)
+        } else {
+            MappedAddress::Rom(address as usize)
+        }
     }
-    fn map_chr_write(&mut self, address: u16) -> u16 {
+    fn map_chr_write(&mut self, address: u16) -> MappedAddress {
         if address > 0x1fff {
             panic!("Error: Attempted CHR write beyond 0x1FFF using Mapper000");
         } else if self.header.chr_rom_pages != 0 {
-            panic!("Error: CHR Page > 0 using Mapper000");
+            // CHR-ROM, games do write to it by mistake.
+            MappedAddress::Unmapped
         } else {
-            address
+            MappedAddress::Ram(address as usize)
         }
     }
     fn irq_flag(&self) -> bool {
@@ -62,4 +75,4 @@ impl Mapper for Mapper000 {
     fn can_ppu_read(&self, address: u16) -> bool {
         address <= 0x1FFF
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,82 @@
+use crate::apu::Pulse;
+
+// MMC5 expansion sound: two pulse channels like the APU's, without the sweep
+// unit, and 8 bit PCM.
+// ========
+// 0x5000 -> 0x5003 => Pulse 1 (duty/envelope, -, timer low, length/timer high)
+// 0x5004 -> 0x5007 => Pulse 2
+// 0x5010           => 0: PCM read mode (from 0x8000-0xBFFF, not emulated)
+// 0x5011           => PCM level, writes of 0 are ignored
+// 0x5015           => Channel enable (write) / status (read)
+
+// Envelopes and length counters are clocked at 240Hz, without the APU's frame counter.
+const FRAME_CYCLES: u32 = 7457;
+
+pub struct Mmc5Audio {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    pcm_read_mode: bool,
+    pcm: u8,
+    frame_cycle: u32,
+    odd_cycle: bool,
+}
+
+impl Mmc5Audio {
+    pub fn new() -> Self {
+        Mmc5Audio {
+            pulse1: Pulse::without_sweep(),
+            pulse2: Pulse::without_sweep(),
+            pcm_read_mode: false,
+            pcm: 0,
+            frame_cycle: 0,
+            odd_cycle: false,
+        }
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            0x5015 => (self.pulse1.length > 0) as u8 | ((self.pulse2.length > 0) as u8) << 1,
+            _ => 0,
+        }
+    }
+
+    pub fn write(&mut self, address: u16, data: u8) {
+        match address {
+            0x5000..=0x5003 => self.pulse1.write(address & 3, data),
+            0x5004..=0x5007 => self.pulse2.write(address & 3, data),
+            0x5010 => self.pcm_read_mode = data & 0x01 != 0,
+            0x5011 if !self.pcm_read_mode && data != 0 => self.pcm = data,
+            0x5015 => {
+                self.pulse1.enabled = data & 0x01 != 0;
+                self.pulse2.enabled = data & 0x02 != 0;
+                if !self.pulse1.enabled { self.pulse1.length = 0; }
+                if !self.pulse2.enabled { self.pulse2.length = 0; }
+            }
+            _ => {}
+        }
+    }
+
+    // Called once per CPU cycle.
+    pub fn clock(&mut self) {
+        if self.odd_cycle {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+        self.odd_cycle = !self.odd_cycle;
+
+        self.frame_cycle += 1;
+        if self.frame_cycle == FRAME_CYCLES {
+            self.frame_cycle = 0;
+            for pulse in [&mut self.pulse1, &mut self.pulse2] {
+                pulse.envelope.clock();
+                pulse.clock_length();
+            }
+        }
+    }
+
+    // Output level in 0.0..=1.0, the pulses and PCM weighted the same.
+    pub fn output(&self) -> f32 {
+        let pulse = (self.pulse1.output() + self.pulse2.output()) as f32 / 30.0;
+        (pulse + self.pcm as f32 / 255.0) / 2.0
+    }
+}
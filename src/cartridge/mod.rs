@@ -2,49 +2,117 @@ mod mapper;
 mod mapper000;
 mod cartridge_header;
 mod cartridge_data;
-use mapper::Mapper;
+mod unif;
+mod fds;
+mod fds_audio;
+mod mmc5_audio;
+mod n163_audio;
+mod vrc6_audio;
+pub mod nsf;
+mod hash;
+pub mod game_db;
+pub mod patch;
+use mapper::{Mapper, MappedAddress};
+use fds::Fds;
+use nsf::{Nsf, NsfMapper};
+use game_db::{GameDb, HeaderOverride};
 use mapper000::Mapper000;
 use cartridge_header::CartridgeHeader;
 use cartridge_data::CartridgeData;
+use std::convert::TryFrom;
 
-#[derive(Copy, Clone)]
+const INES_HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+const PRG_RAM_PAGE_SIZE: usize = 0x2000;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum Mirroring {
     Vertical,
     Horizontal,
+    OneScreenLower,
+    OneScreenUpper,
+    FourScreen,
 }
 
 pub struct Cartridge {
     pub header: CartridgeHeader,
     data: CartridgeData,
     mapper: Box<dyn Mapper>,
+    // Set when the game database corrected the ROM's header.
+    pub header_override: Option<HeaderOverride>,
 }
 
 impl Cartridge {
     pub fn new(data: &[u8]) -> Self {
-        let mapper = (data[6] >> 4) | (data[7] & 0xf0);
-        let prg_ram_pages = if data[8] == 0 { 1 } else { data[8] as usize };
+        Cartridge::with_game_db(data, &GameDb::builtin())
+    }
 
+    pub fn with_game_db(data: &[u8], game_db: &GameDb) -> Self {
+        Cartridge::parse(data, game_db).unwrap_or_else(|e| panic!("Error: {}", e))
+    }
 
-        let mirroring = if data[6] & 1 == 0 {
-            Mirroring::Horizontal
+    // Like with_game_db, but images that can't be loaded are an Err instead of a panic.
+    pub fn parse(data: &[u8], game_db: &GameDb) -> Result<Self, String> {
+        if data.starts_with(fds::FDS_MAGIC) {
+            return Err(String::from("Disk images need the FDS BIOS, use Cartridge::new_disk"));
+        }
+        let (mut header, mut cart_data) = if data.starts_with(unif::MAGIC) {
+            unif::parse(data)?
         } else {
-            Mirroring::Vertical
+            parse_ines(data)?
         };
 
+        // NES 2.0 headers are trusted, the database is there for iNES ones.
+        let header_override = if is_nes2(data) {
+            None
+        } else {
+            game_db.apply(&mut header, &cart_data.prg_rom, &cart_data.chr_rom)
+        };
+        if header_override.is_some() {
+            cart_data.prg_ram = vec![0u8; header.prg_ram_bytes()];
+        }
 
-        let header = CartridgeHeader::new(mapper, mirroring,data[4] as usize, prg_ram_pages, data[5] as usize);
-        let cart_data = CartridgeData::new(data[header.prg_rom_range()].to_vec(), vec![0u8; header.prg_ram_bytes()] ,data[header.chr_rom_range()].to_vec(), vec![0u8; header.chr_ram_bytes()]);
-        
         //Check for the type of mapper and copy header in the specific mapper's constructor.
         let mapper: Box<dyn Mapper> = match header.mapper_number {
             0 => Box::new(Mapper000::new(header)),
-            n => panic!("Mapper {} not implemented", n),
+            n => return Err(format!("Mapper {} isn't supported", n)),
         };
         
+        Ok(Cartridge {
+            header,
+            data: cart_data,
+            mapper,
+            header_override,
+        })
+    }
+
+    // Famicom Disk System: the RAM adapter runs the BIOS, games come from the disk image.
+    pub fn new_disk(image: &[u8], bios: &[u8]) -> Self {
+        fds::check_bios(bios);
+        let header = CartridgeHeader::new(20, Mirroring::Horizontal, true, 1, 4, 0);
+        let cart_data = CartridgeData::new(bios.to_vec(), vec![0u8; header.prg_ram_bytes()], Vec::new(), vec![0u8; header.chr_ram_bytes()]);
+
+        Cartridge {
+            header,
+            data: cart_data,
+            mapper: Box::new(Fds::new(image)),
+            header_override: None,
+        }
+    }
+
+    // NSF player cartridge. FDS tunes run from RAM the mapper keeps and fills with their banks.
+    pub fn new_nsf(nsf: &Nsf) -> Self {
+        let prg = nsf.prg_image();
+        let header = CartridgeHeader::new(0, Mirroring::Horizontal, false, prg.len().div_ceil(0x4000), 1, 0);
+        let prg_ram = vec![0u8; header.prg_ram_bytes()];
+        let mapper = Box::new(NsfMapper::new(nsf));
+        let cart_data = CartridgeData::new(prg, prg_ram, Vec::new(), Vec::new());
+
         Cartridge {
-            header: header,
+            header,
             data: cart_data,
-            mapper: mapper,
+            mapper,
+            header_override: None,
         }
     }
 
@@ -52,17 +120,161 @@ impl Cartridge {
         self.mapper.can_cpu_read(addr)
     }
 
+    pub fn can_cpu_write(&self, addr: u16) -> bool {
+        self.mapper.can_cpu_write(addr)
+    }
+
     pub fn can_ppu_read(&self, addr: u16) -> bool {
         self.mapper.can_ppu_read(addr)
     }
 
-    pub fn cpu_read(&self, addr: u16) -> u8 {
-        let mapped_addr = self.mapper.map_prg_read(addr);
-        self.data.prg_rom[mapped_addr as usize]
+    pub fn cpu_read(&mut self, addr: u16) -> u8 {
+        match self.mapper.map_prg_read(addr) {
+            MappedAddress::Rom(i) => self.data.prg_rom[i],
+            MappedAddress::Ram(i) => self.data.prg_ram[i],
+            MappedAddress::Register => self.mapper.read_register(addr),
+            MappedAddress::Unmapped => 0,
+        }
+    }
+
+    pub fn cpu_peek(&self, addr: u16) -> u8 {
+        match self.mapper.map_prg_read(addr) {
+            MappedAddress::Rom(i) => self.data.prg_rom[i],
+            MappedAddress::Ram(i) => self.data.prg_ram[i],
+            MappedAddress::Register => self.mapper.peek_register(addr),
+            MappedAddress::Unmapped => 0,
+        }
+    }
+
+    pub fn prg_rom_size(&self) -> usize {
+        self.data.prg_rom.len()
+    }
+
+    pub fn chr_rom_size(&self) -> usize {
+        self.data.chr_rom.len()
+    }
+
+    pub fn chr_rom_offset(&self, addr: u16) -> Option<usize> {
+        match self.mapper.map_chr_read(addr) {
+            MappedAddress::Rom(i) if self.can_ppu_read(addr) => Some(i),
+            _ => None,
+        }
+    }
+
+    pub fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        match self.mapper.map_prg_read(addr) {
+            MappedAddress::Rom(i) if self.can_cpu_read(addr) => Some(i),
+            _ => None,
+        }
+    }
+
+    pub fn cpu_write(&mut self, addr: u16, data: u8) {
+        match self.mapper.map_prg_write(addr) {
+            MappedAddress::Ram(i) => self.data.prg_ram[i] = data,
+            MappedAddress::Register => self.mapper.write_register(addr, data),
+            MappedAddress::Rom(_) | MappedAddress::Unmapped => {}
+        }
     }
 
     pub fn ppu_read(&self, addr: u16) -> u8 {
-        let mapped_addr = self.mapper.map_chr_read(addr);
-        self.data.chr_rom[mapped_addr as usize]
+        match self.mapper.map_chr_read(addr) {
+            MappedAddress::Rom(i) => self.data.chr_rom[i],
+            MappedAddress::Ram(i) => self.data.chr_ram[i],
+            MappedAddress::Register | MappedAddress::Unmapped => 0,
+        }
+    }
+
+    pub fn ppu_write(&mut self, addr: u16, data: u8) {
+        if let MappedAddress::Ram(i) = self.mapper.map_chr_write(addr) {
+            self.data.chr_ram[i] = data;
+        }
+    }
+
+    pub fn clock(&mut self) {
+        self.mapper.clock();
+    }
+
+    pub fn irq(&self) -> bool {
+        self.mapper.irq_flag()
+    }
+
+    pub fn mirroring(&self) -> Mirroring {
+        self.mapper.mirroring().unwrap_or(self.header.mirroring)
+    }
+
+    pub fn audio_output(&self) -> f32 {
+        self.mapper.audio_output()
+    }
+
+    pub fn disk_side_count(&self) -> usize {
+        self.mapper.disk_side_count()
     }
-}
\ No newline at end of file
+
+    pub fn disk_side(&self) -> Option<usize> {
+        self.mapper.disk_side()
+    }
+
+    // Swap disks: Some(n) inserts side n, None ejects.
+    pub fn insert_disk_side(&mut self, side: Option<usize>) {
+        self.mapper.insert_disk_side(side);
+    }
+}
+
+fn parse_ines(data: &[u8]) -> Result<(CartridgeHeader, CartridgeData), String> {
+    if data.len() < INES_HEADER_SIZE {
+        return Err(String::from("iNES header is too short"));
+    }
+    let (mapper, prg_rom_pages, prg_ram_pages, chr_rom_pages) = if is_nes2(data) {
+        parse_nes2(data)?
+    } else {
+        // Old dumping tools wrote their name ("DiskDude!") over bytes 7-15, when
+        // 12-15 aren't zero byte 7 can't be trusted for the mapper's high nibble.
+        let dirty = data[12..16].iter().any(|&b| b != 0);
+        let mapper_high = if dirty { 0 } else { data[7] & 0xf0 };
+        let prg_ram_pages = if data[8] == 0 || dirty { 1 } else { data[8] as usize };
+        (((data[6] >> 4) | mapper_high) as u16, data[4] as usize, prg_ram_pages, data[5] as usize)
+    };
+    let mapper = u8::try_from(mapper).map_err(|_| format!("Mapper {} isn't supported", mapper))?;
+    let battery = data[6] & 0b10 != 0;
+
+    let mirroring = if data[6] & 0b1000 != 0 {
+        Mirroring::FourScreen
+    } else if data[6] & 1 == 0 {
+        Mirroring::Horizontal
+    } else {
+        Mirroring::Vertical
+    };
+
+
+    let header = CartridgeHeader::new(mapper, mirroring, battery, prg_rom_pages, prg_ram_pages, chr_rom_pages);
+    // A trainer between the header and PRG ROM is skipped, nothing loads it at $7000.
+    let trainer = if data[6] & 0b100 != 0 { TRAINER_SIZE } else { 0 };
+    let (prg_rom, chr_rom) = (header.prg_rom_range(), header.chr_rom_range());
+    if data.len() < chr_rom.end + trainer {
+        return Err(String::from("ROM is shorter than its header says"));
+    }
+    let prg_rom = data[prg_rom.start + trainer..prg_rom.end + trainer].to_vec();
+    let chr_rom = data[chr_rom.start + trainer..chr_rom.end + trainer].to_vec();
+    let cart_data = CartridgeData::new(prg_rom, vec![0u8; header.prg_ram_bytes()], chr_rom, vec![0u8; header.chr_ram_bytes()]);
+    Ok((header, cart_data))
+}
+
+fn is_nes2(data: &[u8]) -> bool {
+    data.starts_with(b"NES\x1a") && data.len() >= INES_HEADER_SIZE && data[7] & 0x0C == 0x08
+}
+
+// NES 2.0 keeps the mapper's top bits in byte 8, the ROM sizes' in byte 9 and
+// the PRG-RAM and battery backed PRG-RAM sizes as shift counts in byte 10.
+fn parse_nes2(data: &[u8]) -> Result<(u16, usize, usize, usize), String> {
+    let mapper = ((data[6] >> 4) | (data[7] & 0xf0)) as u16 | ((data[8] as u16 & 0x0f) << 8);
+    if data[9] & 0x0f == 0x0f || data[9] >> 4 == 0x0f {
+        return Err(String::from("NES 2.0 exponent ROM sizes aren't supported"));
+    }
+    let prg_rom_pages = data[4] as usize | (data[9] as usize & 0x0f) << 8;
+    let chr_rom_pages = data[5] as usize | (data[9] as usize >> 4) << 8;
+    let ram_bytes = |shift: u8| if shift == 0 { 0 } else { 64usize << shift };
+    let prg_ram_bytes = ram_bytes(data[10] & 0x0f) + ram_bytes(data[10] >> 4);
+    // Mappers map $6000-$7FFF whether or not there's RAM there.
+    let prg_ram_pages = prg_ram_bytes.div_ceil(PRG_RAM_PAGE_SIZE).max(1);
+    Ok((mapper, prg_rom_pages, prg_ram_pages, chr_rom_pages))
+}
@@ -0,0 +1,117 @@
+// Namco 163 expansion sound: up to 8 wavetable channels sharing 128 bytes of
+// RAM with their registers, updated one at a time every 15 CPU cycles.
+// ========
+// 0xF800 => RAM address (7: increment after each 0x4800 access, 0-6: address)
+// 0x4800 => RAM at that address
+// RAM at 0x40 + 8 * n for channel n, 7 always on, then 6, 5... down to 8 - count:
+//   +0 / +2 / +4 (0-1)  => Frequency low / mid / high
+//   +1 / +3 / +5        => Phase low / mid / high
+//   +4 (2-7)            => Wave length, 256 minus this in 4 bit samples
+//   +6                  => Wave address, in 4 bit samples (low nibble first)
+//   +7                  => Volume (0-3), and at 0x7F the channel count - 1 (4-6)
+
+const UPDATE_CYCLES: u32 = 15;
+// A 15 sample at volume 15.
+const MAX_OUTPUT: f32 = 225.0;
+
+pub struct N163Audio {
+    ram: [u8; 128],
+    address: u8,
+    increment: bool,
+    timer: u32,
+    // The next one to update.
+    channel: usize,
+    outputs: [u8; 8],
+}
+
+impl N163Audio {
+    pub fn new() -> Self {
+        N163Audio {
+            ram: [0; 128],
+            address: 0,
+            increment: false,
+            timer: 0,
+            channel: 7,
+            outputs: [0; 8],
+        }
+    }
+
+    pub fn read(&mut self, address: u16) -> u8 {
+        match address {
+            0x4800..=0x4FFF => {
+                let data = self.ram[self.address as usize];
+                self.step_address();
+                data
+            }
+            _ => 0,
+        }
+    }
+
+    pub fn peek(&self, address: u16) -> u8 {
+        match address {
+            0x4800..=0x4FFF => self.ram[self.address as usize],
+            _ => 0,
+        }
+    }
+
+    pub fn write(&mut self, address: u16, data: u8) {
+        match address {
+            0x4800..=0x4FFF => {
+                self.ram[self.address as usize] = data;
+                self.step_address();
+            }
+            0xF800..=0xFFFF => {
+                self.address = data & 0x7F;
+                self.increment = data & 0x80 != 0;
+            }
+            _ => {}
+        }
+    }
+
+    // Called once per CPU cycle.
+    pub fn clock(&mut self) {
+        self.timer += 1;
+        if self.timer < UPDATE_CYCLES {
+            return;
+        }
+        self.timer = 0;
+        self.update(self.channel);
+        let first = 8 - self.channel_count();
+        self.channel = if self.channel <= first { 7 } else { self.channel - 1 };
+    }
+
+    // Output level in 0.0..=1.0. The chip plays its channels one after the
+    // other, so they're averaged.
+    pub fn output(&self) -> f32 {
+        let count = self.channel_count();
+        let sum: u32 = self.outputs[8 - count..].iter().map(|&output| output as u32).sum();
+        sum as f32 / count as f32 / MAX_OUTPUT
+    }
+
+    fn step_address(&mut self) {
+        if self.increment {
+            self.address = (self.address + 1) & 0x7F;
+        }
+    }
+
+    fn channel_count(&self) -> usize {
+        ((self.ram[0x7F] >> 4) & 0x07) as usize + 1
+    }
+
+    fn update(&mut self, channel: usize) {
+        let base = 0x40 + channel * 8;
+        let ram = &mut self.ram;
+        let frequency = ram[base] as u32 | (ram[base + 2] as u32) << 8 | ((ram[base + 4] & 0x03) as u32) << 16;
+        let length = (256 - (ram[base + 4] & 0xFC) as u32) << 16;
+        let phase = ram[base + 1] as u32 | (ram[base + 3] as u32) << 8 | (ram[base + 5] as u32) << 16;
+        let phase = (phase + frequency) % length;
+        ram[base + 1] = phase as u8;
+        ram[base + 3] = (phase >> 8) as u8;
+        ram[base + 5] = (phase >> 16) as u8;
+
+        let sample_address = ((phase >> 16) + ram[base + 6] as u32) & 0xFF;
+        let byte = ram[(sample_address >> 1) as usize];
+        let sample = if sample_address & 1 == 0 { byte & 0x0F } else { byte >> 4 };
+        self.outputs[channel] = sample * (ram[base + 7] & 0x0F);
+    }
+}
@@ -0,0 +1,482 @@
+use super::Mapper;
+use super::MappedAddress;
+use super::fds_audio::FdsAudio;
+use super::mmc5_audio::Mmc5Audio;
+use super::n163_audio::N163Audio;
+use super::vrc6_audio::Vrc6Audio;
+
+// NSF
+// ========
+// 0x00 => "NESM\x1A"
+// 0x05 => Version
+// 0x06 => Total songs
+// 0x07 => Starting song (1 based)
+// 0x08 => Load address
+// 0x0A => Init address
+// 0x0C => Play address
+// 0x0E => Name, artist, copyright (32 bytes each, null terminated)
+// 0x6E => NTSC play speed (1/1000000 sec ticks)
+// 0x70 => Bankswitch init values (8 bytes, all zero means no bankswitching)
+// 0x78 => PAL play speed
+// 0x7A => 0: PAL tune, 1: dual PAL/NTSC
+// 0x7B => Expansion sound chips (VRC6, VRC7, FDS, MMC5, N163, Sunsoft 5B)
+// 0x80 => Music data
+//
+// NSFe is chunked like UNIF ("NSFE", then 4 byte length, 4 byte ID, data) and
+// carries the same fields in its INFO, DATA, BANK, RATE and auth chunks.
+
+pub const NSF_MAGIC: &[u8; 5] = b"NESM\x1a";
+pub const NSFE_MAGIC: &[u8; 4] = b"NSFE";
+const NSF_HEADER_SIZE: usize = 0x80;
+const BANK_SIZE: usize = 0x1000;
+// FDS tunes have RAM from 0x6000 up, the others from 0x8000.
+const FDS_LOWEST_ADDRESS: u16 = 0x6000;
+const LOWEST_ADDRESS: u16 = 0x8000;
+const MMC5_EXRAM_SIZE: usize = 0x400;
+const DEFAULT_NTSC_SPEED: u16 = 16639;
+const DEFAULT_PAL_SPEED: u16 = 19997;
+
+pub const CHIP_VRC6: u8 = 0x01;
+pub const CHIP_VRC7: u8 = 0x02;
+pub const CHIP_FDS: u8 = 0x04;
+pub const CHIP_MMC5: u8 = 0x08;
+pub const CHIP_N163: u8 = 0x10;
+pub const CHIP_5B: u8 = 0x20;
+// VRC7's FM synthesis and the Sunsoft 5B aren't emulated, tunes for them aren't played.
+const UNSUPPORTED_CHIPS: [(u8, &str); 2] = [
+    (CHIP_VRC7, "VRC7"),
+    (CHIP_5B, "Sunsoft 5B"),
+];
+
+// Synthetic driver the player runs, mapped at DRIVER_ADDRESS:
+// 0x4100: JSR init
+// 0x4103: JMP 0x4103     <- idles here between calls
+// 0x4106: JSR play
+// 0x4109: JMP 0x4103
+pub const DRIVER_ADDRESS: u16 = 0x4100;
+pub const DRIVER_INIT: u16 = DRIVER_ADDRESS;
+pub const DRIVER_IDLE: u16 = DRIVER_ADDRESS + 3;
+pub const DRIVER_PLAY: u16 = DRIVER_ADDRESS + 6;
+const DRIVER_SIZE: usize = 12;
+
+#[derive(Clone)]
+pub struct Nsf {
+    pub songs: u8,
+    pub starting_song: u8,
+    pub load_address: u16,
+    pub init_address: u16,
+    pub play_address: u16,
+    pub name: String,
+    pub artist: String,
+    pub copyright: String,
+    pub ntsc_speed: u16,
+    pub pal_speed: u16,
+    pub pal: bool,
+    pub chips: u8,
+    pub banks: [u8; 8],
+    pub data: Vec<u8>,
+}
+
+impl Nsf {
+    pub fn new(file: &[u8]) -> Self {
+        let nsf = if file.starts_with(NSF_MAGIC) {
+            parse_nsf(file)
+        } else if file.starts_with(NSFE_MAGIC) {
+            parse_nsfe(file)
+        } else {
+            panic!("Error: Not an NSF/NSFe file");
+        };
+        // Without bankswitching the data goes at its load address, there has to be RAM or ROM there.
+        if !nsf.bankswitched() && nsf.load_address < nsf.lowest_address() {
+            panic!("Error: NSF load address ${:04X} is below ${:04X}", nsf.load_address, nsf.lowest_address());
+        }
+        nsf
+    }
+
+    fn lowest_address(&self) -> u16 {
+        if self.chips & CHIP_FDS != 0 { FDS_LOWEST_ADDRESS } else { LOWEST_ADDRESS }
+    }
+
+    // Names of the expansion chips the tune uses that aren't emulated.
+    pub fn unsupported_chips(&self) -> Vec<&'static str> {
+        UNSUPPORTED_CHIPS.iter().filter(|(flag, _)| self.chips & flag != 0).map(|&(_, name)| name).collect()
+    }
+
+    pub fn bankswitched(&self) -> bool {
+        self.banks.iter().any(|&b| b != 0)
+    }
+
+    // Play routine period in microseconds.
+    pub fn play_speed(&self) -> u16 {
+        if self.pal { self.pal_speed } else { self.ntsc_speed }
+    }
+
+    // PRG image split in 4KB banks, with the data placed at its load address.
+    pub fn prg_image(&self) -> Vec<u8> {
+        let padding = if self.bankswitched() {
+            (self.load_address & 0x0FFF) as usize
+        } else {
+            (self.load_address - self.lowest_address()) as usize
+        };
+        let mut image = vec![0u8; padding];
+        image.extend_from_slice(&self.data);
+        image.resize(image.len().div_ceil(BANK_SIZE).max(1) * BANK_SIZE, 0);
+        image
+    }
+
+    // Banks mapped at 0x8000..0xFFFF (and 0x6000..0x7FFF for FDS tunes) on reset.
+    pub fn initial_banks(&self) -> [u8; 10] {
+        let mut banks = [0u8; 10];
+        if self.bankswitched() {
+            banks[2..].copy_from_slice(&self.banks);
+            // FDS tunes take 0x6000..0x7FFF from the 0xE000/0xF000 values.
+            banks[0] = self.banks[6];
+            banks[1] = self.banks[7];
+        } else if self.chips & CHIP_FDS != 0 {
+            for (i, bank) in banks.iter_mut().enumerate() {
+                *bank = i as u8;
+            }
+        } else {
+            for (i, bank) in banks.iter_mut().skip(2).enumerate() {
+                *bank = i as u8;
+            }
+        }
+        banks
+    }
+}
+
+fn word(data: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([data[at], data[at + 1]])
+}
+
+fn text(data: &[u8]) -> String {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).trim().to_string()
+}
+
+fn speed_or_default(speed: u16, default: u16) -> u16 {
+    if speed == 0 { default } else { speed }
+}
+
+fn parse_nsf(file: &[u8]) -> Nsf {
+    if file.len() <= NSF_HEADER_SIZE {
+        panic!("Error: NSF file is too short");
+    }
+    let mut banks = [0u8; 8];
+    banks.copy_from_slice(&file[0x70..0x78]);
+    Nsf {
+        songs: file[0x06],
+        starting_song: file[0x07].max(1),
+        load_address: word(file, 0x08),
+        init_address: word(file, 0x0A),
+        play_address: word(file, 0x0C),
+        name: text(&file[0x0E..0x2E]),
+        artist: text(&file[0x2E..0x4E]),
+        copyright: text(&file[0x4E..0x6E]),
+        ntsc_speed: speed_or_default(word(file, 0x6E), DEFAULT_NTSC_SPEED),
+        pal_speed: speed_or_default(word(file, 0x78), DEFAULT_PAL_SPEED),
+        // Dual region tunes are played as NTSC.
+        pal: file[0x7A] & 0x03 == 0x01,
+        chips: file[0x7B],
+        banks,
+        data: file[NSF_HEADER_SIZE..].to_vec(),
+    }
+}
+
+fn parse_nsfe(file: &[u8]) -> Nsf {
+    let mut nsf = Nsf {
+        songs: 1,
+        starting_song: 1,
+        load_address: 0,
+        init_address: 0,
+        play_address: 0,
+        name: String::new(),
+        artist: String::new(),
+        copyright: String::new(),
+        ntsc_speed: DEFAULT_NTSC_SPEED,
+        pal_speed: DEFAULT_PAL_SPEED,
+        pal: false,
+        chips: 0,
+        banks: [0; 8],
+        data: Vec::new(),
+    };
+    let mut has_info = false;
+
+    let mut pos = NSFE_MAGIC.len();
+    while pos + 8 <= file.len() {
+        let len = u32::from_le_bytes([file[pos], file[pos + 1], file[pos + 2], file[pos + 3]]) as usize;
+        let id = &file[pos + 4..pos + 8];
+        let start = pos + 8;
+        let end = start + len;
+        if end > file.len() {
+            panic!("Error: NSFe chunk {} runs past end of file", String::from_utf8_lossy(id));
+        }
+        let body = &file[start..end];
+
+        match id {
+            b"INFO" => {
+                if body.len() < 9 {
+                    panic!("Error: NSFe INFO chunk is too short");
+                }
+                nsf.load_address = word(body, 0);
+                nsf.init_address = word(body, 2);
+                nsf.play_address = word(body, 4);
+                nsf.pal = body[6] & 0x03 == 0x01;
+                nsf.chips = body[7];
+                nsf.songs = body[8];
+                // Starting song is 0 based in NSFe.
+                nsf.starting_song = body.get(9).copied().unwrap_or(0) + 1;
+                has_info = true;
+            }
+            b"DATA" => nsf.data = body.to_vec(),
+            b"BANK" => {
+                for (bank, &value) in nsf.banks.iter_mut().zip(body.iter()) {
+                    *bank = value;
+                }
+            }
+            b"RATE" => {
+                if body.len() >= 2 { nsf.ntsc_speed = speed_or_default(word(body, 0), DEFAULT_NTSC_SPEED); }
+                if body.len() >= 4 { nsf.pal_speed = speed_or_default(word(body, 2), DEFAULT_PAL_SPEED); }
+            }
+            b"auth" => {
+                let mut fields = body.split(|&b| b == 0).map(text);
+                nsf.name = fields.next().unwrap_or_default();
+                nsf.artist = fields.next().unwrap_or_default();
+                nsf.copyright = fields.next().unwrap_or_default();
+            }
+            b"NEND" => break,
+            _ => {
+                // Uppercase first letter means the chunk is required to play the file.
+                if id[0].is_ascii_uppercase() {
+                    panic!("Error: Unsupported required NSFe chunk {}", String::from_utf8_lossy(id));
+                }
+            }
+        }
+        pos = end;
+    }
+
+    if !has_info || nsf.data.is_empty() {
+        panic!("Error: NSFe file is missing its INFO or DATA chunk");
+    }
+    nsf
+}
+
+// NSF "mapper": 4KB banks selected through 0x5FF8..0x5FFF, 8KB PRG RAM at
+// 0x6000..0x7FFF and the player driver at DRIVER_ADDRESS, with the registers of
+// the expansion chips the tune uses.
+// FDS tunes get 0x6000..0xFFFF as RAM instead, and 0x5FF6..0x5FFF copy banks
+// into it.
+pub struct NsfMapper {
+    banks: [u8; 10],
+    bank_count: usize,
+    // The PRG image and the RAM it's copied to, for FDS tunes.
+    fds_prg: Vec<u8>,
+    fds_ram: Vec<u8>,
+    driver: [u8; DRIVER_SIZE],
+    fds_audio: Option<FdsAudio>,
+    vrc6_audio: Option<Vrc6Audio>,
+    mmc5_audio: Option<Mmc5Audio>,
+    n163_audio: Option<N163Audio>,
+    // MMC5's multiplier at 0x5205/0x5206 and its ExRAM at 0x5C00..0x5FF5, also
+    // there for tunes to use.
+    mmc5_multiplicands: [u8; 2],
+    mmc5_exram: Vec<u8>,
+}
+
+impl NsfMapper {
+    pub fn new(nsf: &Nsf) -> Self {
+        let init = nsf.init_address.to_le_bytes();
+        let play = nsf.play_address.to_le_bytes();
+        let idle = DRIVER_IDLE.to_le_bytes();
+        let chip = |flag: u8| nsf.chips & flag != 0;
+        let prg = nsf.prg_image();
+        let mut mapper = NsfMapper {
+            banks: nsf.initial_banks(),
+            bank_count: prg.len() / BANK_SIZE,
+            fds_ram: if chip(CHIP_FDS) { vec![0; 10 * BANK_SIZE] } else { Vec::new() },
+            fds_prg: if chip(CHIP_FDS) { prg } else { Vec::new() },
+            driver: [
+                0x20, init[0], init[1],
+                0x4C, idle[0], idle[1],
+                0x20, play[0], play[1],
+                0x4C, idle[0], idle[1],
+            ],
+            fds_audio: if chip(CHIP_FDS) { Some(FdsAudio::new()) } else { None },
+            vrc6_audio: if chip(CHIP_VRC6) { Some(Vrc6Audio::new()) } else { None },
+            mmc5_audio: if chip(CHIP_MMC5) { Some(Mmc5Audio::new()) } else { None },
+            n163_audio: if chip(CHIP_N163) { Some(N163Audio::new()) } else { None },
+            mmc5_multiplicands: [0; 2],
+            mmc5_exram: if chip(CHIP_MMC5) { vec![0; MMC5_EXRAM_SIZE] } else { Vec::new() },
+        };
+        for slot in 0..mapper.banks.len() {
+            mapper.load_fds_bank(slot);
+        }
+        mapper
+    }
+
+    fn fds(&self) -> bool {
+        !self.fds_ram.is_empty()
+    }
+
+    fn mmc5(&self) -> bool {
+        !self.mmc5_exram.is_empty()
+    }
+
+    fn bank_offset(&self, slot: usize, address: u16) -> usize {
+        (self.banks[slot] as usize % self.bank_count) * BANK_SIZE + (address as usize & 0x0FFF)
+    }
+
+    // Copies the bank selected for slot into FDS RAM, over whatever the tune left there.
+    fn load_fds_bank(&mut self, slot: usize) {
+        if !self.fds() {
+            return;
+        }
+        let bank = self.bank_offset(slot, 0);
+        self.fds_ram[slot * BANK_SIZE..(slot + 1) * BANK_SIZE].copy_from_slice(&self.fds_prg[bank..bank + BANK_SIZE]);
+    }
+
+    fn is_register(&self, address: u16) -> bool {
+        match address {
+            0x4040..=0x4097 => self.fds_audio.is_some(),
+            0x4800..=0x4FFF => self.n163_audio.is_some(),
+            0x5000..=0x5015 | 0x5205..=0x5206 | 0x5C00..=0x5FF5 => self.mmc5(),
+            0x6000..=0xFFFF if self.fds() => true,
+            _ => false,
+        }
+    }
+
+    // Registers that are only written, in ROM space.
+    fn is_write_register(&self, address: u16) -> bool {
+        match address {
+            0x9000..=0x9003 | 0xA000..=0xA002 | 0xB000..=0xB002 => self.vrc6_audio.is_some(),
+            0xF800..=0xFFFF => self.n163_audio.is_some(),
+            _ => false,
+        }
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        match address {
+            0x5205 => self.mmc5_multiplicands[0].wrapping_mul(self.mmc5_multiplicands[1]),
+            0x5206 => ((self.mmc5_multiplicands[0] as u16 * self.mmc5_multiplicands[1] as u16) >> 8) as u8,
+            0x5C00..=0x5FF5 => self.mmc5_exram[(address - 0x5C00) as usize],
+            0x6000..=0xFFFF => self.fds_ram[(address - 0x6000) as usize],
+            _ => self.driver.get(address.wrapping_sub(DRIVER_ADDRESS) as usize).copied().unwrap_or(0),
+        }
+    }
+}
+
+impl Mapper for NsfMapper {
+    fn map_prg_read(&self, address: u16) -> MappedAddress {
+        match address {
+            _ if self.is_register(address) => MappedAddress::Register,
+            _ if (DRIVER_ADDRESS..DRIVER_ADDRESS + DRIVER_SIZE as u16).contains(&address) => MappedAddress::Register,
+            0x6000..=0x7FFF => MappedAddress::Ram((address - 0x6000) as usize),
+            0x8000..=0xFFFF => MappedAddress::Rom(self.bank_offset(((address - 0x6000) >> 12) as usize, address)),
+            _ => MappedAddress::Unmapped,
+        }
+    }
+
+    fn map_prg_write(&mut self, address: u16) -> MappedAddress {
+        match address {
+            _ if self.is_register(address) || self.is_write_register(address) => MappedAddress::Register,
+            0x5FF6..=0x5FFF => MappedAddress::Register,
+            0x6000..=0x7FFF => MappedAddress::Ram((address - 0x6000) as usize),
+            _ => MappedAddress::Unmapped,
+        }
+    }
+
+    fn map_chr_read(&self, _address: u16) -> MappedAddress {
+        MappedAddress::Unmapped
+    }
+
+    fn map_chr_write(&mut self, _address: u16) -> MappedAddress {
+        MappedAddress::Unmapped
+    }
+
+    fn can_cpu_read(&self, address: u16) -> bool {
+        address >= 0x4020
+    }
+
+    fn can_ppu_read(&self, _address: u16) -> bool {
+        false
+    }
+
+    fn read_register(&mut self, address: u16) -> u8 {
+        match address {
+            0x4040..=0x4097 => self.fds_audio.as_ref().map_or(0, |audio| audio.read(address)),
+            0x4800..=0x4FFF => self.n163_audio.as_mut().map_or(0, |audio| audio.read(address)),
+            0x5000..=0x5015 => self.mmc5_audio.as_ref().map_or(0, |audio| audio.read(address)),
+            _ => self.peek(address),
+        }
+    }
+
+    fn peek_register(&self, address: u16) -> u8 {
+        match address {
+            0x4040..=0x4097 => self.fds_audio.as_ref().map_or(0, |audio| audio.read(address)),
+            0x4800..=0x4FFF => self.n163_audio.as_ref().map_or(0, |audio| audio.peek(address)),
+            0x5000..=0x5015 => self.mmc5_audio.as_ref().map_or(0, |audio| audio.read(address)),
+            _ => self.peek(address),
+        }
+    }
+
+    fn write_register(&mut self, address: u16, data: u8) {
+        match address {
+            0x4040..=0x4097 => {
+                if let Some(audio) = self.fds_audio.as_mut() {
+                    audio.write(address, data);
+                }
+            }
+            0x4800..=0x4FFF | 0xF800..=0xFFFF if self.n163_audio.is_some() => {
+                if let Some(audio) = self.n163_audio.as_mut() {
+                    audio.write(address, data);
+                }
+            }
+            0x5000..=0x5015 => {
+                if let Some(audio) = self.mmc5_audio.as_mut() {
+                    audio.write(address, data);
+                }
+            }
+            0x5205..=0x5206 => self.mmc5_multiplicands[(address - 0x5205) as usize] = data,
+            0x5C00..=0x5FF5 => self.mmc5_exram[(address - 0x5C00) as usize] = data,
+            0x5FF6..=0x5FFF => {
+                let slot = (address - 0x5FF6) as usize;
+                self.banks[slot] = data;
+                self.load_fds_bank(slot);
+            }
+            0x9000..=0x9003 | 0xA000..=0xA002 | 0xB000..=0xB002 if self.vrc6_audio.is_some() => {
+                if let Some(audio) = self.vrc6_audio.as_mut() {
+                    audio.write(address, data);
+                }
+            }
+            0x6000..=0xFFFF if self.fds() => self.fds_ram[(address - 0x6000) as usize] = data,
+            _ => {}
+        }
+    }
+
+    fn clock(&mut self) {
+        if let Some(audio) = self.fds_audio.as_mut() {
+            audio.clock();
+        }
+        if let Some(audio) = self.vrc6_audio.as_mut() {
+            audio.clock();
+        }
+        if let Some(audio) = self.mmc5_audio.as_mut() {
+            audio.clock();
+        }
+        if let Some(audio) = self.n163_audio.as_mut() {
+            audio.clock();
+        }
+    }
+
+    // The chips the tune uses, averaged.
+    fn audio_output(&self) -> f32 {
+        let outputs = [
+            self.fds_audio.as_ref().map(|audio| audio.output()),
+            self.vrc6_audio.as_ref().map(|audio| audio.output()),
+            self.mmc5_audio.as_ref().map(|audio| audio.output()),
+            self.n163_audio.as_ref().map(|audio| audio.output()),
+        ];
+        let (sum, count) = outputs.iter().flatten().fold((0.0, 0), |(sum, count), output| (sum + output, count + 1));
+        if count == 0 { 0.0 } else { sum / count as f32 }
+    }
+}
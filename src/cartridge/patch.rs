@@ -0,0 +1,227 @@
+use super::hash::Crc32;
+
+// Soft-patching of ROM images before they're parsed.
+// ========
+// IPS: "PATCH", records of 3 byte offset + 2 byte size + data (size 0 => RLE:
+//      2 byte count + 1 byte value), "EOF", optional 3 byte truncate length.
+// BPS: "BPS1", varint source/target/metadata sizes, metadata, actions
+//      (SourceRead, TargetRead, SourceCopy, TargetCopy), source/target/patch CRC32.
+// UPS: "UPS1", varint input/output sizes, (varint skip, XOR bytes up to 0x00)*,
+//      input/output/patch CRC32.
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const BPS_MAGIC: &[u8] = b"BPS1";
+const UPS_MAGIC: &[u8] = b"UPS1";
+const FOOTER_SIZE: usize = 12;
+
+pub fn apply(rom: &[u8], patch: &[u8]) -> Vec<u8> {
+    if patch.starts_with(IPS_MAGIC) {
+        apply_ips(rom, patch)
+    } else if patch.starts_with(BPS_MAGIC) {
+        apply_bps(rom, patch)
+    } else if patch.starts_with(UPS_MAGIC) {
+        apply_ups(rom, patch)
+    } else {
+        panic!("Error: Unknown patch format");
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finish()
+}
+
+struct PatchReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PatchReader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        PatchReader { data, pos }
+    }
+
+    fn byte(&mut self) -> u8 {
+        let byte = *self.data.get(self.pos).expect("Error: Patch is truncated");
+        self.pos += 1;
+        byte
+    }
+
+    fn bytes(&mut self, count: usize) -> &'a [u8] {
+        let bytes = self.data.get(self.pos..self.pos + count).expect("Error: Patch is truncated");
+        self.pos += count;
+        bytes
+    }
+
+    fn big_endian(&mut self, count: usize) -> usize {
+        self.bytes(count).iter().fold(0, |value, &b| (value << 8) | b as usize)
+    }
+
+    // BPS/UPS variable length number: 7 bits per byte, last byte has bit 7 set.
+    fn varint(&mut self) -> usize {
+        let mut value = 0usize;
+        let mut shift = 1usize;
+        loop {
+            let byte = self.byte();
+            value += (byte & 0x7F) as usize * shift;
+            if byte & 0x80 != 0 {
+                return value;
+            }
+            shift <<= 7;
+            value += shift;
+        }
+    }
+
+    fn footer_crc(&self, index: usize) -> u32 {
+        let at = self.data.len() - FOOTER_SIZE + index * 4;
+        u32::from_le_bytes([self.data[at], self.data[at + 1], self.data[at + 2], self.data[at + 3]])
+    }
+}
+
+fn apply_ips(rom: &[u8], patch: &[u8]) -> Vec<u8> {
+    let mut out = rom.to_vec();
+    let mut reader = PatchReader::new(patch, IPS_MAGIC.len());
+    loop {
+        let offset = reader.bytes(3);
+        if offset == IPS_EOF {
+            break;
+        }
+        let offset = offset.iter().fold(0, |value, &b| (value << 8) | b as usize);
+        let size = reader.big_endian(2);
+        let (count, fill) = if size == 0 {
+            // RLE record
+            let count = reader.big_endian(2);
+            (count, Some(reader.byte()))
+        } else {
+            (size, None)
+        };
+        if out.len() < offset + count {
+            out.resize(offset + count, 0);
+        }
+        match fill {
+            Some(value) => out[offset..offset + count].fill(value),
+            None => out[offset..offset + count].copy_from_slice(reader.bytes(count)),
+        }
+    }
+    // Truncate extension (Lunar IPS)
+    if patch.len() - reader.pos >= 3 {
+        let length = reader.big_endian(3);
+        out.truncate(length);
+    }
+    out
+}
+
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Vec<u8> {
+    if patch.len() < BPS_MAGIC.len() + FOOTER_SIZE {
+        panic!("Error: BPS patch is truncated");
+    }
+    let mut reader = PatchReader::new(patch, BPS_MAGIC.len());
+    if crc32(&patch[..patch.len() - 4]) != reader.footer_crc(2) {
+        panic!("Error: BPS patch is corrupt (patch checksum mismatch)");
+    }
+    if crc32(rom) != reader.footer_crc(0) {
+        panic!("Error: BPS patch was made for a different ROM (source checksum mismatch)");
+    }
+
+    let source_size = reader.varint();
+    let target_size = reader.varint();
+    let metadata_size = reader.varint();
+    reader.bytes(metadata_size);
+    if source_size != rom.len() {
+        panic!("Error: BPS patch expects a {} byte ROM, got {}", source_size, rom.len());
+    }
+
+    let mut out: Vec<u8> = Vec::with_capacity(target_size);
+    let mut source_offset: isize = 0;
+    let mut target_offset: isize = 0;
+    let end = patch.len() - FOOTER_SIZE;
+    while reader.pos < end {
+        let data = reader.varint();
+        let length = (data >> 2) + 1;
+        match data & 3 {
+            // SourceRead
+            0 => {
+                let at = out.len();
+                out.extend_from_slice(rom.get(at..at + length).expect("Error: BPS SourceRead out of range"));
+            }
+            // TargetRead
+            1 => out.extend_from_slice(reader.bytes(length)),
+            // SourceCopy
+            2 => {
+                source_offset += signed_offset(reader.varint());
+                let at = source_offset as usize;
+                out.extend_from_slice(rom.get(at..at + length).expect("Error: BPS SourceCopy out of range"));
+                source_offset += length as isize;
+            }
+            // TargetCopy, may overlap what it's writing so copy byte by byte
+            _ => {
+                target_offset += signed_offset(reader.varint());
+                for _ in 0..length {
+                    let byte = *out.get(target_offset as usize).expect("Error: BPS TargetCopy out of range");
+                    out.push(byte);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    if out.len() != target_size || crc32(&out) != reader.footer_crc(1) {
+        panic!("Error: BPS patch produced a bad ROM (target checksum mismatch)");
+    }
+    out
+}
+
+fn signed_offset(data: usize) -> isize {
+    let value = (data >> 1) as isize;
+    if data & 1 != 0 { -value } else { value }
+}
+
+fn apply_ups(rom: &[u8], patch: &[u8]) -> Vec<u8> {
+    if patch.len() < UPS_MAGIC.len() + FOOTER_SIZE {
+        panic!("Error: UPS patch is truncated");
+    }
+    let mut reader = PatchReader::new(patch, UPS_MAGIC.len());
+    if crc32(&patch[..patch.len() - 4]) != reader.footer_crc(2) {
+        panic!("Error: UPS patch is corrupt (patch checksum mismatch)");
+    }
+
+    let input_size = reader.varint();
+    let output_size = reader.varint();
+    // UPS patches work both ways, applying to the output gives back the input.
+    let rom_crc = crc32(rom);
+    let (expected_crc, out_size) = if rom_crc == reader.footer_crc(0) && rom.len() == input_size {
+        (reader.footer_crc(1), output_size)
+    } else if rom_crc == reader.footer_crc(1) && rom.len() == output_size {
+        (reader.footer_crc(0), input_size)
+    } else {
+        panic!("Error: UPS patch was made for a different ROM (input checksum mismatch)");
+    };
+
+    let mut out = rom.to_vec();
+    out.resize(out_size.max(rom.len()), 0);
+    let mut pos = 0;
+    let end = patch.len() - FOOTER_SIZE;
+    while reader.pos < end {
+        pos += reader.varint();
+        loop {
+            let byte = reader.byte();
+            if pos >= out.len() {
+                out.resize(pos + 1, 0);
+            }
+            if byte == 0 {
+                pos += 1;
+                break;
+            }
+            out[pos] ^= byte;
+            pos += 1;
+        }
+    }
+    out.truncate(out_size);
+
+    if crc32(&out) != expected_crc {
+        panic!("Error: UPS patch produced a bad ROM (output checksum mismatch)");
+    }
+    out
+}
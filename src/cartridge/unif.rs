@@ -0,0 +1,142 @@
+use super::Mirroring;
+use super::CartridgeHeader;
+use super::CartridgeData;
+
+// UNIF
+// ========
+// 0x00 => "UNIF"
+// 0x04 => Revision (u32, little endian)
+// 0x08 => Reserved (24 bytes)
+// 0x20 => Chunks: 4 byte ID, u32 little endian length, data
+//
+// Chunks we care about:
+// MAPR       => Board name, null terminated ("NES-NROM-256", "UNL-...")
+// PRG0..PRGF => PRG-ROM pieces, concatenated in order
+// CHR0..CHRF => CHR-ROM pieces, concatenated in order
+// MIRR       => 0: Horizontal, 1: Vertical, 2: Single A, 3: Single B, 4: Four screen, 5: Mapper controlled
+// BATR       => Presence means battery backed PRG-RAM
+// Everything else (NAME, READ, DINF, TVCI, CTRL, PCK*, CCK*...) is skipped.
+
+pub const MAGIC: &[u8; 4] = b"UNIF";
+const HEADER_SIZE: usize = 32;
+const PRG_ROM_PAGE_SIZE: usize = 0x4000;
+const CHR_ROM_PAGE_SIZE: usize = 0x2000;
+
+pub fn parse(data: &[u8]) -> Result<(CartridgeHeader, CartridgeData), String> {
+    if data.len() < HEADER_SIZE || &data[0..4] != MAGIC {
+        return Err(String::from("Not a UNIF image"));
+    }
+
+    let mut board: Option<String> = None;
+    let mut prg_chunks: [Option<&[u8]>; 16] = [None; 16];
+    let mut chr_chunks: [Option<&[u8]>; 16] = [None; 16];
+    let mut mirroring = Mirroring::Horizontal;
+    let mut battery = false;
+
+    let mut pos = HEADER_SIZE;
+    while pos + 8 <= data.len() {
+        let id = &data[pos..pos + 4];
+        let len = u32::from_le_bytes([data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]]) as usize;
+        let start = pos + 8;
+        let end = start + len;
+        if end > data.len() {
+            return Err(format!("UNIF chunk {} runs past end of file", String::from_utf8_lossy(id)));
+        }
+        let body = &data[start..end];
+
+        match id {
+            b"MAPR" => {
+                let name_end = body.iter().position(|&b| b == 0).unwrap_or(body.len());
+                board = Some(String::from_utf8_lossy(&body[..name_end]).trim().to_string());
+            }
+            b"MIRR" => {
+                mirroring = match body.first() {
+                    Some(0) => Mirroring::Horizontal,
+                    Some(1) => Mirroring::Vertical,
+                    Some(2) => Mirroring::OneScreenLower,
+                    Some(3) => Mirroring::OneScreenUpper,
+                    Some(4) => Mirroring::FourScreen,
+                    // Mapper controlled, the mapper sets it up on its own.
+                    _ => Mirroring::Horizontal,
+                };
+            }
+            b"BATR" => battery = true,
+            _ if &id[0..3] == b"PRG" => {
+                if let Some(i) = chunk_index(id[3]) { prg_chunks[i] = Some(body); }
+            }
+            _ if &id[0..3] == b"CHR" => {
+                if let Some(i) = chunk_index(id[3]) { chr_chunks[i] = Some(body); }
+            }
+            _ => {}
+        }
+        pos = end;
+    }
+
+    let board = board.ok_or("UNIF image has no MAPR chunk")?;
+    let mapper = board_to_mapper(&board).ok_or_else(|| format!("Unknown UNIF board {}", board))?;
+    if mapper != 0 {
+        return Err(format!("UNIF board {} (mapper {}) isn't supported, only NROM boards are", board, mapper));
+    }
+
+    let prg_rom: Vec<u8> = prg_chunks.iter().flatten().flat_map(|c| c.iter().copied()).collect();
+    let chr_rom: Vec<u8> = chr_chunks.iter().flatten().flat_map(|c| c.iter().copied()).collect();
+    if prg_rom.is_empty() {
+        return Err(String::from("UNIF image has no PRG chunks"));
+    }
+
+    let prg_rom_pages = prg_rom.len().div_ceil(PRG_ROM_PAGE_SIZE);
+    let chr_rom_pages = chr_rom.len().div_ceil(CHR_ROM_PAGE_SIZE);
+    // Smaller chips (8KB PRG on NROM-128 boards) show up mirrored across the whole page.
+    let prg_rom = mirror_to(prg_rom, prg_rom_pages * PRG_ROM_PAGE_SIZE, "PRG")?;
+    let chr_rom = mirror_to(chr_rom, chr_rom_pages * CHR_ROM_PAGE_SIZE, "CHR")?;
+    // UNIF doesn't store the PRG-RAM size, every board we know of has at most 8KB.
+    let header = CartridgeHeader::new(mapper, mirroring, battery, prg_rom_pages, 1, chr_rom_pages);
+    let cart_data = CartridgeData::new(prg_rom, vec![0u8; header.prg_ram_bytes()], chr_rom, vec![0u8; header.chr_ram_bytes()]);
+    Ok((header, cart_data))
+}
+
+// The board leaves the top address lines off a smaller chip, which only
+// repeats it evenly when its size is a power of two.
+fn mirror_to(rom: Vec<u8>, size: usize, name: &str) -> Result<Vec<u8>, String> {
+    if !rom.is_empty() && !rom.len().is_power_of_two() {
+        return Err(format!("UNIF {} ROM is {} bytes, not a power of two", name, rom.len()));
+    }
+    if rom.len() == size {
+        return Ok(rom);
+    }
+    Ok(rom.iter().copied().cycle().take(size).collect())
+}
+
+fn chunk_index(digit: u8) -> Option<usize> {
+    (digit as char).to_digit(16).map(|d| d as usize)
+}
+
+// Translates a UNIF board name to the iNES mapper number implementing it.
+// Board names are compared without their "NES-"/"HVC-"/"UNL-"... prefix.
+pub fn board_to_mapper(board: &str) -> Option<u8> {
+    let name = board.to_ascii_uppercase();
+    let name = ["NES-", "HVC-", "UNL-", "BTL-", "BMC-", "IREM-", "KONAMI-", "TENGEN-"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(&name)
+        .to_string();
+
+    let mapper = match name.as_str() {
+        "NROM" | "NROM-128" | "NROM-256" | "RROM" | "RROM-128" | "SROM" | "HROM" => 0,
+        "SAROM" | "SBROM" | "SCROM" | "SEROM" | "SFROM" | "SGROM" | "SHROM" | "SJROM" | "SKROM" | "SLROM"
+        | "SL1ROM" | "SNROM" | "SOROM" | "SUROM" | "SXROM" => 1,
+        "UNROM" | "UOROM" => 2,
+        "CNROM" => 3,
+        "TBROM" | "TEROM" | "TFROM" | "TGROM" | "TKROM" | "TLROM" | "TL1ROM" | "TLSROM" | "TQROM" | "TR1ROM"
+        | "TSROM" | "TVROM" | "B4" => 4,
+        "EKROM" | "ELROM" | "ETROM" | "EWROM" => 5,
+        "AMROM" | "ANROM" | "AN1ROM" | "AOROM" => 7,
+        "PNROM" => 9,
+        "FJROM" | "FKROM" => 10,
+        "CPROM" => 13,
+        "BNROM" => 34,
+        "GNROM" | "MHROM" => 66,
+        _ => return None,
+    };
+    Some(mapper)
+}
@@ -0,0 +1,157 @@
+// VRC6 expansion sound: two pulse channels with 8 duty cycles and a sawtooth.
+// ========
+// 0x9000 / 0xA000  => Pulse 1 / 2 (7: ignore duty, 4-6: duty, 0-3: volume)
+// 0x9001 / 0xA001  => Pulse 1 / 2 period low
+// 0x9002 / 0xA002  => Pulse 1 / 2 (7: enable, 0-3: period high)
+// 0x9003           => 0: halt all, 1: periods / 16, 2: periods / 256
+// 0xB000           => Sawtooth accumulator rate (0-5)
+// 0xB001 / 0xB002  => Sawtooth period low / (7: enable, 0-3: period high)
+
+// 15 + 15 + 31
+const MAX_OUTPUT: f32 = 61.0;
+
+#[derive(Default)]
+struct Pulse {
+    volume: u8,
+    duty: u8,
+    ignore_duty: bool,
+    enabled: bool,
+    period: u16,
+    timer: u16,
+    // Counts down from 15, the output is on while it's at or below duty.
+    step: u8,
+}
+
+impl Pulse {
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.volume = data & 0x0F;
+                self.duty = (data >> 4) & 0x07;
+                self.ignore_duty = data & 0x80 != 0;
+            }
+            1 => self.period = (self.period & 0x0F00) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | (((data & 0x0F) as u16) << 8);
+                self.enabled = data & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 15;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step = self.step.wrapping_sub(1) & 0x0F;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.ignore_duty || self.step <= self.duty) { self.volume } else { 0 }
+    }
+}
+
+#[derive(Default)]
+struct Sawtooth {
+    rate: u8,
+    enabled: bool,
+    period: u16,
+    timer: u16,
+    // The rate is added every second step, the 14th clears the accumulator.
+    step: u8,
+    accumulator: u8,
+}
+
+impl Sawtooth {
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => self.rate = data & 0x3F,
+            1 => self.period = (self.period & 0x0F00) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | (((data & 0x0F) as u16) << 8);
+                self.enabled = data & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step += 1;
+            if self.step == 14 {
+                self.step = 0;
+                self.accumulator = 0;
+            } else if self.step & 1 == 0 {
+                self.accumulator = self.accumulator.wrapping_add(self.rate);
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+pub struct Vrc6Audio {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    sawtooth: Sawtooth,
+    halt: bool,
+    shift: u8,
+}
+
+impl Vrc6Audio {
+    pub fn new() -> Self {
+        Vrc6Audio {
+            pulse1: Pulse { step: 15, ..Pulse::default() },
+            pulse2: Pulse { step: 15, ..Pulse::default() },
+            sawtooth: Sawtooth::default(),
+            halt: false,
+            shift: 0,
+        }
+    }
+
+    pub fn write(&mut self, address: u16, data: u8) {
+        match address {
+            0x9000..=0x9002 => self.pulse1.write(address & 3, data),
+            0x9003 => {
+                self.halt = data & 0x01 != 0;
+                self.shift = if data & 0x04 != 0 { 8 } else if data & 0x02 != 0 { 4 } else { 0 };
+            }
+            0xA000..=0xA002 => self.pulse2.write(address & 3, data),
+            0xB000..=0xB002 => self.sawtooth.write(address & 3, data),
+            _ => {}
+        }
+    }
+
+    // Called once per CPU cycle.
+    pub fn clock(&mut self) {
+        if self.halt {
+            return;
+        }
+        self.pulse1.clock(self.shift);
+        self.pulse2.clock(self.shift);
+        self.sawtooth.clock(self.shift);
+    }
+
+    // Output level in 0.0..=1.0
+    pub fn output(&self) -> f32 {
+        (self.pulse1.output() + self.pulse2.output() + self.sawtooth.output()) as f32 / MAX_OUTPUT
+    }
+}
@@ -0,0 +1,89 @@
+use crate::bus::cartridge::Cartridge;
+use std::fs;
+use std::io;
+
+// PRG ROM byte flags, FCEUX's xPdcAADC. Opcodes and their operands are both
+// code, FCEUX doesn't tell them apart.
+pub const CODE: u8 = 0x01;
+pub const DATA: u8 = 0x02;
+// Which 8KB window of $8000-$FFFF the byte was mapped to when last used.
+pub const BANK_MASK: u8 = 0x0C;
+// Reached through a pointer, JMP ($xxxx) targets and (zp),Y / (zp,X) data.
+pub const INDIRECT_CODE: u8 = 0x10;
+pub const INDIRECT_DATA: u8 = 0x20;
+// Played by the DMC.
+pub const PCM: u8 = 0x40;
+
+// CHR ROM byte flags.
+pub const RENDERED: u8 = 0x01;
+// Read by the CPU through $2007.
+pub const CHR_READ: u8 = 0x02;
+
+// Code/data log kept per ROM offset, so the same CPU address in different
+// banks is told apart. The file is FCEUX's .cdl: one flags byte per PRG ROM
+// byte, then one per CHR ROM byte.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CodeDataLog {
+    pub prg: Vec<u8>,
+    pub chr: Vec<u8>,
+}
+
+impl CodeDataLog {
+    pub fn new(prg_size: usize, chr_size: usize) -> Self {
+        CodeDataLog {
+            prg: vec![0; prg_size],
+            chr: vec![0; chr_size],
+        }
+    }
+
+    pub fn for_cartridge(cartridge: &Cartridge) -> Self {
+        CodeDataLog::new(cartridge.prg_rom_size(), cartridge.chr_rom_size())
+    }
+
+    // A .cdl for a ROM with these sizes, None when it's for a different one.
+    pub fn parse(data: &[u8], prg_size: usize, chr_size: usize) -> Option<CodeDataLog> {
+        if data.len() != prg_size + chr_size {
+            return None;
+        }
+        Some(CodeDataLog {
+            prg: data[..prg_size].to_vec(),
+            chr: data[prg_size..].to_vec(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [self.prg.as_slice(), self.chr.as_slice()].concat()
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        fs::write(path, self.to_bytes())
+    }
+
+    // offset in PRG ROM was used at addr as flags say.
+    pub fn log_prg(&mut self, offset: usize, addr: u16, flags: u8) {
+        if let Some(byte) = self.prg.get_mut(offset) {
+            let bank = ((addr >> 13) as u8 & 0x03) << 2;
+            *byte = (*byte & !BANK_MASK) | bank | flags;
+        }
+    }
+
+    pub fn log_chr(&mut self, offset: usize, flags: u8) {
+        if let Some(byte) = self.chr.get_mut(offset) {
+            *byte |= flags;
+        }
+    }
+
+    // PRG: 1234 code, 567 data, 890 unused of 32768 bytes
+    pub fn summary(&self) -> String {
+        let count = |bytes: &[u8], flags: u8| bytes.iter().filter(|&&byte| byte & flags != 0).count();
+        let prg = format!("PRG: {} code, {} data, {} unused of {} bytes",
+                          count(&self.prg, CODE), count(&self.prg, DATA | PCM),
+                          self.prg.iter().filter(|&&byte| byte & !BANK_MASK == 0).count(), self.prg.len());
+        if self.chr.is_empty() {
+            return prg;
+        }
+        format!("{}\nCHR: {} rendered, {} read, {} unused of {} bytes",
+                prg, count(&self.chr, RENDERED), count(&self.chr, CHR_READ),
+                self.chr.iter().filter(|&&byte| byte == 0).count(), self.chr.len())
+    }
+}
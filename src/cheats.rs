@@ -0,0 +1,186 @@
+use std::fs;
+use std::io;
+
+// Game Genie letters, in the order of the 4 bit values they stand for.
+const GAME_GENIE_LETTERS: &str = "APZLGITYEOXUKSVN";
+const RAM_END: u16 = 0x1FFF;
+const RAM_SIZE: usize = 0x800;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CheatKind {
+    // Replaces what the cartridge returns for CPU reads, like a Game Genie.
+    Read,
+    // Written to RAM every frame, like a Pro Action Replay.
+    Freeze,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Cheat {
+    pub kind: CheatKind,
+    pub addr: u16,
+    pub value: u8,
+    // Only when the byte there is this, so a code for one ROM bank leaves the others alone.
+    pub compare: Option<u8>,
+    pub enabled: bool,
+    pub name: String,
+}
+
+impl Cheat {
+    // A Game Genie code (SXIOPO, YEUZUGAA), a Pro Action Replay code
+    // (00AAAAVV) or a raw one (AAAA:VV, AAAA?CC:VV) in hex. Raw codes freeze RAM
+    // addresses and replace reads of anything else.
+    pub fn parse(code: &str) -> Result<Cheat, String> {
+        let code = code.trim().to_uppercase();
+        let cheat = if code.contains(':') {
+            parse_raw(&code)
+        } else if code.len() == 8 && code.chars().all(|c| c.is_ascii_hexdigit()) {
+            parse_pro_action_replay(&code)
+        } else {
+            parse_game_genie(&code)
+        };
+        cheat.ok_or_else(|| format!("Not a cheat code: {}", code))
+    }
+}
+
+// Decodes the letters into the 4 bit values and shuffles their bits into place.
+fn parse_game_genie(code: &str) -> Option<Cheat> {
+    let n: Vec<u16> = code.chars()
+        .map(|c| GAME_GENIE_LETTERS.find(c).map(|i| i as u16))
+        .collect::<Option<_>>()?;
+    // Bit 3 of the third letter says whether the code has 8 letters.
+    if (n.len() != 6 && n.len() != 8) || (n[2] & 8 != 0) != (n.len() == 8) {
+        return None;
+    }
+    let addr = 0x8000 | ((n[3] & 7) << 12) | ((n[5] & 7) << 8) | ((n[4] & 8) << 8)
+        | ((n[2] & 7) << 4) | ((n[1] & 8) << 4) | (n[4] & 7) | (n[3] & 8);
+    let value = ((n[1] & 7) << 4) | ((n[0] & 8) << 4) | (n[0] & 7);
+    let (value, compare) = if n.len() == 6 {
+        (value | (n[5] & 8), None)
+    } else {
+        let compare = ((n[7] & 7) << 4) | ((n[6] & 8) << 4) | (n[6] & 7) | (n[5] & 8);
+        (value | (n[7] & 8), Some(compare as u8))
+    };
+    Some(Cheat { kind: CheatKind::Read, addr, value: value as u8, compare, enabled: true, name: code.to_string() })
+}
+
+// The first byte is ignored, then the address and the value.
+fn parse_pro_action_replay(code: &str) -> Option<Cheat> {
+    let addr = u16::from_str_radix(&code[2..6], 16).ok()?;
+    let value = u8::from_str_radix(&code[6..], 16).ok()?;
+    if addr > RAM_END {
+        return None;
+    }
+    Some(Cheat { kind: CheatKind::Freeze, addr, value, compare: None, enabled: true, name: code.to_string() })
+}
+
+fn parse_raw(code: &str) -> Option<Cheat> {
+    let (target, value) = code.split_once(':')?;
+    let (addr, compare) = match target.split_once('?') {
+        Some((addr, compare)) => (addr, Some(u8::from_str_radix(compare, 16).ok()?)),
+        None => (target, None),
+    };
+    let addr = u16::from_str_radix(addr, 16).ok()?;
+    let kind = if addr <= RAM_END { CheatKind::Freeze } else { CheatKind::Read };
+    let value = u8::from_str_radix(value, 16).ok()?;
+    Some(Cheat { kind, addr, value, compare, enabled: true, name: String::new() })
+}
+
+// The cheats BUS applies. The file is FCEUX's .cht, a line per cheat:
+//   [S][C][:]aaaa:vv[:cc]:name
+// S for Read cheats, C when there's a compare value and ':' when it's disabled.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct Cheats {
+    pub list: Vec<Cheat>,
+}
+
+impl Cheats {
+    pub fn new() -> Self {
+        Cheats { list: Vec::new() }
+    }
+
+    pub fn parse(text: &str) -> Result<Cheats, String> {
+        let list = text.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| parse_cht_line(line).ok_or_else(|| format!("Not a cheat: {}", line)))
+            .collect::<Result<_, _>>()?;
+        Ok(Cheats { list })
+    }
+
+    pub fn load(path: &str) -> Self {
+        let text = fs::read_to_string(path).unwrap_or_else(|e| panic!("Error: Cannot read {}: {}", path, e));
+        Cheats::parse(&text).unwrap_or_else(|e| panic!("Error: {} in {}", e, path))
+    }
+
+    pub fn to_text(&self) -> String {
+        self.list.iter().map(|cheat| {
+            let mut line = String::new();
+            if cheat.kind == CheatKind::Read {
+                line.push('S');
+            }
+            if cheat.compare.is_some() {
+                line.push('C');
+            }
+            if !cheat.enabled {
+                line.push(':');
+            }
+            match cheat.compare {
+                Some(compare) => line += &format!("{:04x}:{:02x}:{:02x}", cheat.addr, cheat.value, compare),
+                None => line += &format!("{:04x}:{:02x}", cheat.addr, cheat.value),
+            }
+            format!("{}:{}\n", line, cheat.name)
+        }).collect()
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        fs::write(path, self.to_text())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    // What a CPU read of addr from the cartridge that got data returns.
+    pub fn read(&self, addr: u16, data: u8) -> u8 {
+        self.list.iter()
+            .find(|cheat| cheat.enabled && cheat.kind == CheatKind::Read && cheat.addr == addr
+                  && cheat.compare.is_none_or(|compare| compare == data))
+            .map_or(data, |cheat| cheat.value)
+    }
+
+    // Writes the Freeze cheats to the 2KB of RAM. Those outside it do nothing.
+    // written is called with the address of every byte put back.
+    pub fn freeze(&self, memory: &mut [u8], mut written: impl FnMut(u16)) {
+        for cheat in self.list.iter().filter(|cheat| cheat.enabled && cheat.kind == CheatKind::Freeze) {
+            if cheat.addr > RAM_END {
+                continue;
+            }
+            let byte = &mut memory[cheat.addr as usize % RAM_SIZE];
+            if cheat.compare.is_none_or(|compare| compare == *byte) {
+                *byte = cheat.value;
+                written(cheat.addr);
+            }
+        }
+    }
+}
+
+// The hex is lowercase, so a C that starts the address isn't taken for the compare flag.
+fn parse_cht_line(line: &str) -> Option<Cheat> {
+    let (kind, rest) = match line.strip_prefix('S') {
+        Some(rest) => (CheatKind::Read, rest),
+        None => (CheatKind::Freeze, line),
+    };
+    let (has_compare, rest) = match rest.strip_prefix('C') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+    let (enabled, rest) = match rest.strip_prefix(':') {
+        Some(rest) => (false, rest),
+        None => (true, rest),
+    };
+    let mut fields = rest.splitn(if has_compare { 4 } else { 3 }, ':');
+    let addr = u16::from_str_radix(fields.next()?, 16).ok()?;
+    let value = u8::from_str_radix(fields.next()?, 16).ok()?;
+    let compare = if has_compare { Some(u8::from_str_radix(fields.next()?, 16).ok()?) } else { None };
+    let name = fields.next().unwrap_or("").to_string();
+    Some(Cheat { kind, addr, value, compare, enabled, name })
+}
@@ -1,16 +1,17 @@
-use crate::bus::BUS;
-use std::fs::OpenOptions;
-use std::io::prelude::*;
+use crate::cdl;
+use crate::profiler::{Flow, Profiler};
+use crate::trace::{TraceEntry, Tracer};
 //  P -> Flag registor, each bit represents a flag.
 //  00000001 -> Carry Flag
 //  00000010 -> Zero flag
 //  00000100 -> Interrupt Disable
-//  00001000 -> Decimal Mode (Doesnt Matter in NES as BCD Instructions are dropped in NES's CPU)
+//  00001000 -> Decimal Mode (Doesnt Matter in NES as BCD Instructions are dropped in NES's CPU, see CpuVariant)
 //  00010000 -> Break Command
 //  00100000 -> Unused bit
 //  01000000 -> Overflow flag
 //  10000000 -> Negative flag
 
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum Mode {
     Immediate,
     ZeroPage,
@@ -24,8 +25,20 @@ pub enum Mode {
     IndirectX,
     IndirectY,
     Implied,
+    Accumulator,
+    // 65C02 only
+    ZeroPageIndirect,
+    AbsoluteIndirectX,
 }
-#[allow(dead_code)]
+
+// The CPU is also used for 6502 targets other than the NES.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CpuVariant {
+    Ricoh2A03,  // NES, decimal mode is wired off
+    Nmos6502,
+    Cmos65C02,
+}
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum Interrupt {
     Reset,
     Irq,
@@ -33,10 +46,86 @@ pub enum Interrupt {
     Break,
 }
 
-pub struct CPU {
-    pub bus: BUS,
-    cycles: u8,
-    cycle_count: usize,
+// Devices that can pull the IRQ line low, one bit each. The line stays
+// asserted as long as any of them holds it.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum IrqSource {
+    FrameCounter = 0b001,
+    Dmc = 0b010,
+    Mapper = 0b100,
+}
+
+// Everything the CPU is connected to. The NES has its BUS, tests can use a
+// flat 64KB memory.
+pub trait Bus {
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, data: u8);
+    // Same as read but without side effects on the hardware, for logs and debuggers.
+    fn peek(&self, addr: u16) -> u8;
+
+    // Called at the end of every CPU cycle.
+    fn tick(&mut self) {}
+
+    // Called by CPU::power_on, before the reset sequence runs.
+    fn power_on(&mut self) {}
+
+    // IrqSource bits of everything holding the IRQ line right now.
+    fn irq_lines(&self) -> u8 {
+        0
+    }
+    fn nmi_line(&self) -> bool {
+        false
+    }
+
+    // Hooks for debugging tools, they do nothing unless a bus has use for them.
+
+    // Offset into the ROM image that addr reads from with the banks mapped
+    // right now, None when it isn't ROM. Tells banks apart in symbol files.
+    fn prg_rom_offset(&self, _addr: u16) -> Option<usize> {
+        None
+    }
+
+    // How the CPU used addr, as cdl::CODE and the like, for code/data logs.
+    fn code_data(&mut self, _addr: u16, _flags: u8) {}
+
+    // Called with the address of every opcode before it is fetched.
+    fn execute(&mut self, _addr: u16) {}
+}
+
+// What an instruction does once its addressing mode has done its bus accesses.
+// Every variant maps to a fixed sequence of bus cycles, see CPU::execute_cycle.
+pub enum Operation<B: Bus> {
+    Read(fn(&mut CPU<B>, u8)),
+    Write(fn(&mut CPU<B>) -> u8),
+    // Read-modify-write, also used on A in Accumulator mode.
+    Modify(fn(&mut CPU<B>, u8) -> u8),
+    Implied(fn(&mut CPU<B>)),
+    Branch(fn(&mut CPU<B>) -> bool),
+    Push(fn(&mut CPU<B>) -> u8),
+    Pull(fn(&mut CPU<B>, u8)),
+    Jmp,
+    Jsr,
+    Rts,
+    Rti,
+    Brk,
+    Interrupt(Interrupt),
+    // JAM/KIL, the CPU locks up until it is reset.
+    Jam,
+}
+
+// Derived Copy would want B: Copy.
+impl<B: Bus> Clone for Operation<B> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<B: Bus> Copy for Operation<B> {}
+
+#[allow(clippy::upper_case_acronyms)]
+pub struct CPU<B: Bus> {
+    pub bus: B,
+    pub cycle_count: usize,
     pub a: u8,
     pub x: u8,
     pub y: u8,
@@ -44,458 +133,1049 @@ pub struct CPU {
     pub p: u8,
     pub sp: u8,
     pub pc: u16,
+
+    // Instruction in flight
+    opcode: u8,
+    mode: Mode,
+    operation: Operation<B>,
+    step: u8,           // Cycle of the instruction that runs next, 0 => fetch an opcode
+    access_step: u8,    // Cycle the effective address was ready on, 0 => not yet
+    addr: u16,
+    pointer: u8,
+    data: u8,
+    page_crossed: bool,
+    penalty: u8,        // Cycles taken on top of base_cycles (page crossing, branches)
+    pending_interrupt: Option<Interrupt>,
+    vector: u16,
+
+    variant: CpuVariant,
+    // Sees every instruction before it runs when set.
+    pub tracer: Option<Box<dyn Tracer>>,
+    // Sees every instruction boundary when set.
+    pub profiler: Option<Profiler>,
+    opcodes: Box<[OpcodeInfo<B>; 256]>,
+
+    // Interrupt lines, sampled at the end of every cycle
+    irq_lines: u8,      // IrqSource bits
+    nmi_line: bool,
+    irq_detected: bool, // IRQ asserted and I clear on the last poll
+    nmi_detected: bool, // Falling edge seen on NMI, stays set until serviced
+    branch_poll: (bool, bool), // nmi_detected and irq_detected as a taken branch fetched its operand
 }
 
-const CYCLES_LIST :[u8; 256] = [
-        7, 6, 2, 8, 3, 3, 5, 5, 3, 2, 2, 2, 4, 4, 6, 6, //0x00...0x0F
-        2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, //0x10...0x1F
-        6, 6, 2, 8, 3, 3, 5, 5, 4, 2, 2, 2, 4, 4, 6, 6, //0x20...0x2F
-        2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, //0x30...0x3F
-        6, 6, 2, 8, 3, 3, 5, 5, 3, 2, 2, 2, 3, 4, 6, 6, //0x40...0x4F
-        2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, //0x50...0x5F
-        6, 6, 2, 8, 3, 3, 5, 5, 4, 2, 2, 2, 5, 4, 6, 6, //0x60...0x6F
-        2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, //0x70...0x7F
-        2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4, //0x80...0x8F
-        2, 6, 2, 6, 4, 4, 4, 4, 2, 5, 2, 5, 5, 5, 5, 5, //0x90...0x9F
-        2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4, //0xA0...0xAF
-        2, 5, 2, 5, 4, 4, 4, 4, 2, 4, 2, 4, 4, 4, 4, 4, //0xB0...0xBF
-        2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6, //0xC0...0xCF
-        2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, //0xD0...0xDF
-        2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6, //0xE0...0xEF
-        2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7  //0xF0...0xFF
+// Value ORed into A by the unstable XAA/LXA opcodes, it differs between chips.
+const MAGIC_CONSTANT: u8 = 0xEE;
+
+// Everything known about one opcode. OPCODES drives execution, the cycle
+// checks, the trace log and the disassembler.
+pub struct OpcodeInfo<B: Bus> {
+    pub mnemonic: &'static str,
+    pub mode: Mode,
+    pub bytes: u8,
+    pub base_cycles: u8,
+    pub page_cross_penalty: bool,   // +1 cycle when indexing crosses a page, branches also take +1 when taken
+                                    // (without it the fix up cycle is always spent, it is in base_cycles)
+    pub handler: Operation<B>,
+    pub official: bool,
+}
+
+impl<B: Bus> Clone for OpcodeInfo<B> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<B: Bus> Copy for OpcodeInfo<B> {}
+
+const fn official<B: Bus>(mnemonic: &'static str, mode: Mode, base_cycles: u8, handler: Operation<B>) -> OpcodeInfo<B> {
+    OpcodeInfo {
+        mnemonic,
+        mode,
+        bytes: instruction_bytes(mode),
+        base_cycles,
+        // Writes and read-modify-writes always spend the extra cycle, it is in base_cycles.
+        page_cross_penalty: matches!(mode, Mode::Relative)
+            || (matches!(handler, Operation::Read(_)) && matches!(mode, Mode::AbsoluteX | Mode::AbsoluteY | Mode::IndirectY)),
+        handler,
+        official: true,
+    }
+}
+
+const fn unofficial<B: Bus>(mnemonic: &'static str, mode: Mode, base_cycles: u8, handler: Operation<B>) -> OpcodeInfo<B> {
+    let mut info = official(mnemonic, mode, base_cycles, handler);
+    info.official = false;
+    info
+}
+
+const fn instruction_bytes(mode: Mode) -> u8 {
+    match mode {
+        Mode::Implied | Mode::Accumulator => 1,
+        Mode::Absolute | Mode::AbsoluteX | Mode::AbsoluteY | Mode::Indirect | Mode::AbsoluteIndirectX => 3,
+        _ => 2,
+    }
+}
+
+impl<B: Bus> CPU<B> {
+    pub fn opcode_table(variant: CpuVariant) -> [OpcodeInfo<B>; 256] {
+        match variant {
+            CpuVariant::Ricoh2A03 | CpuVariant::Nmos6502 => Self::OPCODES,
+            CpuVariant::Cmos65C02 => Self::OPCODES_65C02,
+        }
+    }
+
+    pub const OPCODES_65C02: [OpcodeInfo<B>; 256] = cmos_opcodes();
+
+    pub const OPCODES: [OpcodeInfo<B>; 256] = [
+        official("BRK", Mode::Implied, 7, Operation::Brk),                      // 0x00
+        official("ORA", Mode::IndirectX, 6, Operation::Read(CPU::ora)),         // 0x01
+        unofficial("JAM", Mode::Implied, 2, Operation::Jam),                    // 0x02
+        unofficial("SLO", Mode::IndirectX, 8, Operation::Modify(CPU::slo)),     // 0x03
+        unofficial("NOP", Mode::ZeroPage, 3, Operation::Read(CPU::nop_read)),   // 0x04
+        official("ORA", Mode::ZeroPage, 3, Operation::Read(CPU::ora)),          // 0x05
+        official("ASL", Mode::ZeroPage, 5, Operation::Modify(CPU::asl)),        // 0x06
+        unofficial("SLO", Mode::ZeroPage, 5, Operation::Modify(CPU::slo)),      // 0x07
+        official("PHP", Mode::Implied, 3, Operation::Push(CPU::php)),           // 0x08
+        official("ORA", Mode::Immediate, 2, Operation::Read(CPU::ora)),         // 0x09
+        official("ASL", Mode::Accumulator, 2, Operation::Modify(CPU::asl)),     // 0x0A
+        unofficial("ANC", Mode::Immediate, 2, Operation::Read(CPU::anc)),       // 0x0B
+        unofficial("NOP", Mode::Absolute, 4, Operation::Read(CPU::nop_read)),   // 0x0C
+        official("ORA", Mode::Absolute, 4, Operation::Read(CPU::ora)),          // 0x0D
+        official("ASL", Mode::Absolute, 6, Operation::Modify(CPU::asl)),        // 0x0E
+        unofficial("SLO", Mode::Absolute, 6, Operation::Modify(CPU::slo)),      // 0x0F
+        official("BPL", Mode::Relative, 2, Operation::Branch(CPU::bpl)),        // 0x10
+        official("ORA", Mode::IndirectY, 5, Operation::Read(CPU::ora)),         // 0x11
+        unofficial("JAM", Mode::Implied, 2, Operation::Jam),                    // 0x12
+        unofficial("SLO", Mode::IndirectY, 8, Operation::Modify(CPU::slo)),     // 0x13
+        unofficial("NOP", Mode::ZeroPageX, 4, Operation::Read(CPU::nop_read)),  // 0x14
+        official("ORA", Mode::ZeroPageX, 4, Operation::Read(CPU::ora)),         // 0x15
+        official("ASL", Mode::ZeroPageX, 6, Operation::Modify(CPU::asl)),       // 0x16
+        unofficial("SLO", Mode::ZeroPageX, 6, Operation::Modify(CPU::slo)),     // 0x17
+        official("CLC", Mode::Implied, 2, Operation::Implied(CPU::clc)),        // 0x18
+        official("ORA", Mode::AbsoluteY, 4, Operation::Read(CPU::ora)),         // 0x19
+        unofficial("NOP", Mode::Implied, 2, Operation::Implied(CPU::nop)),      // 0x1A
+        unofficial("SLO", Mode::AbsoluteY, 7, Operation::Modify(CPU::slo)),     // 0x1B
+        unofficial("NOP", Mode::AbsoluteX, 4, Operation::Read(CPU::nop_read)),  // 0x1C
+        official("ORA", Mode::AbsoluteX, 4, Operation::Read(CPU::ora)),         // 0x1D
+        official("ASL", Mode::AbsoluteX, 7, Operation::Modify(CPU::asl)),       // 0x1E
+        unofficial("SLO", Mode::AbsoluteX, 7, Operation::Modify(CPU::slo)),     // 0x1F
+        official("JSR", Mode::Absolute, 6, Operation::Jsr),                     // 0x20
+        official("AND", Mode::IndirectX, 6, Operation::Read(CPU::and)),         // 0x21
+        unofficial("JAM", Mode::Implied, 2, Operation::Jam),                    // 0x22
+        unofficial("RLA", Mode::IndirectX, 8, Operation::Modify(CPU::rla)),     // 0x23
+        official("BIT", Mode::ZeroPage, 3, Operation::Read(CPU::bit)),          // 0x24
+        official("AND", Mode::ZeroPage, 3, Operation::Read(CPU::and)),          // 0x25
+        official("ROL", Mode::ZeroPage, 5, Operation::Modify(CPU::rol)),        // 0x26
+        unofficial("RLA", Mode::ZeroPage, 5, Operation::Modify(CPU::rla)),      // 0x27
+        official("PLP", Mode::Implied, 4, Operation::Pull(CPU::plp)),           // 0x28
+        official("AND", Mode::Immediate, 2, Operation::Read(CPU::and)),         // 0x29
+        official("ROL", Mode::Accumulator, 2, Operation::Modify(CPU::rol)),     // 0x2A
+        unofficial("ANC", Mode::Immediate, 2, Operation::Read(CPU::anc)),       // 0x2B
+        official("BIT", Mode::Absolute, 4, Operation::Read(CPU::bit)),          // 0x2C
+        official("AND", Mode::Absolute, 4, Operation::Read(CPU::and)),          // 0x2D
+        official("ROL", Mode::Absolute, 6, Operation::Modify(CPU::rol)),        // 0x2E
+        unofficial("RLA", Mode::Absolute, 6, Operation::Modify(CPU::rla)),      // 0x2F
+        official("BMI", Mode::Relative, 2, Operation::Branch(CPU::bmi)),        // 0x30
+        official("AND", Mode::IndirectY, 5, Operation::Read(CPU::and)),         // 0x31
+        unofficial("JAM", Mode::Implied, 2, Operation::Jam),                    // 0x32
+        unofficial("RLA", Mode::IndirectY, 8, Operation::Modify(CPU::rla)),     // 0x33
+        unofficial("NOP", Mode::ZeroPageX, 4, Operation::Read(CPU::nop_read)),  // 0x34
+        official("AND", Mode::ZeroPageX, 4, Operation::Read(CPU::and)),         // 0x35
+        official("ROL", Mode::ZeroPageX, 6, Operation::Modify(CPU::rol)),       // 0x36
+        unofficial("RLA", Mode::ZeroPageX, 6, Operation::Modify(CPU::rla)),     // 0x37
+        official("SEC", Mode::Implied, 2, Operation::Implied(CPU::sec)),        // 0x38
+        official("AND", Mode::AbsoluteY, 4, Operation::Read(CPU::and)),         // 0x39
+        unofficial("NOP", Mode::Implied, 2, Operation::Implied(CPU::nop)),      // 0x3A
+        unofficial("RLA", Mode::AbsoluteY, 7, Operation::Modify(CPU::rla)),     // 0x3B
+        unofficial("NOP", Mode::AbsoluteX, 4, Operation::Read(CPU::nop_read)),  // 0x3C
+        official("AND", Mode::AbsoluteX, 4, Operation::Read(CPU::and)),         // 0x3D
+        official("ROL", Mode::AbsoluteX, 7, Operation::Modify(CPU::rol)),       // 0x3E
+        unofficial("RLA", Mode::AbsoluteX, 7, Operation::Modify(CPU::rla)),     // 0x3F
+        official("RTI", Mode::Implied, 6, Operation::Rti),                      // 0x40
+        official("EOR", Mode::IndirectX, 6, Operation::Read(CPU::eor)),         // 0x41
+        unofficial("JAM", Mode::Implied, 2, Operation::Jam),                    // 0x42
+        unofficial("SRE", Mode::IndirectX, 8, Operation::Modify(CPU::sre)),     // 0x43
+        unofficial("NOP", Mode::ZeroPage, 3, Operation::Read(CPU::nop_read)),   // 0x44
+        official("EOR", Mode::ZeroPage, 3, Operation::Read(CPU::eor)),          // 0x45
+        official("LSR", Mode::ZeroPage, 5, Operation::Modify(CPU::lsr)),        // 0x46
+        unofficial("SRE", Mode::ZeroPage, 5, Operation::Modify(CPU::sre)),      // 0x47
+        official("PHA", Mode::Implied, 3, Operation::Push(CPU::pha)),           // 0x48
+        official("EOR", Mode::Immediate, 2, Operation::Read(CPU::eor)),         // 0x49
+        official("LSR", Mode::Accumulator, 2, Operation::Modify(CPU::lsr)),     // 0x4A
+        unofficial("ALR", Mode::Immediate, 2, Operation::Read(CPU::alr)),       // 0x4B
+        official("JMP", Mode::Absolute, 3, Operation::Jmp),                     // 0x4C
+        official("EOR", Mode::Absolute, 4, Operation::Read(CPU::eor)),          // 0x4D
+        official("LSR", Mode::Absolute, 6, Operation::Modify(CPU::lsr)),        // 0x4E
+        unofficial("SRE", Mode::Absolute, 6, Operation::Modify(CPU::sre)),      // 0x4F
+        official("BVC", Mode::Relative, 2, Operation::Branch(CPU::bvc)),        // 0x50
+        official("EOR", Mode::IndirectY, 5, Operation::Read(CPU::eor)),         // 0x51
+        unofficial("JAM", Mode::Implied, 2, Operation::Jam),                    // 0x52
+        unofficial("SRE", Mode::IndirectY, 8, Operation::Modify(CPU::sre)),     // 0x53
+        unofficial("NOP", Mode::ZeroPageX, 4, Operation::Read(CPU::nop_read)),  // 0x54
+        official("EOR", Mode::ZeroPageX, 4, Operation::Read(CPU::eor)),         // 0x55
+        official("LSR", Mode::ZeroPageX, 6, Operation::Modify(CPU::lsr)),       // 0x56
+        unofficial("SRE", Mode::ZeroPageX, 6, Operation::Modify(CPU::sre)),     // 0x57
+        official("CLI", Mode::Implied, 2, Operation::Implied(CPU::cli)),        // 0x58
+        official("EOR", Mode::AbsoluteY, 4, Operation::Read(CPU::eor)),         // 0x59
+        unofficial("NOP", Mode::Implied, 2, Operation::Implied(CPU::nop)),      // 0x5A
+        unofficial("SRE", Mode::AbsoluteY, 7, Operation::Modify(CPU::sre)),     // 0x5B
+        unofficial("NOP", Mode::AbsoluteX, 4, Operation::Read(CPU::nop_read)),  // 0x5C
+        official("EOR", Mode::AbsoluteX, 4, Operation::Read(CPU::eor)),         // 0x5D
+        official("LSR", Mode::AbsoluteX, 7, Operation::Modify(CPU::lsr)),       // 0x5E
+        unofficial("SRE", Mode::AbsoluteX, 7, Operation::Modify(CPU::sre)),     // 0x5F
+        official("RTS", Mode::Implied, 6, Operation::Rts),                      // 0x60
+        official("ADC", Mode::IndirectX, 6, Operation::Read(CPU::adc)),         // 0x61
+        unofficial("JAM", Mode::Implied, 2, Operation::Jam),                    // 0x62
+        unofficial("RRA", Mode::IndirectX, 8, Operation::Modify(CPU::rra)),     // 0x63
+        unofficial("NOP", Mode::ZeroPage, 3, Operation::Read(CPU::nop_read)),   // 0x64
+        official("ADC", Mode::ZeroPage, 3, Operation::Read(CPU::adc)),          // 0x65
+        official("ROR", Mode::ZeroPage, 5, Operation::Modify(CPU::ror)),        // 0x66
+        unofficial("RRA", Mode::ZeroPage, 5, Operation::Modify(CPU::rra)),      // 0x67
+        official("PLA", Mode::Implied, 4, Operation::Pull(CPU::pla)),           // 0x68
+        official("ADC", Mode::Immediate, 2, Operation::Read(CPU::adc)),         // 0x69
+        official("ROR", Mode::Accumulator, 2, Operation::Modify(CPU::ror)),     // 0x6A
+        unofficial("ARR", Mode::Immediate, 2, Operation::Read(CPU::arr)),       // 0x6B
+        official("JMP", Mode::Indirect, 5, Operation::Jmp),                     // 0x6C
+        official("ADC", Mode::Absolute, 4, Operation::Read(CPU::adc)),          // 0x6D
+        official("ROR", Mode::Absolute, 6, Operation::Modify(CPU::ror)),        // 0x6E
+        unofficial("RRA", Mode::Absolute, 6, Operation::Modify(CPU::rra)),      // 0x6F
+        official("BVS", Mode::Relative, 2, Operation::Branch(CPU::bvs)),        // 0x70
+        official("ADC", Mode::IndirectY, 5, Operation::Read(CPU::adc)),         // 0x71
+        unofficial("JAM", Mode::Implied, 2, Operation::Jam),                    // 0x72
+        unofficial("RRA", Mode::IndirectY, 8, Operation::Modify(CPU::rra)),     // 0x73
+        unofficial("NOP", Mode::ZeroPageX, 4, Operation::Read(CPU::nop_read)),  // 0x74
+        official("ADC", Mode::ZeroPageX, 4, Operation::Read(CPU::adc)),         // 0x75
+        official("ROR", Mode::ZeroPageX, 6, Operation::Modify(CPU::ror)),       // 0x76
+        unofficial("RRA", Mode::ZeroPageX, 6, Operation::Modify(CPU::rra)),     // 0x77
+        official("SEI", Mode::Implied, 2, Operation::Implied(CPU::sei)),        // 0x78
+        official("ADC", Mode::AbsoluteY, 4, Operation::Read(CPU::adc)),         // 0x79
+        unofficial("NOP", Mode::Implied, 2, Operation::Implied(CPU::nop)),      // 0x7A
+        unofficial("RRA", Mode::AbsoluteY, 7, Operation::Modify(CPU::rra)),     // 0x7B
+        unofficial("NOP", Mode::AbsoluteX, 4, Operation::Read(CPU::nop_read)),  // 0x7C
+        official("ADC", Mode::AbsoluteX, 4, Operation::Read(CPU::adc)),         // 0x7D
+        official("ROR", Mode::AbsoluteX, 7, Operation::Modify(CPU::ror)),       // 0x7E
+        unofficial("RRA", Mode::AbsoluteX, 7, Operation::Modify(CPU::rra)),     // 0x7F
+        unofficial("NOP", Mode::Immediate, 2, Operation::Read(CPU::nop_read)),  // 0x80
+        official("STA", Mode::IndirectX, 6, Operation::Write(CPU::sta)),        // 0x81
+        unofficial("NOP", Mode::Immediate, 2, Operation::Read(CPU::nop_read)),  // 0x82
+        unofficial("SAX", Mode::IndirectX, 6, Operation::Write(CPU::sax)),      // 0x83
+        official("STY", Mode::ZeroPage, 3, Operation::Write(CPU::sty)),         // 0x84
+        official("STA", Mode::ZeroPage, 3, Operation::Write(CPU::sta)),         // 0x85
+        official("STX", Mode::ZeroPage, 3, Operation::Write(CPU::stx)),         // 0x86
+        unofficial("SAX", Mode::ZeroPage, 3, Operation::Write(CPU::sax)),       // 0x87
+        official("DEY", Mode::Implied, 2, Operation::Implied(CPU::dey)),        // 0x88
+        unofficial("NOP", Mode::Immediate, 2, Operation::Read(CPU::nop_read)),  // 0x89
+        official("TXA", Mode::Implied, 2, Operation::Implied(CPU::txa)),        // 0x8A
+        unofficial("XAA", Mode::Immediate, 2, Operation::Read(CPU::xaa)),       // 0x8B
+        official("STY", Mode::Absolute, 4, Operation::Write(CPU::sty)),         // 0x8C
+        official("STA", Mode::Absolute, 4, Operation::Write(CPU::sta)),         // 0x8D
+        official("STX", Mode::Absolute, 4, Operation::Write(CPU::stx)),         // 0x8E
+        unofficial("SAX", Mode::Absolute, 4, Operation::Write(CPU::sax)),       // 0x8F
+        official("BCC", Mode::Relative, 2, Operation::Branch(CPU::bcc)),        // 0x90
+        official("STA", Mode::IndirectY, 6, Operation::Write(CPU::sta)),        // 0x91
+        unofficial("JAM", Mode::Implied, 2, Operation::Jam),                    // 0x92
+        unofficial("SHA", Mode::IndirectY, 6, Operation::Write(CPU::sha)),      // 0x93
+        official("STY", Mode::ZeroPageX, 4, Operation::Write(CPU::sty)),        // 0x94
+        official("STA", Mode::ZeroPageX, 4, Operation::Write(CPU::sta)),        // 0x95
+        official("STX", Mode::ZeroPageY, 4, Operation::Write(CPU::stx)),        // 0x96
+        unofficial("SAX", Mode::ZeroPageY, 4, Operation::Write(CPU::sax)),      // 0x97
+        official("TYA", Mode::Implied, 2, Operation::Implied(CPU::tya)),        // 0x98
+        official("STA", Mode::AbsoluteY, 5, Operation::Write(CPU::sta)),        // 0x99
+        official("TXS", Mode::Implied, 2, Operation::Implied(CPU::txs)),        // 0x9A
+        unofficial("TAS", Mode::AbsoluteY, 5, Operation::Write(CPU::tas)),      // 0x9B
+        unofficial("SHY", Mode::AbsoluteX, 5, Operation::Write(CPU::shy)),      // 0x9C
+        official("STA", Mode::AbsoluteX, 5, Operation::Write(CPU::sta)),        // 0x9D
+        unofficial("SHX", Mode::AbsoluteY, 5, Operation::Write(CPU::shx)),      // 0x9E
+        unofficial("SHA", Mode::AbsoluteY, 5, Operation::Write(CPU::sha)),      // 0x9F
+        official("LDY", Mode::Immediate, 2, Operation::Read(CPU::ldy)),         // 0xA0
+        official("LDA", Mode::IndirectX, 6, Operation::Read(CPU::lda)),         // 0xA1
+        official("LDX", Mode::Immediate, 2, Operation::Read(CPU::ldx)),         // 0xA2
+        unofficial("LAX", Mode::IndirectX, 6, Operation::Read(CPU::lax)),       // 0xA3
+        official("LDY", Mode::ZeroPage, 3, Operation::Read(CPU::ldy)),          // 0xA4
+        official("LDA", Mode::ZeroPage, 3, Operation::Read(CPU::lda)),          // 0xA5
+        official("LDX", Mode::ZeroPage, 3, Operation::Read(CPU::ldx)),          // 0xA6
+        unofficial("LAX", Mode::ZeroPage, 3, Operation::Read(CPU::lax)),        // 0xA7
+        official("TAY", Mode::Implied, 2, Operation::Implied(CPU::tay)),        // 0xA8
+        official("LDA", Mode::Immediate, 2, Operation::Read(CPU::lda)),         // 0xA9
+        official("TAX", Mode::Implied, 2, Operation::Implied(CPU::tax)),        // 0xAA
+        unofficial("LXA", Mode::Immediate, 2, Operation::Read(CPU::lxa)),       // 0xAB
+        official("LDY", Mode::Absolute, 4, Operation::Read(CPU::ldy)),          // 0xAC
+        official("LDA", Mode::Absolute, 4, Operation::Read(CPU::lda)),          // 0xAD
+        official("LDX", Mode::Absolute, 4, Operation::Read(CPU::ldx)),          // 0xAE
+        unofficial("LAX", Mode::Absolute, 4, Operation::Read(CPU::lax)),        // 0xAF
+        official("BCS", Mode::Relative, 2, Operation::Branch(CPU::bcs)),        // 0xB0
+        official("LDA", Mode::IndirectY, 5, Operation::Read(CPU::lda)),         // 0xB1
+        unofficial("JAM", Mode::Implied, 2, Operation::Jam),                    // 0xB2
+        unofficial("LAX", Mode::IndirectY, 5, Operation::Read(CPU::lax)),       // 0xB3
+        official("LDY", Mode::ZeroPageX, 4, Operation::Read(CPU::ldy)),         // 0xB4
+        official("LDA", Mode::ZeroPageX, 4, Operation::Read(CPU::lda)),         // 0xB5
+        official("LDX", Mode::ZeroPageY, 4, Operation::Read(CPU::ldx)),         // 0xB6
+        unofficial("LAX", Mode::ZeroPageY, 4, Operation::Read(CPU::lax)),       // 0xB7
+        official("CLV", Mode::Implied, 2, Operation::Implied(CPU::clv)),        // 0xB8
+        official("LDA", Mode::AbsoluteY, 4, Operation::Read(CPU::lda)),         // 0xB9
+        official("TSX", Mode::Implied, 2, Operation::Implied(CPU::tsx)),        // 0xBA
+        unofficial("LAS", Mode::AbsoluteY, 4, Operation::Read(CPU::las)),       // 0xBB
+        official("LDY", Mode::AbsoluteX, 4, Operation::Read(CPU::ldy)),         // 0xBC
+        official("LDA", Mode::AbsoluteX, 4, Operation::Read(CPU::lda)),         // 0xBD
+        official("LDX", Mode::AbsoluteY, 4, Operation::Read(CPU::ldx)),         // 0xBE
+        unofficial("LAX", Mode::AbsoluteY, 4, Operation::Read(CPU::lax)),       // 0xBF
+        official("CPY", Mode::Immediate, 2, Operation::Read(CPU::cpy)),         // 0xC0
+        official("CMP", Mode::IndirectX, 6, Operation::Read(CPU::cmp)),         // 0xC1
+        unofficial("NOP", Mode::Immediate, 2, Operation::Read(CPU::nop_read)),  // 0xC2
+        unofficial("DCP", Mode::IndirectX, 8, Operation::Modify(CPU::dcp)),     // 0xC3
+        official("CPY", Mode::ZeroPage, 3, Operation::Read(CPU::cpy)),          // 0xC4
+        official("CMP", Mode::ZeroPage, 3, Operation::Read(CPU::cmp)),          // 0xC5
+        official("DEC", Mode::ZeroPage, 5, Operation::Modify(CPU::dec)),        // 0xC6
+        unofficial("DCP", Mode::ZeroPage, 5, Operation::Modify(CPU::dcp)),      // 0xC7
+        official("INY", Mode::Implied, 2, Operation::Implied(CPU::iny)),        // 0xC8
+        official("CMP", Mode::Immediate, 2, Operation::Read(CPU::cmp)),         // 0xC9
+        official("DEX", Mode::Implied, 2, Operation::Implied(CPU::dex)),        // 0xCA
+        unofficial("AXS", Mode::Immediate, 2, Operation::Read(CPU::axs)),       // 0xCB
+        official("CPY", Mode::Absolute, 4, Operation::Read(CPU::cpy)),          // 0xCC
+        official("CMP", Mode::Absolute, 4, Operation::Read(CPU::cmp)),          // 0xCD
+        official("DEC", Mode::Absolute, 6, Operation::Modify(CPU::dec)),        // 0xCE
+        unofficial("DCP", Mode::Absolute, 6, Operation::Modify(CPU::dcp)),      // 0xCF
+        official("BNE", Mode::Relative, 2, Operation::Branch(CPU::bne)),        // 0xD0
+        official("CMP", Mode::IndirectY, 5, Operation::Read(CPU::cmp)),         // 0xD1
+        unofficial("JAM", Mode::Implied, 2, Operation::Jam),                    // 0xD2
+        unofficial("DCP", Mode::IndirectY, 8, Operation::Modify(CPU::dcp)),     // 0xD3
+        unofficial("NOP", Mode::ZeroPageX, 4, Operation::Read(CPU::nop_read)),  // 0xD4
+        official("CMP", Mode::ZeroPageX, 4, Operation::Read(CPU::cmp)),         // 0xD5
+        official("DEC", Mode::ZeroPageX, 6, Operation::Modify(CPU::dec)),       // 0xD6
+        unofficial("DCP", Mode::ZeroPageX, 6, Operation::Modify(CPU::dcp)),     // 0xD7
+        official("CLD", Mode::Implied, 2, Operation::Implied(CPU::cld)),        // 0xD8
+        official("CMP", Mode::AbsoluteY, 4, Operation::Read(CPU::cmp)),         // 0xD9
+        unofficial("NOP", Mode::Implied, 2, Operation::Implied(CPU::nop)),      // 0xDA
+        unofficial("DCP", Mode::AbsoluteY, 7, Operation::Modify(CPU::dcp)),     // 0xDB
+        unofficial("NOP", Mode::AbsoluteX, 4, Operation::Read(CPU::nop_read)),  // 0xDC
+        official("CMP", Mode::AbsoluteX, 4, Operation::Read(CPU::cmp)),         // 0xDD
+        official("DEC", Mode::AbsoluteX, 7, Operation::Modify(CPU::dec)),       // 0xDE
+        unofficial("DCP", Mode::AbsoluteX, 7, Operation::Modify(CPU::dcp)),     // 0xDF
+        official("CPX", Mode::Immediate, 2, Operation::Read(CPU::cpx)),         // 0xE0
+        official("SBC", Mode::IndirectX, 6, Operation::Read(CPU::sbc)),         // 0xE1
+        unofficial("NOP", Mode::Immediate, 2, Operation::Read(CPU::nop_read)),  // 0xE2
+        unofficial("ISC", Mode::IndirectX, 8, Operation::Modify(CPU::isc)),     // 0xE3
+        official("CPX", Mode::ZeroPage, 3, Operation::Read(CPU::cpx)),          // 0xE4
+        official("SBC", Mode::ZeroPage, 3, Operation::Read(CPU::sbc)),          // 0xE5
+        official("INC", Mode::ZeroPage, 5, Operation::Modify(CPU::inc)),        // 0xE6
+        unofficial("ISC", Mode::ZeroPage, 5, Operation::Modify(CPU::isc)),      // 0xE7
+        official("INX", Mode::Implied, 2, Operation::Implied(CPU::inx)),        // 0xE8
+        official("SBC", Mode::Immediate, 2, Operation::Read(CPU::sbc)),         // 0xE9
+        official("NOP", Mode::Implied, 2, Operation::Implied(CPU::nop)),        // 0xEA
+        unofficial("SBC", Mode::Immediate, 2, Operation::Read(CPU::sbc)),       // 0xEB
+        official("CPX", Mode::Absolute, 4, Operation::Read(CPU::cpx)),          // 0xEC
+        official("SBC", Mode::Absolute, 4, Operation::Read(CPU::sbc)),          // 0xED
+        official("INC", Mode::Absolute, 6, Operation::Modify(CPU::inc)),        // 0xEE
+        unofficial("ISC", Mode::Absolute, 6, Operation::Modify(CPU::isc)),      // 0xEF
+        official("BEQ", Mode::Relative, 2, Operation::Branch(CPU::beq)),        // 0xF0
+        official("SBC", Mode::IndirectY, 5, Operation::Read(CPU::sbc)),         // 0xF1
+        unofficial("JAM", Mode::Implied, 2, Operation::Jam),                    // 0xF2
+        unofficial("ISC", Mode::IndirectY, 8, Operation::Modify(CPU::isc)),     // 0xF3
+        unofficial("NOP", Mode::ZeroPageX, 4, Operation::Read(CPU::nop_read)),  // 0xF4
+        official("SBC", Mode::ZeroPageX, 4, Operation::Read(CPU::sbc)),         // 0xF5
+        official("INC", Mode::ZeroPageX, 6, Operation::Modify(CPU::inc)),       // 0xF6
+        unofficial("ISC", Mode::ZeroPageX, 6, Operation::Modify(CPU::isc)),     // 0xF7
+        official("SED", Mode::Implied, 2, Operation::Implied(CPU::sed)),        // 0xF8
+        official("SBC", Mode::AbsoluteY, 4, Operation::Read(CPU::sbc)),         // 0xF9
+        unofficial("NOP", Mode::Implied, 2, Operation::Implied(CPU::nop)),      // 0xFA
+        unofficial("ISC", Mode::AbsoluteY, 7, Operation::Modify(CPU::isc)),     // 0xFB
+        unofficial("NOP", Mode::AbsoluteX, 4, Operation::Read(CPU::nop_read)),  // 0xFC
+        official("SBC", Mode::AbsoluteX, 4, Operation::Read(CPU::sbc)),         // 0xFD
+        official("INC", Mode::AbsoluteX, 7, Operation::Modify(CPU::inc)),       // 0xFE
+        unofficial("ISC", Mode::AbsoluteX, 7, Operation::Modify(CPU::isc)),     // 0xFF
     ];
+}
+
+// The 65C02 turned every undefined opcode into a NOP, fixed JMP ($xxFF) and
+// added a handful of instructions and the (zp) addressing mode.
+const fn cmos_opcodes<B: Bus>() -> [OpcodeInfo<B>; 256] {
+    let mut table = CPU::<B>::OPCODES;
+    let mut opcode = 0;
+    while opcode < 256 {
+        if !table[opcode].official {
+            table[opcode] = unofficial("NOP", Mode::Implied, 1, Operation::Implied(CPU::nop));
+        }
+        opcode += 1;
+    }
+    let nops = [0x02, 0x22, 0x42, 0x62, 0x82, 0xC2, 0xE2];
+    let mut i = 0;
+    while i < nops.len() {
+        table[nops[i]] = unofficial("NOP", Mode::Immediate, 2, Operation::Read(CPU::nop_read));
+        i += 1;
+    }
+    table[0x44] = unofficial("NOP", Mode::ZeroPage, 3, Operation::Read(CPU::nop_read));
+    table[0x54] = unofficial("NOP", Mode::ZeroPageX, 4, Operation::Read(CPU::nop_read));
+    table[0xD4] = unofficial("NOP", Mode::ZeroPageX, 4, Operation::Read(CPU::nop_read));
+    table[0xF4] = unofficial("NOP", Mode::ZeroPageX, 4, Operation::Read(CPU::nop_read));
+    table[0x5C] = unofficial("NOP", Mode::Absolute, 8, Operation::Read(CPU::nop_read));
+    table[0xDC] = unofficial("NOP", Mode::Absolute, 4, Operation::Read(CPU::nop_read));
+    table[0xFC] = unofficial("NOP", Mode::Absolute, 4, Operation::Read(CPU::nop_read));
+
+    table[0x12] = official("ORA", Mode::ZeroPageIndirect, 5, Operation::Read(CPU::ora));
+    table[0x32] = official("AND", Mode::ZeroPageIndirect, 5, Operation::Read(CPU::and));
+    table[0x52] = official("EOR", Mode::ZeroPageIndirect, 5, Operation::Read(CPU::eor));
+    table[0x72] = official("ADC", Mode::ZeroPageIndirect, 5, Operation::Read(CPU::adc));
+    table[0x92] = official("STA", Mode::ZeroPageIndirect, 5, Operation::Write(CPU::sta));
+    table[0xB2] = official("LDA", Mode::ZeroPageIndirect, 5, Operation::Read(CPU::lda));
+    table[0xD2] = official("CMP", Mode::ZeroPageIndirect, 5, Operation::Read(CPU::cmp));
+    table[0xF2] = official("SBC", Mode::ZeroPageIndirect, 5, Operation::Read(CPU::sbc));
+
+    table[0x04] = official("TSB", Mode::ZeroPage, 5, Operation::Modify(CPU::tsb));
+    table[0x0C] = official("TSB", Mode::Absolute, 6, Operation::Modify(CPU::tsb));
+    table[0x14] = official("TRB", Mode::ZeroPage, 5, Operation::Modify(CPU::trb));
+    table[0x1C] = official("TRB", Mode::Absolute, 6, Operation::Modify(CPU::trb));
+    table[0x1A] = official("INC", Mode::Accumulator, 2, Operation::Modify(CPU::inc));
+    table[0x3A] = official("DEC", Mode::Accumulator, 2, Operation::Modify(CPU::dec));
+    table[0x34] = official("BIT", Mode::ZeroPageX, 4, Operation::Read(CPU::bit));
+    table[0x3C] = official("BIT", Mode::AbsoluteX, 4, Operation::Read(CPU::bit));
+    table[0x89] = official("BIT", Mode::Immediate, 2, Operation::Read(CPU::bit_immediate));
+    table[0x5A] = official("PHY", Mode::Implied, 3, Operation::Push(CPU::phy));
+    table[0x7A] = official("PLY", Mode::Implied, 4, Operation::Pull(CPU::ply));
+    table[0xDA] = official("PHX", Mode::Implied, 3, Operation::Push(CPU::phx));
+    table[0xFA] = official("PLX", Mode::Implied, 4, Operation::Pull(CPU::plx));
+    table[0x64] = official("STZ", Mode::ZeroPage, 3, Operation::Write(CPU::stz));
+    table[0x74] = official("STZ", Mode::ZeroPageX, 4, Operation::Write(CPU::stz));
+    table[0x9C] = official("STZ", Mode::Absolute, 4, Operation::Write(CPU::stz));
+    table[0x9E] = official("STZ", Mode::AbsoluteX, 5, Operation::Write(CPU::stz));
+    table[0x80] = official("BRA", Mode::Relative, 2, Operation::Branch(CPU::bra));
+    table[0x6C] = official("JMP", Mode::Indirect, 6, Operation::Jmp);
+    table[0x7C] = official("JMP", Mode::AbsoluteIndirectX, 6, Operation::Jmp);
+
+    // Shifts and rotates on abs,X skip the fix up cycle when no page is crossed.
+    let shifts = [0x1E, 0x3E, 0x5E, 0x7E];
+    let mut i = 0;
+    while i < shifts.len() {
+        table[shifts[i]].base_cycles = 6;
+        table[shifts[i]].page_cross_penalty = true;
+        i += 1;
+    }
+    table
+}
 
     #[allow(dead_code)]
-impl CPU {
-    pub fn new(bus: BUS) -> Self {
+impl<B: Bus> CPU<B> {
+    pub fn new(bus: B) -> Self {
+        CPU::with_variant(bus, CpuVariant::Ricoh2A03)
+    }
+
+    // Registers are left as power_on and the reset sequence would leave them,
+    // without running it, so code can be started from any pc.
+    pub fn with_variant(bus: B, variant: CpuVariant) -> Self {
         CPU {
             bus,
-            cycles: 0,
             cycle_count: 7,
             a: 0,
             x: 0,
             y: 0,
 
-            p: 0b00100000,
+            p: 0b00100100,
             sp: 0xFD,
             pc: 0,
+
+            opcode: 0,
+            mode: Mode::Implied,
+            operation: Operation::Implied(CPU::nop),
+            step: 0,
+            access_step: 0,
+            addr: 0,
+            pointer: 0,
+            data: 0,
+            page_crossed: false,
+            penalty: 0,
+            pending_interrupt: None,
+            vector: 0,
+
+            variant,
+            tracer: None,
+            profiler: None,
+            opcodes: Box::new(CPU::opcode_table(variant)),
+
+            irq_lines: 0,
+            nmi_line: false,
+            irq_detected: false,
+            nmi_detected: false,
+            branch_poll: (false, false),
         }
     }
 
-    pub fn clock(&mut self, debug: bool) {
-        if self.cycles == 0 {
-            let opcode = self.read(self.pc);
-            self.cycles = CYCLES_LIST[opcode as usize];
-            let op_1 = self.read(self.pc+1);
-            let op_2 = self.read(self.pc+2);
-
-            if debug {log_cpu(self.pc, opcode,op_1,op_2,self.a,self.x,self.y,self.p,self.sp,self.cycle_count)};
+    // One CPU cycle, doing exactly the one bus access the 6502 does on it.
+    pub fn clock(&mut self) {
+        if self.step == 0 {
+            if let Some(profiler) = self.profiler.as_mut() {
+                let flow = match self.operation {
+                    Operation::Jsr => Flow::Call,
+                    Operation::Rts | Operation::Rti => Flow::Return,
+                    Operation::Brk => Flow::Interrupt(Interrupt::Break),
+                    Operation::Interrupt(interrupt) => Flow::Interrupt(interrupt),
+                    _ => Flow::Next,
+                };
+                profiler.step(&self.bus, flow, self.pc, self.sp, self.cycle_count);
+            }
+            if let Some(interrupt) = self.pending_interrupt.take() {
+                // The opcode fetch is thrown away and the interrupt sequence runs instead.
+                self.read(self.pc);
+                self.operation = Operation::Interrupt(interrupt);
+                self.mode = Mode::Implied;
+            } else {
+                if let Some(mut tracer) = self.tracer.take() {
+                    if tracer.wants(self.pc, self.cycle_count) {
+                        let entry = TraceEntry::capture(self, tracer.symbols());
+                        tracer.trace(&entry);
+                    }
+                    self.tracer = Some(tracer);
+                }
 
+                let pc = self.pc;
+                self.bus.execute(pc);
+                self.opcode = self.read(pc);
+                self.increment_pc();
+                let info = &self.opcodes[self.opcode as usize];
+                self.mode = info.mode;
+                self.operation = info.handler;
+                for i in 0..info.bytes as u16 {
+                    self.bus.code_data(pc.wrapping_add(i), cdl::CODE);
+                }
+            }
             self.set_unused(); //Always true
-            self.pc += 1;
-            match opcode {
-                0x00 => self.brk(),
-                0x01 => self.ora(Mode::IndirectX),
-                0x05 => self.ora(Mode::ZeroPage),
-                0x06 => self.asl(Mode::ZeroPage),
-                0x08 => self.php(Mode::Implied),
-                0x09 => self.ora(Mode::Immediate),
-                0x0a => self.asl_a(),
-                0x0d => self.ora(Mode::Absolute),
-                0x0e => self.asl(Mode::Absolute),
-                0x10 => self.bpl(Mode::Relative),
-                0x11 => self.ora(Mode::IndirectY),
-                0x15 => self.ora(Mode::ZeroPageX),
-                0x16 => self.asl(Mode::ZeroPageX),
-                0x18 => self.clc(Mode::Implied),
-                0x19 => self.ora(Mode::AbsoluteY),
-                0x1d => self.ora(Mode::AbsoluteX),
-                0x1e => self.asl(Mode::AbsoluteX),
-                0x20 => self.jsr(Mode::Absolute),
-                0x21 => self.and(Mode::IndirectX),
-                0x24 => self.bit(Mode::ZeroPage),
-                0x25 => self.and(Mode::ZeroPage),
-                0x26 => self.rol(Mode::ZeroPage),
-                0x28 => self.plp(Mode::Implied),
-                0x29 => self.and(Mode::Immediate),
-                0x2a => self.rol_a(),
-                0x2c => self.bit(Mode::Absolute),
-                0x2d => self.and(Mode::Absolute),
-                0x2e => self.rol(Mode::Absolute),
-                0x30 => self.bmi(Mode::Relative),
-                0x31 => self.and(Mode::IndirectY),
-                0x35 => self.and(Mode::ZeroPageX),
-                0x36 => self.rol(Mode::ZeroPageX),
-                0x38 => self.sec(Mode::Implied),
-                0x39 => self.and(Mode::AbsoluteY),
-                0x3d => self.and(Mode::AbsoluteX),
-                0x3e => self.rol(Mode::AbsoluteX),
-                0x40 => self.rti(Mode::Implied),
-                0x41 => self.eor(Mode::IndirectX),
-                0x45 => self.eor(Mode::ZeroPage),
-                0x46 => self.lsr(Mode::ZeroPage),
-                0x48 => self.pha(Mode::Implied),
-                0x49 => self.eor(Mode::Immediate),
-                0x4a => self.lsr_a(),
-                0x4c => self.jmp(Mode::Absolute),
-                0x4d => self.eor(Mode::Absolute),
-                0x4e => self.lsr(Mode::Absolute),
-                0x50 => self.bvc(Mode::Relative),
-                0x51 => self.eor(Mode::IndirectY),
-                0x55 => self.eor(Mode::ZeroPageX),
-                0x56 => self.lsr(Mode::ZeroPageX),
-                0x58 => self.cli(Mode::Implied),
-                0x59 => self.eor(Mode::AbsoluteY),
-                0x5d => self.eor(Mode::AbsoluteX),
-                0x5e => self.lsr(Mode::AbsoluteX),
-                0x60 => self.rts(Mode::Implied),
-                0x61 => self.adc(Mode::IndirectX),
-                0x65 => self.adc(Mode::ZeroPage),
-                0x66 => self.ror(Mode::ZeroPage),
-                0x68 => self.pla(Mode::Implied),
-                0x69 => self.adc(Mode::Immediate),
-                0x6a => self.ror_a(),
-                0x6c => self.jmp(Mode::Indirect),
-                0x6d => self.adc(Mode::Absolute),
-                0x6e => self.ror(Mode::Absolute),
-                0x70 => self.bvs(Mode::Relative),
-                0x71 => self.adc(Mode::IndirectY),
-                0x75 => self.adc(Mode::ZeroPageX),
-                0x76 => self.ror(Mode::ZeroPageX),
-                0x78 => self.sei(Mode::Implied),
-                0x79 => self.adc(Mode::AbsoluteY),
-                0x7d => self.adc(Mode::AbsoluteX),
-                0x7e => self.ror(Mode::AbsoluteX),
-                0x81 => self.sta(Mode::IndirectX),
-                0x84 => self.sty(Mode::ZeroPage),
-                0x85 => self.sta(Mode::ZeroPage),
-                0x86 => self.stx(Mode::ZeroPage),
-                0x88 => self.dey(Mode::Implied),
-                0x8a => self.txa(Mode::Implied),
-                0x8c => self.sty(Mode::Absolute),
-                0x8d => self.sta(Mode::Absolute),
-                0x8e => self.stx(Mode::Absolute),
-                0x90 => self.bcc(Mode::Relative),
-                0x91 => self.sta(Mode::IndirectY),
-                0x94 => self.sty(Mode::ZeroPageX),
-                0x95 => self.sta(Mode::ZeroPageX),
-                0x96 => self.stx(Mode::ZeroPageY),
-                0x98 => self.tya(Mode::Implied),
-                0x99 => self.sta(Mode::AbsoluteY),
-                0x9a => self.txs(Mode::Implied),
-                0x9d => self.sta(Mode::AbsoluteX),
-                0xa0 => self.ldy(Mode::Immediate),
-                0xa1 => self.lda(Mode::IndirectX),
-                0xa2 => self.ldx(Mode::Immediate),
-                0xa4 => self.ldy(Mode::ZeroPage),
-                0xa5 => self.lda(Mode::ZeroPage),
-                0xa6 => self.ldx(Mode::ZeroPage),
-                0xa8 => self.tay(Mode::Implied),
-                0xa9 => self.lda(Mode::Immediate),
-                0xaa => self.tax(Mode::Implied),
-                0xac => self.ldy(Mode::Absolute),
-                0xad => self.lda(Mode::Absolute),
-                0xae => self.ldx(Mode::Absolute),
-                0xb0 => self.bcs(Mode::Relative),
-                0xb1 => self.lda(Mode::IndirectY),
-                0xb4 => self.ldy(Mode::ZeroPageX),
-                0xb5 => self.lda(Mode::ZeroPageX),
-                0xb6 => self.ldx(Mode::ZeroPageY),
-                0xb8 => self.clv(Mode::Implied),
-                0xb9 => self.lda(Mode::AbsoluteY),
-                0xba => self.tsx(Mode::Implied),
-                0xbc => self.ldy(Mode::AbsoluteX),
-                0xbd => self.lda(Mode::AbsoluteX),
-                0xbe => self.ldx(Mode::AbsoluteY),
-                0xc0 => self.cpy(Mode::Immediate),
-                0xc1 => self.cmp(Mode::IndirectX),
-                0xc4 => self.cpy(Mode::ZeroPage),
-                0xc5 => self.cmp(Mode::ZeroPage),
-                0xc6 => self.dec(Mode::ZeroPage),
-                0xc8 => self.iny(Mode::Implied),
-                0xc9 => self.cmp(Mode::Immediate),
-                0xca => self.dex(Mode::Implied),
-                0xcc => self.cpy(Mode::Absolute),
-                0xcd => self.cmp(Mode::Absolute),
-                0xce => self.dec(Mode::Absolute),
-                0xd0 => self.bne(Mode::Relative),
-                0xd1 => self.cmp(Mode::IndirectY),
-                0xd5 => self.cmp(Mode::ZeroPageX),
-                0xd6 => self.dec(Mode::ZeroPageX),
-                0xd8 => self.cld(Mode::Implied),
-                0xd9 => self.cmp(Mode::AbsoluteY),
-                0xdd => self.cmp(Mode::AbsoluteX),
-                0xde => self.dec(Mode::AbsoluteX),
-                0xe0 => self.cpx(Mode::Immediate),
-                0xe1 => self.sbc(Mode::IndirectX),
-                0xe4 => self.cpx(Mode::ZeroPage),
-                0xe5 => self.sbc(Mode::ZeroPage),
-                0xe6 => self.inc(Mode::ZeroPage),
-                0xe8 => self.inx(Mode::Implied),
-                0xe9 => self.sbc(Mode::Immediate),
-                0xea => self.nop(Mode::Implied),
-                0xec => self.cpx(Mode::Absolute),
-                0xed => self.sbc(Mode::Absolute),
-                0xee => self.inc(Mode::Absolute),
-                0xf0 => self.beq(Mode::Relative),
-                0xf1 => self.sbc(Mode::IndirectY),
-                0xf5 => self.sbc(Mode::ZeroPageX),
-                0xf6 => self.inc(Mode::ZeroPageX),
-                0xf8 => self.sed(Mode::Implied),
-                0xf9 => self.sbc(Mode::AbsoluteY),
-                0xfd => self.sbc(Mode::AbsoluteX),
-                0xfe => self.inc(Mode::AbsoluteX),
-
-                //////// Undocumented Instructions ////////
-
-                0x0C => self.nop_read(Mode::Absolute),
-                0x1C | 0x3C | 0x5C | 0x7C | 0xDC | 0xFC => self.nop_read(Mode::AbsoluteX),
-                0x04 | 0x44 | 0x64 => self.nop_read(Mode::ZeroPage),
-                0x14 | 0x34 | 0x54 | 0x74 | 0xd4 | 0xf4 => self.nop_read(Mode::ZeroPageX),
-                0x80 | 0x82 | 0x89 | 0xc2 | 0xe2 => self.nop_read(Mode::Immediate),
-
-                0xA7 => self.lax(Mode::ZeroPage),
-                0xB7 => self.lax(Mode::ZeroPageY),
-                0xA3 => self.lax(Mode::IndirectX),
-                0xB3 => self.lax(Mode::IndirectY),
-                0xAF => self.lax(Mode::Absolute),
-                0xBF => self.lax(Mode::AbsoluteY),
-
-                0x87 => self.sax(Mode::ZeroPage),
-                0x97 => self.sax(Mode::ZeroPageY),
-                0x83 => self.sax(Mode::IndirectX),
-                0x8F => self.sax(Mode::Absolute),
-
-                0xEB => self.sbc(Mode::Immediate),
-
-                0xC7 => self.dcp(Mode::ZeroPage),
-                0xD7 => self.dcp(Mode::ZeroPageX),
-                0xC3 => self.dcp(Mode::IndirectX),
-                0xD3 => self.dcp(Mode::IndirectY),
-                0xCF => self.dcp(Mode::Absolute),
-                0xDF => self.dcp(Mode::AbsoluteX),
-                0xDB => self.dcp(Mode::AbsoluteY),
-
-                0xE7 => self.isc(Mode::ZeroPage),
-                0xF7 => self.isc(Mode::ZeroPageX),
-                0xE3 => self.isc(Mode::IndirectX),
-                0xF3 => self.isc(Mode::IndirectY),
-                0xEF => self.isc(Mode::Absolute),
-                0xFF => self.isc(Mode::AbsoluteX),
-                0xFB => self.isc(Mode::AbsoluteY),
-
-                0x07 => self.slo(Mode::ZeroPage),
-                0x17 => self.slo(Mode::ZeroPageX),
-                0x03 => self.slo(Mode::IndirectX),
-                0x13 => self.slo(Mode::IndirectY),
-                0x0F => self.slo(Mode::Absolute),
-                0x1F => self.slo(Mode::AbsoluteX),
-                0x1B => self.slo(Mode::AbsoluteY),
-
-                0x27 => self.rla(Mode::ZeroPage),
-                0x37 => self.rla(Mode::ZeroPageX),
-                0x23 => self.rla(Mode::IndirectX),
-                0x33 => self.rla(Mode::IndirectY),
-                0x2F => self.rla(Mode::Absolute),
-                0x3F => self.rla(Mode::AbsoluteX),
-                0x3B => self.rla(Mode::AbsoluteY),
-
-                0x47 => self.sre(Mode::ZeroPage),
-                0x57 => self.sre(Mode::ZeroPageX),
-                0x43 => self.sre(Mode::IndirectX),
-                0x53 => self.sre(Mode::IndirectY),
-                0x4F => self.sre(Mode::Absolute),
-                0x5F => self.sre(Mode::AbsoluteX),
-                0x5B => self.sre(Mode::AbsoluteY),
-
-                0x67 => self.rra(Mode::ZeroPage),
-                0x77 => self.rra(Mode::ZeroPageX),
-                0x63 => self.rra(Mode::IndirectX),
-                0x73 => self.rra(Mode::IndirectY),
-                0x6F => self.rra(Mode::Absolute),
-                0x7F => self.rra(Mode::AbsoluteX),
-                0x7B => self.rra(Mode::AbsoluteY),
-                _ => self.nop(Mode::Implied),
+            self.step = 2;
+            self.access_step = 0;
+            self.penalty = 0;
+            let interrupted = matches!(self.operation, Operation::Interrupt(_));
+            if !interrupted && self.opcodes[self.opcode as usize].base_cycles == 1 {
+                // The 65C02's single cycle NOPs end with their fetch.
+                self.done(1);
             }
+        } else {
+            self.execute_cycle();
             self.set_unused();
         }
+        self.bus.tick();
+        self.poll_interrupts();
         self.cycle_count += 1;
-        self.cycles -= 1;
     }
 
-    //Instructions.
-    fn ora(&mut self, mode: Mode) {
-        let operand: u8 = self.read_operand(&mode, true);
-        let result = self.a | operand;
-        self.set_zero(result == 0x00);
-        self.set_negative((result & 0x80) > 0);
-        self.a = result as u8;
+    // The 6502 looks at its interrupt lines at the end of every cycle, and
+    // whether an interrupt follows an instruction is decided by what it saw
+    // at the end of the instruction's second-to-last cycle. That is why
+    // CLI, SEI and PLP only take effect after the next instruction.
+    fn poll_interrupts(&mut self) {
+        let nmi_line = self.bus.nmi_line();
+        if nmi_line && !self.nmi_line {
+            self.nmi_detected = true;
+        }
+        self.nmi_line = nmi_line;
+        self.irq_lines = self.bus.irq_lines();
+        self.irq_detected = self.irq_lines != 0 && !self.get_interrupt_disable();
     }
 
-    fn asl(&mut self, mode: Mode) {
-        self.asl_ret(&mode);
+    pub fn irq_asserted(&self, source: IrqSource) -> bool {
+        self.irq_lines & source as u8 != 0
     }
 
-    fn asl_a(&mut self) {
-        let operand = self.a;
-        let result: u16 = (operand as u16) << 1;
-        self.set_carry(operand & 0b10000000 != 0);
-        self.set_zero((result & 0x00ff) == 0x00);
+    fn execute_cycle(&mut self) {
+        let step = self.step;
+        self.step += 1;
+
+        match self.operation {
+            Operation::Implied(op) => {
+                self.read(self.pc);
+                op(self);
+                self.done(step);
+            }
+            Operation::Modify(op) if self.mode == Mode::Accumulator => {
+                self.read(self.pc);
+                self.a = op(self, self.a);
+                self.done(step);
+            }
+            Operation::Read(op) => {
+                if self.access_step != 0 {
+                    // Cycles some 65C02 opcodes spend after the access (decimal mode, long NOPs)
+                    self.read(self.addr);
+                    self.finish_read(step);
+                } else if self.address_cycle(step, self.page_cross_penalty()) {
+                    let value = self.read(self.addr);
+                    self.log_data();
+                    op(self, value);
+                    self.access_step = step;
+                    self.finish_read(step);
+                }
+            }
+            Operation::Write(op) => {
+                if self.address_cycle(step, false) {
+                    let value = op(self);
+                    self.write(self.addr, value);
+                    self.done(step);
+                }
+            }
+            Operation::Modify(op) => {
+                if self.access_step == 0 {
+                    if self.address_cycle(step, self.page_cross_penalty()) {
+                        self.access_step = step;
+                        self.data = self.read(self.addr);
+                        self.log_data();
+                    }
+                } else if step == self.access_step + 1 {
+                    // The unmodified value is written back while the ALU works,
+                    // the 65C02 reads it again instead.
+                    if self.variant == CpuVariant::Cmos65C02 {
+                        self.read(self.addr);
+                    } else {
+                        self.write(self.addr, self.data);
+                    }
+                    self.data = op(self, self.data);
+                } else {
+                    self.write(self.addr, self.data);
+                    self.done(step);
+                }
+            }
+            Operation::Branch(condition) => self.branch_cycle(step, condition),
+            Operation::Push(value) => {
+                if step == 2 {
+                    self.read(self.pc);
+                } else {
+                    let value = value(self);
+                    self.push_to_stack(value);
+                    self.done(step);
+                }
+            }
+            Operation::Pull(op) => match step {
+                2 => { self.read(self.pc); }
+                3 => { self.read(0x100 + self.sp as u16); }
+                _ => {
+                    let value = self.pop_from_stack();
+                    op(self, value);
+                    self.done(step);
+                }
+            },
+            Operation::Jmp => self.jmp_cycle(step),
+            Operation::Jsr => match step {
+                2 => self.addr = self.next_byte() as u16,
+                3 => { self.read(0x100 + self.sp as u16); }
+                4 => self.push_to_stack((self.pc >> 8) as u8),
+                5 => self.push_to_stack(self.pc as u8),
+                _ => {
+                    self.addr |= (self.read(self.pc) as u16) << 8;
+                    self.pc = self.addr;
+                    self.done(step);
+                }
+            },
+            Operation::Rts => match step {
+                2 => { self.read(self.pc); }
+                3 => { self.read(0x100 + self.sp as u16); }
+                4 => self.pc = self.pop_from_stack() as u16,
+                5 => self.pc |= (self.pop_from_stack() as u16) << 8,
+                _ => {
+                    self.read(self.pc);
+                    self.increment_pc();
+                    self.done(step);
+                }
+            },
+            Operation::Rti => match step {
+                2 => { self.read(self.pc); }
+                3 => { self.read(0x100 + self.sp as u16); }
+                4 => {
+                    self.p = self.pop_from_stack();
+                    self.set_break(false);
+                }
+                5 => self.pc = self.pop_from_stack() as u16,
+                _ => {
+                    self.pc |= (self.pop_from_stack() as u16) << 8;
+                    self.done(step);
+                }
+            },
+            Operation::Brk | Operation::Interrupt(_) => self.interrupt_cycle(step),
+            Operation::Jam => {
+                // After its second cycle the CPU is stuck, clocked but never
+                // reading or writing again.
+                if step == 2 {
+                    self.read(self.pc);
+                }
+                self.step = 3;
+            }
+        }
+    }
+
+    // Addressing mode cycles. Returns true on the cycle that accesses the
+    // effective address, which is in self.addr by then. Indexed modes do a
+    // dummy read of the unfixed address when they cross a page, opcodes with
+    // a page crossing penalty skip it when they don't.
+    fn address_cycle(&mut self, step: u8, penalty: bool) -> bool {
+        match (self.mode, step) {
+            (Mode::Immediate, _) => {
+                self.addr = self.pc;
+                self.increment_pc();
+                true
+            }
+            (Mode::ZeroPage, 2) | (Mode::ZeroPageX, 2) | (Mode::ZeroPageY, 2)
+            | (Mode::Absolute, 2) | (Mode::AbsoluteX, 2) | (Mode::AbsoluteY, 2) => {
+                self.addr = self.next_byte() as u16;
+                false
+            }
+            (Mode::ZeroPage, _) => true,
+            (Mode::ZeroPageX, 3) | (Mode::ZeroPageY, 3) => {
+                self.read(self.addr);
+                let index = if self.mode == Mode::ZeroPageX { self.x } else { self.y };
+                self.addr = low_byte(offset(self.addr, index));
+                false
+            }
+            (Mode::ZeroPageX, _) | (Mode::ZeroPageY, _) => true,
+            (Mode::Absolute, 3) => {
+                self.addr |= (self.next_byte() as u16) << 8;
+                false
+            }
+            (Mode::Absolute, _) => true,
+            (Mode::AbsoluteX, 3) | (Mode::AbsoluteY, 3) => {
+                let high = (self.next_byte() as u16) << 8;
+                let index = if self.mode == Mode::AbsoluteX { self.x } else { self.y };
+                self.index_address(high, index);
+                false
+            }
+            (Mode::AbsoluteX, 4) | (Mode::AbsoluteY, 4) => self.fix_address(penalty),
+            (Mode::AbsoluteX, _) | (Mode::AbsoluteY, _) => true,
+            (Mode::IndirectX, 2) | (Mode::IndirectY, 2) => {
+                self.pointer = self.next_byte();
+                false
+            }
+            (Mode::IndirectX, 3) => {
+                self.read(self.pointer as u16);
+                self.pointer = self.pointer.wrapping_add(self.x);
+                false
+            }
+            (Mode::IndirectX, 4) => {
+                self.addr = self.read_pointer(self.pointer as u16) as u16;
+                false
+            }
+            (Mode::IndirectX, 5) => {
+                self.addr |= (self.read_pointer(low_byte(self.pointer.wrapping_add(1))) as u16) << 8;
+                false
+            }
+            (Mode::IndirectX, _) => true,
+            (Mode::IndirectY, 3) => {
+                self.addr = self.read_pointer(self.pointer as u16) as u16;
+                false
+            }
+            (Mode::IndirectY, 4) => {
+                let high = (self.read_pointer(low_byte(self.pointer.wrapping_add(1))) as u16) << 8;
+                self.index_address(high, self.y);
+                false
+            }
+            (Mode::IndirectY, 5) => self.fix_address(penalty),
+            (Mode::IndirectY, _) => true,
+            (Mode::ZeroPageIndirect, 2) => {
+                self.pointer = self.next_byte();
+                false
+            }
+            (Mode::ZeroPageIndirect, 3) => {
+                self.addr = self.read_pointer(self.pointer as u16) as u16;
+                false
+            }
+            (Mode::ZeroPageIndirect, 4) => {
+                self.addr |= (self.read_pointer(low_byte(self.pointer.wrapping_add(1))) as u16) << 8;
+                false
+            }
+            (Mode::ZeroPageIndirect, _) => true,
+            _ => panic!("Error: Unknown mode to read from memory"),
+        }
+    }
+
+    // Adds the index to the low byte only, the carry into the high byte takes another cycle.
+    fn index_address(&mut self, high: u16, index: u8) {
+        let low = (self.addr & 0xFF) + index as u16;
+        self.page_crossed = low > 0xFF;
+        self.addr = high | (low & 0xFF);
+    }
+
+    fn fix_address(&mut self, penalty: bool) -> bool {
+        if penalty && !self.page_crossed {
+            return true;
+        }
+        self.read(self.addr);
+        if self.page_crossed {
+            self.addr = self.addr.wrapping_add(0x100);
+            if penalty {
+                self.penalty += 1;
+            }
+        }
+        false
+    }
+
+    fn page_cross_penalty(&self) -> bool {
+        self.opcodes[self.opcode as usize].page_cross_penalty
+    }
+
+    fn finish_read(&mut self, step: u8) {
+        if step >= self.opcodes[self.opcode as usize].base_cycles + self.penalty {
+            self.done(step);
+        }
+    }
+
+    fn branch_cycle(&mut self, step: u8, condition: fn(&mut CPU<B>) -> bool) {
+        match step {
+            2 => {
+                self.data = self.next_byte();
+                if !condition(self) {
+                    self.done(step);
+                } else {
+                    self.branch_poll = (self.nmi_detected, self.irq_detected);
+                }
+            }
+            3 => {
+                self.read(self.pc);
+                self.penalty += 1;
+                let target = self.pc.wrapping_add(self.data as i8 as u16);
+                self.addr = target;
+                if high_byte(target) == high_byte(self.pc) {
+                    self.pc = target;
+                    // A taken branch that stays on its page doesn't poll on its
+                    // second cycle, what was seen then waits an instruction more.
+                    let late_nmi = self.nmi_detected && !self.branch_poll.0;
+                    self.nmi_detected = self.branch_poll.0;
+                    self.irq_detected = self.branch_poll.1;
+                    self.done(step);
+                    self.nmi_detected |= late_nmi;
+                } else {
+                    // PCH is fixed up on the next cycle, this one reads from the wrong page.
+                    self.pc = high_byte(self.pc) | low_byte(target);
+                }
+            }
+            _ => {
+                self.read(self.pc);
+                self.penalty += 1;
+                self.pc = self.addr;
+                self.done(step);
+            }
+        }
+    }
+
+    fn jmp_cycle(&mut self, step: u8) {
+        match step {
+            2 => self.addr = self.next_byte() as u16,
+            3 if self.mode == Mode::Absolute => {
+                self.pc = self.addr | ((self.read(self.pc) as u16) << 8);
+                self.done(step);
+            }
+            3 => self.addr |= (self.next_byte() as u16) << 8,
+            4 if self.variant == CpuVariant::Cmos65C02 => {
+                // Spent on the fixed pointer increment, or on adding X for JMP ($xxxx,X).
+                self.read(self.pc.wrapping_sub(1));
+                if self.mode == Mode::AbsoluteIndirectX {
+                    self.addr = self.addr.wrapping_add(self.x as u16);
+                }
+            }
+            4 => self.data = self.read(self.addr),
+            5 if self.variant == CpuVariant::Cmos65C02 => self.data = self.read(self.addr),
+            _ if self.variant == CpuVariant::Cmos65C02 => {
+                let high_addr = self.addr.wrapping_add(1);
+                let high = self.read(high_addr);
+                self.pc = ((high as u16) << 8) | self.data as u16;
+                self.log_jump_pointer(high_addr);
+                self.done(step);
+            }
+            _ => {
+                // The pointer's high byte is read without carrying into its page.
+                let high_addr = high_byte(self.addr) | low_byte(self.addr.wrapping_add(1));
+                let high = self.read(high_addr);
+                self.pc = ((high as u16) << 8) | self.data as u16;
+                self.log_jump_pointer(high_addr);
+                self.done(step);
+            }
+        }
+    }
+
+    // BRK, IRQ, NMI and reset share the same 7 cycles: push PC and P, then load
+    // the vector. Reset does the stack accesses as reads.
+    fn interrupt_cycle(&mut self, step: u8) {
+        let interrupt = match self.operation {
+            Operation::Interrupt(interrupt) => interrupt,
+            _ => Interrupt::Break,
+        };
+        match step {
+            2 => {
+                self.read(self.pc);
+                if interrupt == Interrupt::Break {
+                    // BRK skips its padding byte.
+                    self.increment_pc();
+                }
+                self.vector = vector(interrupt);
+            }
+            3..=5 if interrupt == Interrupt::Reset => {
+                self.read(0x100 + self.sp as u16);
+                self.sp = self.sp.wrapping_sub(1);
+            }
+            3 => self.push_to_stack((self.pc >> 8) as u8),
+            4 => self.push_to_stack(self.pc as u8),
+            5 => {
+                let flags = if interrupt == Interrupt::Break { self.p | 0b00010000 } else { self.p & !0b00010000 };
+                self.push_to_stack(flags | 0b00100000);
+                // An NMI seen by now hijacks a BRK or IRQ: the pushed B flag stays
+                // the same but the NMI vector is used, and that NMI is gone.
+                if self.nmi_detected && interrupt != Interrupt::Nmi {
+                    self.nmi_detected = false;
+                    self.vector = vector(Interrupt::Nmi);
+                }
+            }
+            6 => {
+                self.addr = self.read(self.vector) as u16;
+                self.bus.code_data(self.vector, cdl::DATA);
+                self.set_interrupt_disable(true);
+                if self.variant == CpuVariant::Cmos65C02 {
+                    self.set_decimal(false);
+                }
+            }
+            _ => {
+                self.pc = self.addr | (self.read(self.vector + 1) as u16) << 8;
+                self.bus.code_data(self.vector + 1, cdl::DATA);
+                self.done(step);
+            }
+        }
+    }
+
+    // The operand the instruction just read, unless it was part of the
+    // instruction itself.
+    fn log_data(&mut self) {
+        let flags = match self.mode {
+            Mode::Immediate => return,
+            Mode::IndirectX | Mode::IndirectY | Mode::ZeroPageIndirect => cdl::DATA | cdl::INDIRECT_DATA,
+            _ => cdl::DATA,
+        };
+        self.bus.code_data(self.addr, flags);
+    }
+
+    // A byte of a zero page pointer, which is data the instruction used.
+    fn read_pointer(&mut self, addr: u16) -> u8 {
+        self.bus.code_data(addr, cdl::DATA);
+        self.read(addr)
+    }
+
+    // JMP ($xxxx) read its pointer from self.addr and high_addr and went to PC.
+    fn log_jump_pointer(&mut self, high_addr: u16) {
+        self.bus.code_data(self.addr, cdl::DATA);
+        self.bus.code_data(high_addr, cdl::DATA);
+        self.bus.code_data(self.pc, cdl::INDIRECT_CODE);
+    }
+
+    fn done(&mut self, step: u8) {
+        if !matches!(self.operation, Operation::Interrupt(_)) {
+            let info = &self.opcodes[self.opcode as usize];
+            debug_assert_eq!(step, info.base_cycles + self.penalty,
+                "Opcode {:02X} took the wrong number of cycles", self.opcode);
+            debug_assert!(self.penalty == 0 || info.page_cross_penalty || self.variant == CpuVariant::Cmos65C02,
+                "Opcode {:02X} took a page crossing penalty", self.opcode);
+        }
+        self.step = 0;
+        self.access_step = 0;
+
+        // The first instruction of a handler always runs before the next interrupt.
+        let interrupted = matches!(self.operation, Operation::Brk | Operation::Interrupt(_));
+        if !interrupted && self.pending_interrupt.is_none() {
+            if self.nmi_detected {
+                self.nmi_detected = false;
+                self.pending_interrupt = Some(Interrupt::Nmi);
+            } else if self.irq_detected {
+                self.pending_interrupt = Some(Interrupt::Irq);
+            }
+        }
+    }
+
+    //Instructions.
+    fn ora(&mut self, operand: u8) {
+        let result = self.a | operand;
+        self.set_zero(result == 0x00);
         self.set_negative((result & 0x80) > 0);
-        self.a = result as u8;
+        self.a = result;
     }
 
-    fn asl_ret(&mut self, mode: &Mode) -> u8 {
-        let address = self.operand_address(mode, false);
-        let operand = self.read(address);
+    fn asl(&mut self, operand: u8) -> u8 {
         let result: u16 = (operand as u16) << 1;
         self.set_carry(operand & 0b10000000 != 0);
         self.set_zero((result & 0x00ff) == 0x00);
         self.set_negative((result & 0x80) > 0);
-        self.write(address, result as u8);
         result as u8
     }
 
-    fn php(&mut self, _ : Mode) {
-        self.set_break(true);
-        self.set_unused();
-        self.push_to_stack(self.p);
-        self.set_break(false);
+    fn php(&mut self) -> u8 {
+        self.p | 0b00110000
     }
 
-    fn bpl(&mut self, _ : Mode) {
-        let condition = !self.get_negative();
-        self.branch(condition);
+    fn bpl(&mut self) -> bool {
+        !self.get_negative()
     }
 
-    fn clc(&mut self, _ : Mode) {
+    fn clc(&mut self) {
         self.set_carry(false);
     }
 
-    fn jsr(&mut self, _ : Mode) {
-        let x = Mode::Absolute;
-        let target_address = self.operand_address(&x, false);
-        let return_address = self.pc - 1;
-        self.push_to_stack((return_address >> 8) as u8);
-        self.push_to_stack(return_address as u8);
-        self.pc = target_address;
-    }
-
-    fn and(&mut self, mode: Mode) {
-        let operand = self.read_operand(&mode, true);
+    fn and(&mut self, operand: u8) {
         let result = self.a & operand;
         self.set_zero(result == 0x0);
         self.set_negative((result & 0x80) > 0);
         self.a = result;
     }
 
-    fn bit(&mut self, mode: Mode) {
-        let operand = self.read_operand(&mode, false);
+    fn bit(&mut self, operand: u8) {
         let result = self.a & operand;
-        self.set_zero((result as u8) == 0);
+        self.set_zero(result == 0);
         self.set_overflow(operand & 0b01000000 != 0);
         self.set_negative(operand & 0b10000000 != 0);
     }
 
-    fn rol(&mut self, mode: Mode) {
-        self.rol_ret(&mode);
-    }
-
-    fn rol_a(&mut self) {
-        let operand = self.a;
-        let carry: u8;
-        if self.get_carry() { carry = 1 } else { carry = 0 };
-        let result = (operand << 1) | carry;
-        self.set_carry(operand & 0b10000000 != 0);
-        self.set_zero((result as u8) == 0);
-        self.set_negative((result & 0x80) > 0);
-        self.a = result;
-    }
-
-    fn rol_ret(&mut self, mode: &Mode) -> u8 {
-        let address = self.operand_address(mode, false);
-        let operand = self.read(address);
-        let carry: u8;
-        if self.get_carry() { carry = 1 } else { carry = 0 };
+    fn rol(&mut self, operand: u8) -> u8 {
+        let carry: u8 = if self.get_carry() { 1 } else { 0 };
         let result = (operand << 1) | carry;
         self.set_carry(operand & 0b10000000 != 0);
-        self.set_zero((result as u8) == 0);
+        self.set_zero(result == 0);
         self.set_negative((result & 0x80) > 0);
-        self.write(address, result);
         result
     }
 
-    fn plp(&mut self, _ : Mode) {
-        self.p = self.pop_from_stack();
+    fn plp(&mut self, operand: u8) {
+        self.p = operand;
+        self.set_break(false);
         self.set_unused();
     }
 
-    fn bmi(&mut self, _ : Mode) {
-        let condition = self.get_negative();
-        self.branch(condition);
+    fn bmi(&mut self) -> bool {
+        self.get_negative()
     }
 
-    fn sec(&mut self, _ : Mode) {
+    fn sec(&mut self) {
         self.set_carry(true);
     }
 
-    fn rti(&mut self, _ : Mode) {
-        self.p = self.pop_from_stack();
-        self.pc = self.pop_from_stack() as u16;
-        self.pc |= (self.pop_from_stack() as u16) << 8;
-    }
-
-    fn eor(&mut self, mode: Mode) {
-        let operand = self.read_operand(&mode, true);
+    fn eor(&mut self, operand: u8) {
         let result = self.a ^ operand;
-        self.set_zero((result as u8) == 0);
+        self.set_zero(result == 0);
         self.set_negative((result & 0b10000000) != 0);
         self.a = result;
     }
 
-    fn lsr(&mut self, mode: Mode) {
-        self.lsr_ret(&mode);
-    }
-
-    fn lsr_a(&mut self) {
-        let operand = self.a;
+    fn lsr(&mut self, operand: u8) -> u8 {
         let result = operand >> 1;
         self.set_carry(operand & 1 != 0);
-        self.set_zero((result as u8) == 0);
-        self.set_negative((result & 0x80) > 0);
-        self.a = result;
-    }
-
-    fn lsr_ret(&mut self, mode: &Mode) -> u8 {
-        let address = self.operand_address(mode, false);
-        let operand = self.read(address);
-        let result = operand >> 1;
-        self.set_carry(operand & 1 != 0);
-        self.set_zero((result as u8) == 0);
+        self.set_zero(result == 0);
         self.set_negative((result & 0x80) > 0);
-        self.write(address, result);
         result
     }
 
-    fn pha(&mut self, _ : Mode) {
-        self.push_to_stack(self.a);
+    fn pha(&mut self) -> u8 {
+        self.a
     }
 
-    fn jmp(&mut self, mode: Mode) {
-        self.pc = self.operand_address(&mode, false);
-        // self.cycles = 7;
+    fn bvc(&mut self) -> bool {
+        !self.get_overflow()
     }
 
-    fn bvc(&mut self, _ : Mode) {
-        let condition = !self.get_overflow();
-        self.branch(condition);
+    fn adc(&mut self, operand: u8) {
+        if self.decimal_mode() {
+            self.adc_decimal(operand);
+        } else {
+            self.add(operand);
+        }
     }
 
-    fn adc(&mut self, mode: Mode) {
+    // Binary addition, SBC is the same with the operand inverted.
+    fn add(&mut self, operand: u8) {
         let a = self.a;
-        let operand = self.read_operand(&mode, true);
-        let carry: u8;
-        if self.get_carry() { carry = 1 } else { carry = 0 };
+        let carry: u8 = if self.get_carry() { 1 } else { 0 };
         let result = a as u16 + operand as u16 + carry as u16;
         self.set_overflow((a as u16 ^ result) & (operand as u16 ^ result) & 0x80 != 0);
         self.set_carry(result>0xff);
@@ -504,409 +1184,452 @@ impl CPU {
         self.a = result as u8;
     }
 
-    fn cli(&mut self, _ : Mode) {
+    fn cli(&mut self) {
         self.set_interrupt_disable(false);
     }
 
-    fn rts(&mut self, _ : Mode) {
-        self.pc = self.pop_from_stack() as u16;
-        self.pc |= (self.pop_from_stack() as u16) << 8;
-        self.pc += 1;
-    }
-
-    fn ror(&mut self, mode: Mode) {
-        self.ror_ret(&mode);
-    }
-
-    fn ror_a(&mut self) {
-        let operand = self.a;
-        let carry: u8;
-        if self.get_carry() { carry = 1 } else { carry = 0 };
-        let result = (operand >> 1) | (carry << 7);
-        self.set_carry(operand & 1 != 0);
-        self.set_zero((result as u8) == 0);
-        self.set_negative((result & 0x80) > 0);
-        self.a = result;
-    }
-
-    fn ror_ret(&mut self, mode: &Mode) -> u8 {
-        let address = self.operand_address(mode, false);
-        let operand = self.read(address);
-        let carry: u8;
-        if self.get_carry() { carry = 1 } else { carry = 0 };
+    fn ror(&mut self, operand: u8) -> u8 {
+        let carry: u8 = if self.get_carry() { 1 } else { 0 };
         let result = (operand >> 1) | (carry << 7);
         self.set_carry(operand & 1 != 0);
-        self.set_zero((result as u8) == 0);
+        self.set_zero(result == 0);
         self.set_negative((result & 0x80) > 0);
-        self.write(address, result);
         result
     }
 
-    fn pla(&mut self, _ : Mode) {
-        let result = self.pop_from_stack();
-        self.set_zero((result as u8) == 0);
+    fn pla(&mut self, result: u8) {
+        self.set_zero(result == 0);
         self.set_negative((result & 0x80) > 0);
         self.a = result;
     }
 
-    fn bvs(&mut self, _ : Mode) {
-        let condition = self.get_overflow();
-        self.branch(condition);
+    fn bvs(&mut self) -> bool {
+        self.get_overflow()
     }
 
-    fn sei(&mut self, _ : Mode) {
+    fn sei(&mut self) {
         self.set_interrupt_disable(true);
     }
 
-    fn sty(&mut self, mode: Mode) {
-        let address = self.operand_address(&mode, false);
-        let value = self.y;
-        self.write(address, value);
+    fn sty(&mut self) -> u8 {
+        self.y
     }
 
-    fn sta(&mut self, mode: Mode) {
-        let address = self.operand_address(&mode, false);
-        let value = self.a;
-        self.write(address, value);
+    fn sta(&mut self) -> u8 {
+        self.a
     }
 
-    fn stx(&mut self, mode: Mode) {
-        let address = self.operand_address(&mode, false);
-        let value = self.x;
-        self.write(address, value);
+    fn stx(&mut self) -> u8 {
+        self.x
     }
 
-    fn dey(&mut self, _ : Mode) {
+    fn dey(&mut self) {
         let result = self.y.wrapping_sub(1);
-        self.set_zero((result as u8) == 0);
+        self.set_zero(result == 0);
         self.set_negative((result & 0x80) > 0);
         self.y = result;
     }
 
-    fn txa(&mut self, _ : Mode) {
+    fn txa(&mut self) {
         let result = self.x;
-        self.set_zero((result as u8) == 0);
+        self.set_zero(result == 0);
         self.set_negative((result & 0x80) > 0);
         self.a = result;
     }
 
-    fn bcc(&mut self, _ : Mode) {
-        let condition = !self.get_carry();
-        self.branch(condition);
+    fn bcc(&mut self) -> bool {
+        !self.get_carry()
     }
 
-    fn tya(&mut self, _ : Mode) {
+    fn tya(&mut self) {
         let result = self.y;
-        self.set_zero((result as u8) == 0);
+        self.set_zero(result == 0);
         self.set_negative((result & 0x80) > 0);
         self.a = result;
     }
 
-    fn txs(&mut self, _ : Mode) {
+    fn txs(&mut self) {
         let result = self.x;
         self.sp = result;
     }
 
-    fn lax(&mut self, mode: Mode) {
-        self.lda(mode);
+    fn lax(&mut self, operand: u8) {
+        self.lda(operand);
         self.x = self.a;
     }
 
-    fn ldy(&mut self, mode: Mode) {
-        let operand = self.read_operand(&mode, true);
+    fn ldy(&mut self, operand: u8) {
         self.set_zero(operand == 0);
         self.set_negative((operand & 0x80) > 0);
         self.y = operand;
     }
 
-    fn lda(&mut self, mode: Mode) {
-        let operand = self.read_operand(&mode, true);
+    fn lda(&mut self, operand: u8) {
         self.set_zero(operand == 0);
         self.set_negative((operand & 0x80) > 0);
         self.a = operand;
     }
 
-    fn ldx(&mut self, mode: Mode) {
-        let operand = self.read_operand(&mode, true);
+    fn ldx(&mut self, operand: u8) {
         self.set_zero(operand == 0);
         self.set_negative((operand & 0x80) > 0);
         self.x = operand;
     }
 
-    fn tay(&mut self, _ : Mode) {
+    fn tay(&mut self) {
         let result = self.a;
-        self.set_zero((result as u8) == 0);
+        self.set_zero(result == 0);
         self.set_negative((result & 0x80) > 0);
         self.y = result;
     }
 
-    fn tax(&mut self, _ : Mode) {
+    fn tax(&mut self) {
         let result = self.a;
-        self.set_zero((result as u8) == 0);
+        self.set_zero(result == 0);
         self.set_negative((result & 0x80) > 0);
         self.x = result;
     }
 
-    fn bcs(&mut self, _ : Mode) {
-        let condition = self.get_carry();
-        self.branch(condition);
+    fn bcs(&mut self) -> bool {
+        self.get_carry()
     }
 
-    fn clv(&mut self, _ : Mode) {
+    fn clv(&mut self) {
         self.set_overflow(false);
     }
 
-    fn tsx(&mut self, _ : Mode) {
+    fn tsx(&mut self) {
         self.x = self.sp;
         self.set_zero(self.x == 0);
         self.set_negative((self.x & 0x80) > 0);
     }
 
-    fn cpy(&mut self, mode: Mode) {
-        let operand = self.read_operand(&mode, false);
+    fn cpy(&mut self, operand: u8) {
         let y = self.y;
         self.set_zero(y.wrapping_sub(operand) == 0);
         self.set_negative((y.wrapping_sub(operand) & 0x80) > 0);
         self.set_carry(y >= operand);
     }
 
-    fn cmp(&mut self, mode: Mode) {
-        let operand = self.read_operand(&mode, true);
+    fn cmp(&mut self, operand: u8) {
         let a = self.a;
         self.set_zero(a.wrapping_sub(operand) == 0);
         self.set_negative((a.wrapping_sub(operand) & 0x80) > 0);
         self.set_carry(a >= operand);
     }
 
-    fn dec(&mut self, mode: Mode) {
-        let address = self.operand_address(&mode, false);
-        let operand = self.read(address);
+    fn dec(&mut self, operand: u8) -> u8 {
         let result = operand.wrapping_sub(1);
-        self.set_zero((result as u8) == 0);
+        self.set_zero(result == 0);
         self.set_negative((result & 0x80) > 0);
-        self.write(address, result);
+        result
     }
 
-    fn iny(&mut self, _ : Mode) {
+    fn iny(&mut self) {
         let result = self.y.wrapping_add(1);
-        self.set_zero((result as u8) == 0);
+        self.set_zero(result == 0);
         self.set_negative((result & 0x80) > 0);
         self.y = result;
     }
 
-    fn dex(&mut self, _ : Mode) {
+    fn dex(&mut self) {
         let result = self.x.wrapping_sub(1);
-        self.set_zero((result as u8) == 0);
+        self.set_zero(result == 0);
         self.set_negative((result & 0x80) > 0);
         self.x = result;
     }
 
-    fn bne(&mut self, _ : Mode) {
-        let condition = !self.get_zero();
-        self.branch(condition);
+    fn bne(&mut self) -> bool {
+        !self.get_zero()
     }
-    fn cld(&mut self, _ : Mode) {
+    fn cld(&mut self) {
         self.set_decimal(false);
     }
 
-    fn cpx(&mut self, mode: Mode) {
-        let operand = self.read_operand(&mode, false);
+    fn cpx(&mut self, operand: u8) {
         let x = self.x;
         self.set_zero(x.wrapping_sub(operand) == 0);
         self.set_negative((x.wrapping_sub(operand) & 0x80) > 0);
         self.set_carry(x >= operand);
     }
 
-    fn sbc(&mut self, mode: Mode) {
+    fn sbc(&mut self, operand: u8) {
+        if self.decimal_mode() {
+            self.sbc_decimal(operand);
+        } else {
+            self.add(!operand);
+        }
+    }
+
+    fn decimal_mode(&mut self) -> bool {
+        self.variant != CpuVariant::Ricoh2A03 && self.get_decimal()
+    }
+
+    // BCD addition as in the 6502.org decimal mode tutorial. The NMOS chip sets
+    // N and V from the result before the high nibble is adjusted and Z from
+    // the binary sum, the 65C02 sets N and Z from the result and takes a cycle more.
+    fn adc_decimal(&mut self, operand: u8) {
         let a = self.a;
-        let operand = !self.read_operand(&mode, true);
-        let carry: u8;
-        if self.get_carry() { carry = 1 } else { carry = 0 };
-        let result = a as u16 + operand as u16 + carry as u16;
-        self.set_overflow((a as u16 ^ result) & (operand as u16 ^ result) & 0x80 != 0);
-        self.set_carry(result>0xff);
-        self.set_zero((result as u8) == 0);
-        self.set_negative((result & 0x80) > 0);
+        let carry: u16 = if self.get_carry() { 1 } else { 0 };
+        let binary = (a as u16 + operand as u16 + carry) as u8;
+
+        let mut low = (a & 0x0F) as u16 + (operand & 0x0F) as u16 + carry;
+        if low >= 0x0A {
+            low = ((low + 0x06) & 0x0F) + 0x10;
+        }
+        let mut result = (a & 0xF0) as u16 + (operand & 0xF0) as u16 + low;
+        self.set_negative(result & 0x80 != 0);
+        self.set_overflow((a ^ result as u8) & (operand ^ result as u8) & 0x80 != 0);
+        if result >= 0xA0 {
+            result += 0x60;
+        }
+        self.set_carry(result >= 0x100);
         self.a = result as u8;
+
+        if self.variant == CpuVariant::Cmos65C02 {
+            self.set_zero(self.a == 0);
+            self.set_negative(self.a & 0x80 != 0);
+            self.penalty += 1;
+        } else {
+            self.set_zero(binary == 0);
+        }
     }
 
-    fn inc(&mut self, mode: Mode) {
-        let address = self.operand_address(&mode, false);
-        let operand = self.read(address);
+    // BCD subtraction. Flags come from the binary subtraction on the NMOS
+    // chip, the 65C02 sets N and Z from the result.
+    fn sbc_decimal(&mut self, operand: u8) {
+        let a = self.a;
+        let borrow: i16 = if self.get_carry() { 0 } else { 1 };
+        self.add(!operand);
+
+        let mut low = (a & 0x0F) as i16 - (operand & 0x0F) as i16 - borrow;
+        let result = if self.variant == CpuVariant::Cmos65C02 {
+            let mut result = a as i16 - operand as i16 - borrow;
+            if result < 0 {
+                result -= 0x60;
+            }
+            if low < 0 {
+                result -= 0x06;
+            }
+            result
+        } else {
+            if low < 0 {
+                low = ((low - 0x06) & 0x0F) - 0x10;
+            }
+            let mut result = (a & 0xF0) as i16 - (operand & 0xF0) as i16 + low;
+            if result < 0 {
+                result -= 0x60;
+            }
+            result
+        };
+        self.a = result as u8;
+
+        if self.variant == CpuVariant::Cmos65C02 {
+            self.set_zero(self.a == 0);
+            self.set_negative(self.a & 0x80 != 0);
+            self.penalty += 1;
+        }
+    }
+
+    fn inc(&mut self, operand: u8) -> u8 {
         let result = operand.wrapping_add(1);
-        self.set_zero((result as u8) == 0);
+        self.set_zero(result == 0);
         self.set_negative((result & 0x80) > 0);
-        self.write(address, result);
+        result
     }
 
-    fn inx(&mut self, _ : Mode) {
+    fn inx(&mut self) {
         let result = self.x.wrapping_add(1);
-        self.set_zero((result as u8) == 0);
+        self.set_zero(result == 0);
         self.set_negative((result & 0x80) > 0);
         self.x = result;
     }
 
-    fn nop(&mut self, _ : Mode) {}
+    fn nop(&mut self) {}
 
-    fn nop_read(&mut self, mode: Mode) {
-        self.read_operand(&mode, true);
-    }
+    fn nop_read(&mut self, _ : u8) {}
 
-    fn beq(&mut self, _ : Mode) {
-        let condition = self.get_zero();
-        self.branch(condition);
+    fn beq(&mut self) -> bool {
+        self.get_zero()
     }
 
-    fn sed(&mut self, _ : Mode) {
+    fn sed(&mut self) {
         self.set_decimal(true);
     }
 
-    fn sax(&mut self, mode: Mode) {
-        let address = self.operand_address(&mode, false);
-        let result = self.a & self.x;
-        self.write(address, result);
+    fn sax(&mut self) -> u8 {
+        self.a & self.x
     }
 
-    fn dcp(&mut self, mode: Mode) {
-        let address = self.operand_address(&mode, false);
-        let operand = self.read(address);
+    fn dcp(&mut self, operand: u8) -> u8 {
         let result = operand.wrapping_sub(1);
-        self.write(address, result);
-        let a = self.a;
-        self.set_zero((a.wrapping_sub(result) as u8) == 0);
-        self.set_negative((a.wrapping_sub(result) & 0b10000000) != 0);
-        self.set_carry(a >= result);
+        self.cmp(result);
+        result
     }
 
-    fn isc(&mut self, mode: Mode) {
-        //Incrementing
-        let address = self.operand_address(&mode, false);
-        let operand = self.read(address);
+    fn isc(&mut self, operand: u8) -> u8 {
         let result = operand.wrapping_add(1);
-        self.write(address, result);
-        
-        //reasigning operand
-        let operand = !result;
-
-        let a = self.a;
-        let result = a as u16 + operand as u16 + self.get_carry() as u16;
-        self.set_carry(result > 0xFF);
-        self.set_overflow((a ^ (result as u8)) & (operand ^ (result as u8)) & 0x80 != 0);
-        self.set_zero(result == 0);
-        self.set_negative((result & 0b10000000) != 0);
-        self.a = result as u8;
+        self.sbc(result);
+        result
     }
 
-    fn slo(&mut self, mode: Mode) {
-        let result = self.a | self.asl_ret(&mode);
-        self.set_zero((result & 0x00ff) == 0x00);
-        self.set_negative((result & 0x80) > 0);
-        self.a = result;
+    fn slo(&mut self, operand: u8) -> u8 {
+        let result = self.asl(operand);
+        self.ora(result);
+        result
     }
 
-    fn rla(&mut self, mode: Mode) {
-        let result = self.a & self.rol_ret(&mode);
-        self.set_zero((result as u8) == 0);
-        self.set_negative((result & 0x80) > 0);
-        self.a = result;
+    fn rla(&mut self, operand: u8) -> u8 {
+        let result = self.rol(operand);
+        self.and(result);
+        result
     }
     
-    fn sre(&mut self, mode: Mode) {
-        let result = self.a ^ self.lsr_ret(&mode);
-        self.set_zero((result as u8) == 0);
-        self.set_negative((result & 0x80) > 0);
-        self.a = result;
+    fn sre(&mut self, operand: u8) -> u8 {
+        let result = self.lsr(operand);
+        self.eor(result);
+        result
     }
 
-    fn rra(&mut self, mode: Mode) {
-        let a = self.a;
-        let operand = self.ror_ret(&mode);
-        let result = a as u16 + operand as u16 + self.get_carry() as u16;
-        self.set_carry(result > 0xFF);
-        self.set_overflow((a ^ (result as u8)) & (operand ^ (result as u8)) & 0x80 != 0);
-        self.set_zero((result as u8) == 0);
+    fn rra(&mut self, operand: u8) -> u8 {
+        let result = self.ror(operand);
+        self.adc(result);
+        result
+    }
+
+    // 65C02 instructions.
+    fn bra(&mut self) -> bool {
+        true
+    }
+
+    fn bit_immediate(&mut self, operand: u8) {
+        self.set_zero(self.a & operand == 0);
+    }
+
+    fn tsb(&mut self, operand: u8) -> u8 {
+        self.set_zero(self.a & operand == 0);
+        operand | self.a
+    }
+
+    fn trb(&mut self, operand: u8) -> u8 {
+        self.set_zero(self.a & operand == 0);
+        operand & !self.a
+    }
+
+    fn phx(&mut self) -> u8 {
+        self.x
+    }
+
+    fn phy(&mut self) -> u8 {
+        self.y
+    }
+
+    fn plx(&mut self, result: u8) {
+        self.ldx(result);
+    }
+
+    fn ply(&mut self, result: u8) {
+        self.ldy(result);
+    }
+
+    fn stz(&mut self) -> u8 {
+        0
+    }
+
+    fn anc(&mut self, operand: u8) {
+        self.and(operand);
+        let negative = self.get_negative();
+        self.set_carry(negative);
+    }
+
+    fn alr(&mut self, operand: u8) {
+        self.and(operand);
+        self.a = self.lsr(self.a);
+    }
+
+    fn arr(&mut self, operand: u8) {
+        self.and(operand);
+        self.a = self.ror(self.a);
+        let result = self.a;
+        self.set_carry(result & 0b01000000 != 0);
+        self.set_overflow(((result >> 6) ^ (result >> 5)) & 1 != 0);
+    }
+
+    fn axs(&mut self, operand: u8) {
+        let value = self.a & self.x;
+        let result = value.wrapping_sub(operand);
+        self.set_carry(value >= operand);
+        self.set_zero(result == 0);
         self.set_negative((result & 0x80) > 0);
-        self.a = result as u8;
+        self.x = result;
     }
 
-    //Helper functions.
+    fn xaa(&mut self, operand: u8) {
+        let result = (self.a | MAGIC_CONSTANT) & self.x & operand;
+        self.lda(result);
+    }
 
-    pub fn complete(&mut self) -> bool {
-        self.cycles == 0
+    fn lxa(&mut self, operand: u8) {
+        let result = (self.a | MAGIC_CONSTANT) & operand;
+        self.lax(result);
     }
 
-    fn branch(&mut self, condition: bool) {
-        let x = Mode::Immediate;
-        let offset = self.read_operand(&x, true) as i8 as u16;
-        if condition {
-            self.cycles += 1;
-            let absolute_addr = self.pc.wrapping_add(offset as u16);
-            if (absolute_addr & 0xFF00) != (self.pc & 0xFF00) { self.cycles += 1};
-            self.pc = absolute_addr
-        }
+    fn las(&mut self, operand: u8) {
+        let result = operand & self.sp;
+        self.lax(result);
+        self.sp = result;
     }
 
-    fn read_operand(&mut self, mode: &Mode, needs_additional_cycle: bool) -> u8 {
-        let address: u16 = self.operand_address(mode, needs_additional_cycle);
-        self.read(address)
+    fn shy(&mut self) -> u8 {
+        self.unstable_store(self.y)
     }
 
-    fn operand_address(&mut self, mode: &Mode, needs_additional_cycle: bool) -> u16 {
-        match mode {
-            Mode::Immediate => {
-                let original_pc = self.pc;
-                self.increment_pc();
-                original_pc
-            }
-            Mode::ZeroPage => self.next_byte() as u16,
-            Mode::ZeroPageX => {
-                low_byte(offset(self.next_byte(), self.x))
-            }
-            Mode::ZeroPageY => {
-                low_byte(offset(self.next_byte(), self.y))
-            }
-            Mode::Absolute => self.next_word(),
-            Mode::AbsoluteX => {
-                let base = self.next_word();
-                let res = offset(base, self.x);
-                if ((base & 0xFF00) != (res & 0xFF00)) && needs_additional_cycle {
-                    self.cycles += 1
-                }
-                res
-            }
-            Mode::AbsoluteY => {
-                let base = self.next_word();
-                let res = offset(base, self.y);
-                if ((base & 0xFF00) != (res & 0xFF00)) && needs_additional_cycle {
-                    self.cycles += 1
-                }
-                res
-            }
-            Mode::Indirect => {
-                let i = self.next_word();
-                let x = self.read(i);
-                let y = self.read(high_byte(i) | low_byte(i + 1));
-                return ((y as u16) << 8) | (x as u16);
-            }
-            Mode::IndirectX => {
-                let i = offset(self.next_byte(), self.x);
-                let x = self.read(low_byte(i));
-                let y = self.read(low_byte(i + 1));
-                return ((y as u16) << 8) | (x as u16);
-            }
-            Mode::IndirectY => {
-                let i = self.next_byte();
-                let x = self.read(i as u16);
-                let y = self.read(low_byte(i.wrapping_add(1)));
-                let base = ((y as u16) << 8) | (x as u16);
-                let res = offset(base, self.y);
-                if ((base & 0xFF00) != (res & 0xFF00)) && needs_additional_cycle {
-                    self.cycles += 1
-                }
-                res
-            }
-            _ => panic!("Error: Unknown mode to read from memory"),
+    fn shx(&mut self) -> u8 {
+        self.unstable_store(self.x)
+    }
+
+    fn sha(&mut self) -> u8 {
+        self.unstable_store(self.a & self.x)
+    }
+
+    fn tas(&mut self) -> u8 {
+        self.sp = self.a & self.x;
+        self.unstable_store(self.sp)
+    }
+
+    // SHY/SHX/SHA/TAS store the value ANDed with the high byte of the base
+    // address plus one. When indexing crosses a page that value also replaces
+    // the high byte of the address written to.
+    fn unstable_store(&mut self, value: u8) -> u8 {
+        let mut high = (self.addr >> 8) as u8;
+        if !self.page_crossed {
+            high = high.wrapping_add(1);
         }
+        let result = value & high;
+        if self.page_crossed {
+            self.addr = ((result as u16) << 8) | low_byte(self.addr);
+        }
+        result
+    }
+
+    //Helper functions.
+
+    pub fn variant(&self) -> CpuVariant {
+        self.variant
+    }
+
+    // The opcode table for this CPU's variant.
+    pub fn opcodes(&self) -> &[OpcodeInfo<B>; 256] {
+        &self.opcodes
+    }
+
+    pub fn complete(&self) -> bool {
+        self.step == 0
+    }
+
+    // True once a JAM opcode locked the CPU up, only a reset gets it going again.
+    pub fn halted(&self) -> bool {
+        matches!(self.operation, Operation::Jam) && self.step != 0
     }
 
     fn increment_pc(&mut self) {
@@ -919,23 +1642,17 @@ impl CPU {
         self.read(original_pc)
     }
 
-    fn next_word(&mut self) -> u16 {
-        let original_pc: u16 = self.pc;
-        self.increment_pc();
-        self.increment_pc();
-        let x = self.read(original_pc);
-        let y = self.read(original_pc+1);
-        return ((y as u16) << 8) | (x as u16);
-    }
-
+    // The stack wraps around within page 1 like on the real chip.
     fn push_to_stack(&mut self, val: u8) {
         self.write(0x100 + (self.sp as u16), val);
-        self.sp -= 1;
+        self.sp = self.sp.wrapping_sub(1);
     }
 
     fn pop_from_stack(&mut self) -> u8 {
-        self.sp += 1;
-        self.read(0x100 + (self.sp as u16))
+        self.sp = self.sp.wrapping_add(1);
+        let addr = 0x100 + (self.sp as u16);
+        self.bus.code_data(addr, cdl::DATA);
+        self.read(addr)
     }
 
     //Read are write functions are here to make CPU struct project-independent.
@@ -947,135 +1664,57 @@ impl CPU {
         self.bus.write(addr, data);
     }
 
-    pub fn interrupt(&mut self, interrupt_type: Interrupt) {
-        match interrupt_type {
-            Interrupt::Reset => self.reset(),
-            Interrupt::Irq => self.irq(),
-            Interrupt::Nmi => self.nmi(),
-            Interrupt::Break => {
-                self.pc -= 1;
-                self.brk(); //Calling BREAK instruction. BREAK instruction increments pc by 1 where as interrupt does not.
-            }
-        }
-    }
-
-    //private Interrupt functions.
-    fn reset(&mut self) {
-        let lo: u8 = self.read(0xFFFC);
-        let hi: u8 = self.read(0xFFFC + 1);
-        self.pc = (hi as u16) << 8 | (lo as u16);
-
+    // Power switch: registers go to their power up values, the bus gets its
+    // power_on call and the reset sequence then loads PC from $FFFC.
+    pub fn power_on(&mut self) {
         self.a = 0;
         self.x = 0;
         self.y = 0;
-        self.sp = 0xFD;
+        // The reset sequence takes SP down by 3, to $FD.
+        self.sp = 0x00;
         self.p = 0b00100100;
-
-        //Reset takes time.
-        self.cycles = 8;
+        self.cycle_count = 0;
+        self.irq_lines = 0;
+        self.nmi_line = false;
+        self.irq_detected = false;
+        self.bus.power_on();
+        self.soft_reset();
     }
 
-    fn irq(&mut self) {
-        if self.get_interrupt_disable() == false {
-            self.push_to_stack((self.pc >> 8) as u8);
-            self.push_to_stack(self.pc as u8);
-
-            self.set_break(false);
-            self.set_unused();
-            self.set_interrupt_disable(true);
-            self.push_to_stack(self.p);
-
-            let lo = self.read(0xFFFE);
-            let hi = self.read(0xFFFE + 1);
-            self.pc = ((hi as u16) << 8) | lo as u16;
-            self.cycles = 7;
-        }
-    }
-
-    fn nmi(&mut self) {
-        self.push_to_stack((self.pc >> 8) as u8);
-        self.push_to_stack(self.pc as u8);
-
-        self.set_break(false);
-        self.set_unused();
-        self.set_interrupt_disable(true);
-        self.push_to_stack(self.p);
-
-        let lo = self.read(0xFFFA);
-        let hi = self.read(0xFFFA + 1);
-        self.pc = ((hi as u16) << 8) | lo as u16;
-        self.cycles = 8;
-    }
-
-    fn brk(&mut self) {
-        self.pc += 1;
-        self.set_interrupt_disable(true);
-        self.push_to_stack((self.pc >> 8) as u8);
-        self.push_to_stack(self.pc as u8);
-
-        self.set_break(true);
-        self.push_to_stack(self.p);
-        self.set_break(false);
-        self.pc = (self.read(0xffff) as u16) << 8 | self.read(0xfffe) as u16;
+    // Reset button: runs the reset sequence in place of the next opcode fetch.
+    // A, X and Y are kept, SP goes down by 3 and I gets set by the sequence
+    // itself. IRQ and NMI come in through the bus lines instead, see poll_interrupts.
+    pub fn soft_reset(&mut self) {
+        self.step = 0;
+        self.nmi_detected = false;
+        self.pending_interrupt = Some(Interrupt::Reset);
     }
 
     //Get Flags
-    pub fn get_carry(&mut self) -> bool {
-        if (self.p & 0b00000001) == 1 {
-            return true;
-        } else {
-            return false;
-        };
+    pub fn get_carry(&self) -> bool {
+        (self.p & 0b00000001) == 1
     }
 
-    pub fn get_zero(&mut self) -> bool {
-        if (self.p & 0b00000010) == 0b00000010 {
-            return true;
-        } else {
-            return false;
-        };
+    pub fn get_zero(&self) -> bool {
+        (self.p & 0b00000010) == 0b00000010
     }
-    pub fn get_interrupt_disable(&mut self) -> bool {
-        if (self.p & 0b00000100) == 0b00000100 {
-            return true;
-        } else {
-            return false;
-        };
+    pub fn get_interrupt_disable(&self) -> bool {
+        (self.p & 0b00000100) == 0b00000100
     }
-    pub fn get_decimal(&mut self) -> bool {
-        if (self.p & 0b00001000) == 0b00001000 {
-            return true;
-        } else {
-            return false;
-        };
+    pub fn get_decimal(&self) -> bool {
+        (self.p & 0b00001000) == 0b00001000
     }
-    pub fn get_break(&mut self) -> bool {
-        if (self.p & 0b00010000) == 0b00010000 {
-            return true;
-        } else {
-            return false;
-        };
+    pub fn get_break(&self) -> bool {
+        (self.p & 0b00010000) == 0b00010000
     }
-    pub fn get_unsed(&mut self) -> bool {
-        if (self.p & 0b00100000) == 0b00100000 {
-            return true;
-        } else {
-            return false;
-        };
+    pub fn get_unsed(&self) -> bool {
+        (self.p & 0b00100000) == 0b00100000
     }
-    pub fn get_overflow(&mut self) -> bool {
-        if (self.p & 0b01000000) == 0b01000000 {
-            return true;
-        } else {
-            return false;
-        };
+    pub fn get_overflow(&self) -> bool {
+        (self.p & 0b01000000) == 0b01000000
     }
-    pub fn get_negative(&mut self) -> bool {
-        if (self.p & 0b10000000) == 0b10000000 {
-            return true;
-        } else {
-            return false;
-        };
+    pub fn get_negative(&self) -> bool {
+        (self.p & 0b10000000) == 0b10000000
     }
 
     //Set Flags
@@ -1140,20 +1779,13 @@ impl CPU {
     }
 }
 
-fn log_cpu(pc: u16, op: u8, op1: u8, op2: u8, a: u8, x: u8, y: u8, p: u8, sp: u8, tot_cyc: usize) {
-    //TODO: Improve logging. 
-    let mut file = OpenOptions::new()
-                    .write(true)
-                    .create(true)
-                    .append(true)
-                    .open("log.txt")
-                    .unwrap();
-
-                if let Err(e) = writeln!(file, "{:04X}    {:02X} {:02X} {:02X}         A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{}, CYC:{}",pc, op, op1, op2, a, x, y, p, sp, 0, tot_cyc) {
-                    panic!("Couldn't write to file: {}", e);
-                }
-                //Example: C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
-                println!("{:04x}    {:02x} {:02x} {:02x}         A:{:02x} X:{:02x} Y:{:02x} P:{:02x} SP:{:02x} PPU:{}, CYC:{}",pc, op, op1, op2, a, x, y, p, sp, 0, tot_cyc);
+
+fn vector(interrupt: Interrupt) -> u16 {
+    match interrupt {
+        Interrupt::Reset => 0xFFFC,
+        Interrupt::Nmi => 0xFFFA,
+        Interrupt::Irq | Interrupt::Break => 0xFFFE,
+    }
 }
 
 fn high_byte(value: u16) -> u16 {
@@ -1165,5 +1797,5 @@ pub fn low_byte<T: Into<u16>>(value: T) -> u16 {
 }
 
 fn offset<T: Into<u16>>(base: T, offset: u8) -> u16 {
-    base.into().wrapping_add(offset as u16) as u16
+    base.into().wrapping_add(offset as u16)
 }
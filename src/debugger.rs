@@ -0,0 +1,529 @@
+use crate::bus::{ppu_position, Access, AddressSpace, BUS};
+use crate::cpu::{Bus, CPU};
+use crate::disasm::{self, Instruction, Symbols};
+use crate::expr::Expr;
+use std::io::{self, BufRead, Write};
+
+const JSR: u8 = 0x20;
+const RTS: u8 = 0x60;
+// Instructions shown by l, and how many of them come before PC when no
+// address is given.
+const LIST_LENGTH: usize = 12;
+const LIST_CONTEXT: usize = 4;
+const DUMP_LENGTH: u16 = 0x40;
+
+const HELP: &str = "\
+s [N]                  step N instructions
+n                      step, over JSR
+f                      run until the current routine returns
+c                      continue
+fr [N]                 run N frames
+b [ADDR]               add a PC breakpoint, or list breakpoints and watchpoints
+b [ADDR] if EXPR       break (at ADDR) when EXPR is true
+w [ppu] r|w|rw|x ADDR[-ADDR]
+                       watch reads, writes or execution of CPU (or PPU) addresses
+del N                  delete breakpoint or watchpoint N
+r                      registers
+p EXPR                 print the value of EXPR
+disp [EXPR]            print EXPR at every stop, or list those expressions
+undisp N               stop printing expression N
+set REG VALUE          set a, x, y, p, sp, pc or a flag (c z i d b v n) to VALUE
+m [ppu] ADDR [LEN]     hexdump CPU (or PPU) memory
+l [ADDR] [N]           disassemble at ADDR, or around PC
+q                      quit
+Addresses, values and LEN are hex and N is decimal. Addresses can also be labels.
+EXPR is like [$00FE] == 3 && A > $10, over registers, flags (c z i d b v n),
+[ADDR] bytes, {ADDR} words, scanline, dot, frame and cycle, with C's operators.
+Numbers in EXPR are decimal unless they start with $ or 0x.
+An empty line repeats the last command.";
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+    Execute,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Stop {
+    Breakpoint(u16),
+    // At addr, or before any instruction when there's none, if expr isn't 0.
+    Condition {
+        addr: Option<u16>,
+        expr: Expr,
+    },
+    // first..=last.
+    Watchpoint {
+        space: AddressSpace,
+        kind: WatchKind,
+        first: u16,
+        last: u16,
+    },
+}
+
+impl Stop {
+    fn executes(&self, cpu: &CPU<BUS>) -> bool {
+        let pc = cpu.pc;
+        match *self {
+            Stop::Breakpoint(addr) => addr == pc,
+            Stop::Condition { addr, ref expr } => addr.is_none_or(|addr| addr == pc) && expr.eval(cpu) != 0,
+            Stop::Watchpoint { kind: WatchKind::Execute, first, last, .. } => (first..=last).contains(&pc),
+            Stop::Watchpoint { .. } => false,
+        }
+    }
+
+    fn accesses(&self, access: &Access) -> bool {
+        match *self {
+            Stop::Watchpoint { space, kind, first, last } => {
+                let kind_matches = match kind {
+                    WatchKind::Read => !access.write,
+                    WatchKind::Write => access.write,
+                    WatchKind::ReadWrite => true,
+                    WatchKind::Execute => false,
+                };
+                kind_matches && space == access.space && (first..=last).contains(&access.addr)
+            }
+            Stop::Breakpoint(_) | Stop::Condition { .. } => false,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match *self {
+            Stop::Breakpoint(addr) => format!("break ${:04X}", addr),
+            Stop::Condition { addr: Some(addr), ref expr } => format!("break ${:04X} if {}", addr, expr),
+            Stop::Condition { addr: None, ref expr } => format!("break if {}", expr),
+            Stop::Watchpoint { space, kind, first, last } => {
+                let space = if space == AddressSpace::Ppu { "ppu " } else { "" };
+                let kind = match kind {
+                    WatchKind::Read => "r",
+                    WatchKind::Write => "w",
+                    WatchKind::ReadWrite => "rw",
+                    WatchKind::Execute => "x",
+                };
+                format!("watch {}{} ${:04X}-${:04X}", space, kind, first, last)
+            }
+        }
+    }
+}
+
+// Why Debugger::run came back.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Halt {
+    // The run's own condition was met.
+    Done,
+    // Index in stops, with the access that hit a read/write watchpoint.
+    Stop(usize, Option<Access>),
+    Jammed,
+}
+
+// Command line debugger for main.rs --debug. Commands go through command(),
+// repl() reads them from stdin.
+pub struct Debugger {
+    pub stops: Vec<Stop>,
+    pub symbols: Option<Symbols>,
+    // Printed at every stop.
+    pub displays: Vec<Expr>,
+    last_command: String,
+}
+
+impl Debugger {
+    pub fn new(symbols: Option<Symbols>) -> Self {
+        Debugger {
+            stops: Vec::new(),
+            symbols,
+            displays: Vec::new(),
+            last_command: String::new(),
+        }
+    }
+
+    pub fn repl(&mut self, cpu: &mut CPU<BUS>) {
+        println!("{}", self.location(cpu));
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+        loop {
+            print!("> ");
+            io::stdout().flush().expect("Error: Cannot write to stdout");
+            let line = match lines.next() {
+                Some(line) => line.expect("Error: Cannot read from stdin"),
+                None => break,
+            };
+            match self.command(cpu, &line) {
+                Some(output) => println!("{}", output),
+                None => break,
+            }
+        }
+    }
+
+    // Runs one command line and returns what it printed, None when it was q.
+    pub fn command(&mut self, cpu: &mut CPU<BUS>, line: &str) -> Option<String> {
+        let line = if line.trim().is_empty() { self.last_command.clone() } else { line.trim().to_string() };
+        self.last_command = line.clone();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let output = match words.as_slice() {
+            [] => String::new(),
+            ["q"] | ["quit"] => return None,
+            ["h"] | ["help"] => HELP.to_string(),
+            ["s"] => self.step(cpu, 1),
+            ["s", count] => match count.parse() {
+                Ok(count) if count > 0 => self.step(cpu, count),
+                _ => format!("Bad count {}", count),
+            },
+            ["n"] => self.step_over(cpu),
+            ["f"] => self.finish(cpu),
+            ["c"] => {
+                let halt = self.run(cpu, |_, _| false);
+                self.report(cpu, halt)
+            }
+            ["fr"] => self.frames(cpu, 1),
+            ["fr", count] => match count.parse() {
+                Ok(count) => self.frames(cpu, count),
+                Err(_) => format!("Bad frame count {}", count),
+            },
+            ["b"] => self.list_stops(),
+            ["b", addr] => match self.parse_address(cpu, addr) {
+                Some(addr) => self.add_stop(Stop::Breakpoint(addr)),
+                None => format!("Bad address {}", addr),
+            },
+            ["b", "if", expr @ ..] => self.add_condition(cpu, None, expr),
+            ["b", addr, "if", expr @ ..] => match self.parse_address(cpu, addr) {
+                Some(addr) => self.add_condition(cpu, Some(addr), expr),
+                None => format!("Bad address {}", addr),
+            },
+            ["w", rest @ ..] => self.watch(cpu, rest),
+            ["del", index] => match index.parse::<usize>() {
+                Ok(index) if index >= 1 && index <= self.stops.len() => {
+                    format!("Deleted {}", self.stops.remove(index - 1).describe())
+                }
+                _ => format!("No breakpoint or watchpoint {}", index),
+            },
+            ["r"] => registers(cpu),
+            ["p", expr @ ..] if !expr.is_empty() => match self.parse_expr(cpu, expr) {
+                Ok(expr) => format_value(expr.eval(cpu)),
+                Err(error) => error,
+            },
+            ["disp"] => self.list_displays(cpu),
+            ["disp", expr @ ..] => match self.parse_expr(cpu, expr) {
+                Ok(expr) => {
+                    self.displays.push(expr);
+                    self.list_displays(cpu)
+                }
+                Err(error) => error,
+            },
+            ["undisp", index] => match index.parse::<usize>() {
+                Ok(index) if index >= 1 && index <= self.displays.len() => {
+                    format!("Deleted {}", self.displays.remove(index - 1))
+                }
+                _ => format!("No expression {}", index),
+            },
+            ["set", name, value] => match parse_hex(value) {
+                Some(value) => set_register(cpu, name, value),
+                None => format!("Bad value {}", value),
+            },
+            ["m", rest @ ..] => self.dump(cpu, rest),
+            ["l"] => self.list_around_pc(cpu),
+            ["l", addr] => self.list(cpu, addr, LIST_LENGTH),
+            ["l", addr, count] => match count.parse() {
+                Ok(count) => self.list(cpu, addr, count),
+                Err(_) => format!("Bad count {}", count),
+            },
+            _ => format!("Unknown command {}, h for help", line),
+        };
+        Some(output)
+    }
+
+    fn step(&mut self, cpu: &mut CPU<BUS>, count: usize) -> String {
+        let mut left = count;
+        let halt = self.run(cpu, |_, _| {
+            left -= 1;
+            left == 0
+        });
+        self.report(cpu, halt)
+    }
+
+    fn step_over(&mut self, cpu: &mut CPU<BUS>) -> String {
+        if cpu.bus.peek(cpu.pc) != JSR {
+            return self.step(cpu, 1);
+        }
+        let return_address = cpu.pc.wrapping_add(3);
+        let sp = cpu.sp;
+        let halt = self.run(cpu, |cpu, _| cpu.pc == return_address && cpu.sp == sp);
+        self.report(cpu, halt)
+    }
+
+    // Until an RTS takes SP back above where it is now, which is the current
+    // routine's own return as long as it pulls what it pushes.
+    fn finish(&mut self, cpu: &mut CPU<BUS>) -> String {
+        let sp = cpu.sp.wrapping_add(2);
+        let halt = self.run(cpu, |cpu, opcode| opcode == RTS && cpu.sp == sp);
+        self.report(cpu, halt)
+    }
+
+    fn frames(&mut self, cpu: &mut CPU<BUS>, count: usize) -> String {
+        let frame = ppu_position(cpu.cycle_count).0 + count;
+        let halt = self.run(cpu, |cpu, _| ppu_position(cpu.cycle_count).0 >= frame);
+        self.report(cpu, halt)
+    }
+
+    // Runs instructions until done, given the opcode that just ran, says to
+    // stop, or a breakpoint or watchpoint hits. A breakpoint on the first
+    // instruction doesn't count so that running on from one works.
+    pub fn run(&mut self, cpu: &mut CPU<BUS>, mut done: impl FnMut(&CPU<BUS>, u8) -> bool) -> Halt {
+        let watching = self.stops.iter().any(|stop| matches!(stop, Stop::Watchpoint { kind, .. } if *kind != WatchKind::Execute));
+        let mut first = true;
+        loop {
+            if !first {
+                if let Some(i) = self.stops.iter().position(|stop| stop.executes(cpu)) {
+                    return Halt::Stop(i, None);
+                }
+            }
+            first = false;
+
+            let opcode = cpu.bus.peek(cpu.pc);
+            if watching {
+                cpu.bus.accesses = Some(Vec::new());
+            }
+            step_instruction(cpu);
+            let accesses = cpu.bus.accesses.take().unwrap_or_default();
+            for access in accesses {
+                if let Some(i) = self.stops.iter().position(|stop| stop.accesses(&access)) {
+                    return Halt::Stop(i, Some(access));
+                }
+            }
+            if cpu.halted() {
+                return Halt::Jammed;
+            }
+            if done(cpu, opcode) {
+                return Halt::Done;
+            }
+        }
+    }
+
+    fn report(&self, cpu: &CPU<BUS>, halt: Halt) -> String {
+        let report = match halt {
+            Halt::Done => self.location(cpu),
+            Halt::Stop(i, None) => format!("{} {}\n{}", i + 1, self.stops[i].describe(), self.location(cpu)),
+            Halt::Stop(i, Some(access)) => {
+                let action = if access.write { "write" } else { "read" };
+                format!("{} {}: {} ${:02X} at ${:04X}\n{}",
+                        i + 1, self.stops[i].describe(), action, access.data, access.addr, self.location(cpu))
+            }
+            Halt::Jammed => format!("CPU jammed at ${:04X}", cpu.pc.wrapping_sub(1)),
+        };
+        if self.displays.is_empty() {
+            report
+        } else {
+            format!("{}\n{}", report, self.list_displays(cpu))
+        }
+    }
+
+    fn add_stop(&mut self, stop: Stop) -> String {
+        let text = format!("{} {}", self.stops.len() + 1, stop.describe());
+        self.stops.push(stop);
+        text
+    }
+
+    fn add_condition(&mut self, cpu: &CPU<BUS>, addr: Option<u16>, words: &[&str]) -> String {
+        match self.parse_expr(cpu, words) {
+            Ok(expr) => self.add_stop(Stop::Condition { addr, expr }),
+            Err(error) => error,
+        }
+    }
+
+    fn list_displays(&self, cpu: &CPU<BUS>) -> String {
+        if self.displays.is_empty() {
+            return String::from("No expressions");
+        }
+        let lines: Vec<String> = self.displays.iter().enumerate()
+            .map(|(i, expr)| format!("{} {} = {}", i + 1, expr, format_value(expr.eval(cpu))))
+            .collect();
+        lines.join("\n")
+    }
+
+    fn list_stops(&self) -> String {
+        if self.stops.is_empty() {
+            return String::from("No breakpoints or watchpoints");
+        }
+        let lines: Vec<String> = self.stops.iter().enumerate().map(|(i, stop)| format!("{} {}", i + 1, stop.describe())).collect();
+        lines.join("\n")
+    }
+
+    fn watch(&mut self, cpu: &CPU<BUS>, words: &[&str]) -> String {
+        let (space, words) = match words {
+            ["ppu", rest @ ..] => (AddressSpace::Ppu, rest),
+            _ => (AddressSpace::Cpu, words),
+        };
+        let (kind, range) = match words {
+            [kind, range] => (*kind, *range),
+            _ => return String::from("Usage: w [ppu] r|w|rw|x ADDR[-ADDR]"),
+        };
+        let kind = match kind {
+            "r" => WatchKind::Read,
+            "w" => WatchKind::Write,
+            "rw" => WatchKind::ReadWrite,
+            "x" if space == AddressSpace::Cpu => WatchKind::Execute,
+            _ => return format!("Bad watch kind {}", kind),
+        };
+        let (first, last) = range.split_once('-').unwrap_or((range, range));
+        match (self.parse_address(cpu, first), self.parse_address(cpu, last)) {
+            (Some(first), Some(last)) if first <= last => self.add_stop(Stop::Watchpoint { space, kind, first, last }),
+            _ => format!("Bad address range {}", range),
+        }
+    }
+
+    fn dump(&self, cpu: &CPU<BUS>, words: &[&str]) -> String {
+        let (space, words) = match words {
+            ["ppu", rest @ ..] => (AddressSpace::Ppu, rest),
+            _ => (AddressSpace::Cpu, words),
+        };
+        let (addr, length) = match words {
+            [addr] => (self.parse_address(cpu, addr), Some(DUMP_LENGTH)),
+            [addr, length] => (self.parse_address(cpu, addr), parse_hex(length)),
+            _ => return String::from("Usage: m [ppu] ADDR [LEN]"),
+        };
+        let (addr, length) = match (addr, length) {
+            (Some(addr), Some(length)) => (addr, length),
+            _ => return String::from("Bad address or length"),
+        };
+        let peek = |addr: u16| match space {
+            AddressSpace::Cpu => cpu.bus.peek(addr),
+            AddressSpace::Ppu => cpu.bus.ppu_peek(addr),
+        };
+
+        let mut lines = Vec::new();
+        for row in (0..length).step_by(16) {
+            let row_addr = addr.wrapping_add(row);
+            let bytes: Vec<u8> = (0..16.min(length - row)).map(|i| peek(row_addr.wrapping_add(i))).collect();
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            let text: String = bytes.iter().map(|&b| if (0x20..0x7F).contains(&b) { b as char } else { '.' }).collect();
+            lines.push(format!("{:04X}  {:<47}  {}", row_addr, hex.join(" "), text));
+        }
+        lines.join("\n")
+    }
+
+    fn list(&self, cpu: &CPU<BUS>, addr: &str, count: usize) -> String {
+        match self.parse_address(cpu, addr) {
+            Some(addr) => {
+                let instructions = disasm::disassemble_range(&cpu.bus, cpu.opcodes(), addr, count, self.symbols.as_ref());
+                self.format_listing(cpu, &instructions)
+            }
+            None => format!("Bad address {}", addr),
+        }
+    }
+
+    // Code can't be decoded backwards for sure, so this looks for the
+    // furthest start before PC that decodes into an instruction ending right
+    // at PC and shows the last few of those.
+    fn list_around_pc(&self, cpu: &CPU<BUS>) -> String {
+        let symbols = self.symbols.as_ref();
+        let mut before = Vec::new();
+        for distance in (1..=LIST_CONTEXT as u16 * 3).rev() {
+            let mut addr = cpu.pc.wrapping_sub(distance);
+            let mut decoded = Vec::new();
+            while cpu.pc.wrapping_sub(addr) <= distance && addr != cpu.pc {
+                let instruction = disasm::disassemble(&cpu.bus, cpu.opcodes(), addr, symbols);
+                addr = instruction.next();
+                decoded.push(instruction);
+            }
+            if addr == cpu.pc {
+                before = decoded.split_off(decoded.len().saturating_sub(LIST_CONTEXT));
+                break;
+            }
+        }
+        let after = disasm::disassemble_range(&cpu.bus, cpu.opcodes(), cpu.pc, LIST_LENGTH - before.len(), symbols);
+        before.extend(after);
+        self.format_listing(cpu, &before)
+    }
+
+    fn format_listing(&self, cpu: &CPU<BUS>, instructions: &[Instruction]) -> String {
+        let mut lines = Vec::new();
+        for instruction in instructions {
+            if let Some(label) = self.symbols.as_ref().and_then(|s| s.label(&cpu.bus, instruction.addr)) {
+                lines.push(format!("{}:", label));
+            }
+            let marker = if instruction.addr == cpu.pc { '>' } else { ' ' };
+            lines.push(format!("{} {}", marker, instruction.listing()));
+        }
+        lines.join("\n")
+    }
+
+    // Current instruction and registers.
+    fn location(&self, cpu: &CPU<BUS>) -> String {
+        let instruction = disasm::disassemble(&cpu.bus, cpu.opcodes(), cpu.pc, self.symbols.as_ref());
+        format!("{:<40}{}", instruction.listing(), registers(cpu))
+    }
+
+    // The rest of the command line as an expression.
+    fn parse_expr(&self, cpu: &CPU<BUS>, words: &[&str]) -> Result<Expr, String> {
+        Expr::parse(&words.join(" "), &|label| self.symbols.as_ref()?.address(&cpu.bus, label))
+    }
+
+    // Hex, or a label when symbols are loaded.
+    fn parse_address(&self, cpu: &CPU<BUS>, text: &str) -> Option<u16> {
+        parse_hex(text).or_else(|| self.symbols.as_ref()?.address(&cpu.bus, text))
+    }
+}
+
+// Whole CPU cycles until the instruction (or interrupt sequence) is over.
+// Three PPU dots go by per CPU cycle, like in main's loop.
+fn step_instruction(cpu: &mut CPU<BUS>) {
+    loop {
+        cpu.bus.system_clock_count += 3;
+        cpu.clock();
+        if cpu.complete() || cpu.halted() {
+            break;
+        }
+    }
+}
+
+fn registers(cpu: &CPU<BUS>) -> String {
+    let flags = [
+        (cpu.get_negative(), 'N'),
+        (cpu.get_overflow(), 'V'),
+        (cpu.get_unsed(), 'U'),
+        (cpu.get_break(), 'B'),
+        (cpu.get_decimal(), 'D'),
+        (cpu.get_interrupt_disable(), 'I'),
+        (cpu.get_zero(), 'Z'),
+        (cpu.get_carry(), 'C'),
+    ];
+    let flags: String = flags.iter().map(|&(set, flag)| if set { flag } else { flag.to_ascii_lowercase() }).collect();
+    let (frame, scanline, dot) = ppu_position(cpu.cycle_count);
+    format!("A:{:02X} X:{:02X} Y:{:02X} P:{:02X} {} SP:{:02X} PC:{:04X} CYC:{} frame {} scanline {} dot {}",
+            cpu.a, cpu.x, cpu.y, cpu.p, flags, cpu.sp, cpu.pc, cpu.cycle_count, frame, scanline, dot)
+}
+
+fn set_register(cpu: &mut CPU<BUS>, name: &str, value: u16) -> String {
+    let byte = value as u8;
+    let flag = value != 0;
+    match name {
+        "a" => cpu.a = byte,
+        "x" => cpu.x = byte,
+        "y" => cpu.y = byte,
+        "p" => cpu.p = byte,
+        "sp" => cpu.sp = byte,
+        "pc" => cpu.pc = value,
+        "c" => cpu.set_carry(flag),
+        "z" => cpu.set_zero(flag),
+        "i" => cpu.set_interrupt_disable(flag),
+        "d" => cpu.set_decimal(flag),
+        "b" => cpu.set_break(flag),
+        "v" => cpu.set_overflow(flag),
+        "n" => cpu.set_negative(flag),
+        _ => return format!("Unknown register {}", name),
+    }
+    registers(cpu)
+}
+
+// $03 (3), with as many hex digits as the value needs.
+fn format_value(value: i64) -> String {
+    match value {
+        0..=0xFF => format!("${:02X} ({})", value, value),
+        0x100..=0xFFFF => format!("${:04X} ({})", value, value),
+        _ => value.to_string(),
+    }
+}
+
+fn parse_hex(text: &str) -> Option<u16> {
+    u16::from_str_radix(text.trim_start_matches('$'), 16).ok()
+}
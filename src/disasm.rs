@@ -0,0 +1,311 @@
+use crate::cpu::{Bus, Mode, OpcodeInfo};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// Size of the iNES header in front of PRG ROM in the files ca65 links.
+const INES_HEADER_SIZE: usize = 16;
+// Smallest PRG bank size mappers switch, the FDS and NSF one.
+const ROM_SLOT_SIZE: usize = 0x1000;
+// Where Mesen's save and work RAM offsets start on the CPU bus.
+const PRG_RAM_START: u16 = 0x6000;
+
+// Labels for addresses, used in place of the numbers in operands. Labels in
+// PRG ROM can be tied to their offset in it, so that the bank mapped at an
+// address decides which of them applies there.
+#[derive(Clone, Default, Debug)]
+pub struct Symbols {
+    labels: HashMap<u16, String>,
+    rom_labels: HashMap<usize, RomLabel>,
+}
+
+#[derive(Clone, Debug)]
+struct RomLabel {
+    name: String,
+    // Where it was assembled to, when the symbol file says.
+    addr: Option<u16>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Symbols::default()
+    }
+
+    // Plain text, one "C000 reset" per line. Anything after ; or # is a comment.
+    pub fn parse(text: &str) -> Symbols {
+        let mut symbols = Symbols::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split([';', '#']).next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let addr = fields.next().unwrap().trim_start_matches('$');
+            let addr = u16::from_str_radix(addr, 16).unwrap_or_else(|_| panic!("Error: Bad address on symbol line {}", number + 1));
+            let label = fields.next().unwrap_or_else(|| panic!("Error: No label on symbol line {}", number + 1));
+            symbols.insert(addr, label);
+        }
+        symbols
+    }
+
+    // ld65 --dbgfile output. Labels in segments that went into the ROM file
+    // get their PRG ROM offset from the segment's, which counts the iNES
+    // header. Equates and imports are left out.
+    //   seg  id=0,name="CODE",start=0x00C000,size=0x0010,addrsize=absolute,type=ro,oname="game.nes",ooffs=16
+    //   sym  id=0,name="reset",addrsize=absolute,scope=0,def=1,val=0xC000,seg=0,type=lab
+    pub fn parse_dbg(text: &str) -> Symbols {
+        let mut segments = HashMap::new();
+        let mut labels = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let (kind, fields) = match line.split_once(char::is_whitespace) {
+                Some((kind, fields)) if kind == "seg" || kind == "sym" => (kind, dbg_fields(fields)),
+                _ => continue,
+            };
+            let field = |name: &str| fields.iter().find(|(key, _)| *key == name).map(|(_, value)| *value);
+            let number_field = |name: &str| {
+                field(name).map(|value| parse_dbg_number(value).unwrap_or_else(|| panic!("Error: Bad {} on debug info line {}", name, number + 1)))
+            };
+            let id = number_field("id").unwrap_or_else(|| panic!("Error: No id on debug info line {}", number + 1));
+            if kind == "seg" {
+                let start = number_field("start").unwrap_or(0);
+                segments.insert(id, (start, number_field("ooffs")));
+            } else if field("type") == Some("lab") {
+                let name = field("name").unwrap_or_else(|| panic!("Error: No name on debug info line {}", number + 1));
+                let addr = number_field("val").unwrap_or_else(|| panic!("Error: No value on debug info line {}", number + 1));
+                labels.push((name.to_string(), addr as u16, number_field("seg")));
+            }
+        }
+
+        let mut symbols = Symbols::new();
+        for (name, addr, segment) in labels {
+            let offset = match segment.and_then(|id| segments.get(&id)) {
+                Some(&(start, Some(ooffs))) if ooffs >= INES_HEADER_SIZE => Some(ooffs - INES_HEADER_SIZE + addr as usize - start),
+                _ => None,
+            };
+            match offset {
+                Some(offset) => symbols.insert_rom(offset, &name, Some(addr)),
+                None => symbols.insert(addr, &name),
+            }
+        }
+        symbols
+    }
+
+    // NESASM's .fns, "reset = $C000" per line and ; comments. There are no
+    // banks in it, so its labels apply whatever is mapped.
+    pub fn parse_fns(text: &str) -> Symbols {
+        let mut symbols = Symbols::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (label, addr) = line.split_once('=').unwrap_or_else(|| panic!("Error: No = on symbol line {}", number + 1));
+            let addr = u16::from_str_radix(addr.trim().trim_start_matches('$'), 16)
+                .unwrap_or_else(|_| panic!("Error: Bad address on symbol line {}", number + 1));
+            symbols.insert(addr, label.trim());
+        }
+        symbols
+    }
+
+    // Mesen's .mlb, "TYPE:ADDR[-END]:LABEL[:COMMENT]" per line. P (or
+    // NesPrgRom) addresses are PRG ROM offsets, S and W ones are offsets
+    // into PRG RAM at $6000, R and G ones are CPU addresses. Lines without a
+    // label only carry a comment and are skipped.
+    pub fn parse_mlb(text: &str) -> Symbols {
+        let mut symbols = Symbols::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.splitn(4, ':').collect();
+            let (kind, addr, label) = match fields.as_slice() {
+                [kind, addr, label, ..] => (*kind, *addr, *label),
+                _ => panic!("Error: Bad label line {}", number + 1),
+            };
+            if label.is_empty() {
+                continue;
+            }
+            let start = addr.split('-').next().unwrap();
+            let offset = usize::from_str_radix(start, 16).unwrap_or_else(|_| panic!("Error: Bad address on label line {}", number + 1));
+            match kind {
+                "P" | "NesPrgRom" => symbols.insert_rom(offset, label, None),
+                "S" | "W" | "NesSaveRam" | "NesWorkRam" => symbols.insert(PRG_RAM_START.wrapping_add(offset as u16), label),
+                "R" | "G" | "NesInternalRam" | "NesMemory" => symbols.insert(offset as u16, label),
+                // CHR, palette and the like aren't CPU addresses.
+                _ => {}
+            }
+        }
+        symbols
+    }
+
+    // The format goes by the extension: .dbg, .fns, .mlb or else plain text.
+    pub fn load(path: &str) -> Symbols {
+        let text = fs::read_to_string(path).expect("Error: Cannot read symbol file");
+        match Path::new(path).extension().and_then(|extension| extension.to_str()) {
+            Some("dbg") => Symbols::parse_dbg(&text),
+            Some("fns") => Symbols::parse_fns(&text),
+            Some("mlb") => Symbols::parse_mlb(&text),
+            _ => Symbols::parse(&text),
+        }
+    }
+
+    pub fn insert(&mut self, addr: u16, label: &str) {
+        self.labels.insert(addr, label.to_string());
+    }
+
+    // A label at offset in PRG ROM, and where it was assembled to if known.
+    pub fn insert_rom(&mut self, offset: usize, label: &str, addr: Option<u16>) {
+        self.rom_labels.insert(offset, RomLabel { name: label.to_string(), addr });
+    }
+
+    // The label of the PRG ROM byte bus has at addr right now, or else of
+    // addr itself.
+    pub fn label<B: Bus>(&self, bus: &B, addr: u16) -> Option<&str> {
+        let rom_label = bus.prg_rom_offset(addr).and_then(|offset| self.rom_labels.get(&offset));
+        match rom_label {
+            Some(rom_label) => Some(&rom_label.name),
+            None => self.labels.get(&addr).map(String::as_str),
+        }
+    }
+
+    // First address with this label. PRG ROM labels go where their bank is
+    // mapped right now, the highest address when it's mirrored, or where
+    // they were assembled to when it isn't mapped.
+    pub fn address<B: Bus>(&self, bus: &B, label: &str) -> Option<u16> {
+        let addr = self.labels.iter().filter(|(_, l)| l.as_str() == label).map(|(&addr, _)| addr).min();
+        if addr.is_some() {
+            return addr;
+        }
+        let (&offset, rom_label) = self.rom_labels.iter().filter(|(_, l)| l.name == label).min_by_key(|(&offset, _)| offset)?;
+        (0x8000..=0xF000u16).rev()
+            .step_by(ROM_SLOT_SIZE)
+            .map(|slot| slot | (offset % ROM_SLOT_SIZE) as u16)
+            .find(|&addr| bus.prg_rom_offset(addr) == Some(offset))
+            .or(rom_label.addr)
+    }
+
+    pub fn len(&self) -> usize {
+        self.labels.len() + self.rom_labels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty() && self.rom_labels.is_empty()
+    }
+}
+
+// "name=\"a,b\",val=0x10" into its keys and values, without the quotes.
+fn dbg_fields(text: &str) -> Vec<(&str, &str)> {
+    let mut fields = Vec::new();
+    let mut rest = text.trim();
+    while !rest.is_empty() {
+        let (key, value) = rest.split_once('=').unwrap_or((rest, ""));
+        let (value, next) = match value.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"').unwrap_or(quoted.len());
+                (&quoted[..end], quoted[end..].trim_start_matches('"'))
+            }
+            None => value.split_once(',').map_or((value, ""), |(value, next)| (value, next)),
+        };
+        fields.push((key, value));
+        rest = next.trim_start_matches(',');
+    }
+    fields
+}
+
+fn parse_dbg_number(text: &str) -> Option<usize> {
+    match text.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+// One decoded instruction.
+#[derive(Clone, Debug)]
+pub struct Instruction {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    pub mnemonic: &'static str,
+    pub mode: Mode,
+    pub official: bool,
+    // Where a branch or JMP/JSR absolute goes.
+    pub target: Option<u16>,
+    // e.g. "LDA ($10),Y" or "BNE loop".
+    pub text: String,
+}
+
+impl Instruction {
+    // C000  4C F5 C5  JMP $C5F5, with a * before unofficial opcodes.
+    pub fn listing(&self) -> String {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        let mark = if self.official { ' ' } else { '*' };
+        format!("{:04X}  {:<8} {}{}", self.addr, bytes.join(" "), mark, self.text)
+    }
+
+    pub fn next(&self) -> u16 {
+        self.addr.wrapping_add(self.bytes.len() as u16)
+    }
+}
+
+// Decodes the instruction at addr. Only peeks at memory, so it can be used on
+// I/O space without disturbing anything.
+pub fn disassemble<B: Bus>(bus: &B, opcodes: &[OpcodeInfo<B>; 256], addr: u16, symbols: Option<&Symbols>) -> Instruction {
+    let info = &opcodes[bus.peek(addr) as usize];
+    let bytes: Vec<u8> = (0..info.bytes as u16).map(|i| bus.peek(addr.wrapping_add(i))).collect();
+    let low = bytes.get(1).copied().unwrap_or(0);
+    let word = u16::from_le_bytes([low, bytes.get(2).copied().unwrap_or(0)]);
+
+    let name = |value: u16, digits: usize| match symbols.and_then(|s| s.label(bus, value)) {
+        Some(label) => label.to_string(),
+        None => format!("${:0width$X}", value, width = digits),
+    };
+    let target = match info.mode {
+        Mode::Relative => Some(addr.wrapping_add(2).wrapping_add(low as i8 as u16)),
+        Mode::Absolute if info.mnemonic == "JMP" || info.mnemonic == "JSR" => Some(word),
+        _ => None,
+    };
+    let operand = match info.mode {
+        Mode::Immediate => format!("#${:02X}", low),
+        Mode::ZeroPage => name(low as u16, 2),
+        Mode::ZeroPageX => format!("{},X", name(low as u16, 2)),
+        Mode::ZeroPageY => format!("{},Y", name(low as u16, 2)),
+        Mode::Relative => name(target.unwrap(), 4),
+        Mode::Absolute => name(word, 4),
+        Mode::AbsoluteX => format!("{},X", name(word, 4)),
+        Mode::AbsoluteY => format!("{},Y", name(word, 4)),
+        Mode::Indirect => format!("({})", name(word, 4)),
+        Mode::IndirectX => format!("({},X)", name(low as u16, 2)),
+        Mode::IndirectY => format!("({}),Y", name(low as u16, 2)),
+        Mode::ZeroPageIndirect => format!("({})", name(low as u16, 2)),
+        Mode::AbsoluteIndirectX => format!("({},X)", name(word, 4)),
+        Mode::Accumulator => String::from("A"),
+        Mode::Implied => String::new(),
+    };
+    let text = if operand.is_empty() {
+        info.mnemonic.to_string()
+    } else {
+        format!("{} {}", info.mnemonic, operand)
+    };
+
+    Instruction {
+        addr,
+        bytes,
+        mnemonic: info.mnemonic,
+        mode: info.mode,
+        official: info.official,
+        target,
+        text,
+    }
+}
+
+// count instructions one after the other from addr.
+pub fn disassemble_range<B: Bus>(bus: &B, opcodes: &[OpcodeInfo<B>; 256], addr: u16, count: usize, symbols: Option<&Symbols>) -> Vec<Instruction> {
+    let mut instructions = Vec::with_capacity(count);
+    let mut addr = addr;
+    for _ in 0..count {
+        let instruction = disassemble(bus, opcodes, addr, symbols);
+        addr = instruction.next();
+        instructions.push(instruction);
+    }
+    instructions
+}
@@ -0,0 +1,321 @@
+use crate::bus::ppu_position;
+use crate::cpu::{Bus, CPU};
+use std::convert::TryFrom;
+use std::fmt;
+
+// Debugger expressions like [$00FE] == 3 && A > $10.
+//
+// Operands are numbers ($10 and 0x10 are hex, 16 is decimal), labels, the
+// registers a x y p sp pc, the flags c z i d b v n (0 or 1), scanline, dot,
+// frame and cycle, [ADDR] for the byte at ADDR and {ADDR} for the little
+// endian word there. Names are case insensitive, except labels.
+//
+// Operators are C's, with the same precedence:
+//   ! ~ - (unary)  * / %  + -  << >>  < <= > >=  == !=  &  ^  |  &&  ||
+// Comparisons and logic give 0 or 1. Dividing by zero gives 0.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Expr {
+    text: String,
+    node: Node,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Node {
+    Number(i64),
+    Operand(Operand),
+    Byte(Box<Node>),
+    Word(Box<Node>),
+    Unary(&'static str, Box<Node>),
+    Binary(&'static str, Box<Node>, Box<Node>),
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Operand {
+    A,
+    X,
+    Y,
+    P,
+    Sp,
+    Pc,
+    // Mask in P.
+    Flag(u8),
+    Scanline,
+    Dot,
+    Frame,
+    Cycle,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Number(i64),
+    Name(String),
+    // Operators and brackets.
+    Symbol(&'static str),
+}
+
+// Longest first so that << isn't read as two <.
+const SYMBOLS: [&str; 26] = [
+    "&&", "||", "==", "!=", "<=", ">=", "<<", ">>",
+    "+", "-", "*", "/", "%", "&", "|", "^", "<", ">", "!", "~", "(", ")", "[", "]", "{", "}",
+];
+
+// Binary operators from loosest to tightest.
+const PRECEDENCE: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+impl Expr {
+    // Labels are looked up with labels while parsing, so later changes to
+    // them (or to the banks mapped) don't affect the expression.
+    pub fn parse(text: &str, labels: &dyn Fn(&str) -> Option<u16>) -> Result<Expr, String> {
+        let tokens = tokenize(text)?;
+        if tokens.is_empty() {
+            return Err(String::from("Empty expression"));
+        }
+        let mut parser = Parser { tokens, position: 0, labels };
+        let node = parser.binary(0)?;
+        if let Some(token) = parser.tokens.get(parser.position) {
+            return Err(format!("Unexpected {} in expression", token));
+        }
+        Ok(Expr {
+            text: text.trim().to_string(),
+            node,
+        })
+    }
+
+    // Only peeks at memory, so evaluating doesn't disturb I/O registers.
+    pub fn eval<B: Bus>(&self, cpu: &CPU<B>) -> i64 {
+        eval(&self.node, cpu)
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Number(value) => write!(f, "{}", value),
+            Token::Name(name) => write!(f, "{}", name),
+            Token::Symbol(symbol) => write!(f, "{}", symbol),
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let name_char = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '@' || c == '.';
+        let length = if c == '$' || c.is_ascii_digit() {
+            let length = rest[1..].find(|c: char| !c.is_ascii_alphanumeric()).map_or(rest.len(), |i| i + 1);
+            tokens.push(Token::Number(parse_number(&rest[..length])?));
+            length
+        } else if name_char(c) {
+            let length = rest.find(|c: char| !name_char(c)).unwrap_or(rest.len());
+            tokens.push(Token::Name(rest[..length].to_string()));
+            length
+        } else if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+            tokens.push(Token::Symbol(symbol));
+            symbol.len()
+        } else {
+            return Err(format!("Unexpected {} in expression", c));
+        };
+        rest = rest[length..].trim_start();
+    }
+    Ok(tokens)
+}
+
+fn parse_number(text: &str) -> Result<i64, String> {
+    let value = if let Some(hex) = text.strip_prefix('$') {
+        i64::from_str_radix(hex, 16)
+    } else if let Some(hex) = text.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16)
+    } else {
+        text.parse()
+    };
+    value.map_err(|_| format!("Bad number {}", text))
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    labels: &'a dyn Fn(&str) -> Option<u16>,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    // The operator next up if it's one of these.
+    fn operator(&self, operators: &[&'static str]) -> Option<&'static str> {
+        match self.tokens.get(self.position) {
+            Some(Token::Symbol(symbol)) => operators.iter().copied().find(|operator| operator == symbol),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, closing: &str) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Symbol(symbol)) if symbol == closing => Ok(()),
+            Some(token) => Err(format!("Expected {} but found {}", closing, token)),
+            None => Err(format!("Expected {} at the end", closing)),
+        }
+    }
+
+    // Operators at level and tighter, left to right.
+    fn binary(&mut self, level: usize) -> Result<Node, String> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+        let mut node = self.binary(level + 1)?;
+        while let Some(operator) = self.operator(PRECEDENCE[level]) {
+            self.position += 1;
+            let right = self.binary(level + 1)?;
+            node = Node::Binary(operator, Box::new(node), Box::new(right));
+        }
+        Ok(node)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        match self.operator(&["!", "~", "-"]) {
+            Some(operator) => {
+                self.position += 1;
+                Ok(Node::Unary(operator, Box::new(self.unary()?)))
+            }
+            None => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        match self.next() {
+            Some(Token::Number(value)) => Ok(Node::Number(value)),
+            Some(Token::Name(name)) => self.name(&name),
+            Some(Token::Symbol("(")) => {
+                let node = self.binary(0)?;
+                self.expect(")")?;
+                Ok(node)
+            }
+            Some(Token::Symbol("[")) => {
+                let node = self.binary(0)?;
+                self.expect("]")?;
+                Ok(Node::Byte(Box::new(node)))
+            }
+            Some(Token::Symbol("{")) => {
+                let node = self.binary(0)?;
+                self.expect("}")?;
+                Ok(Node::Word(Box::new(node)))
+            }
+            Some(token) => Err(format!("Unexpected {} in expression", token)),
+            None => Err(String::from("Expression ends too soon")),
+        }
+    }
+
+    fn name(&self, name: &str) -> Result<Node, String> {
+        let operand = match name.to_ascii_lowercase().as_str() {
+            "a" => Operand::A,
+            "x" => Operand::X,
+            "y" => Operand::Y,
+            "p" => Operand::P,
+            "sp" => Operand::Sp,
+            "pc" => Operand::Pc,
+            "c" => Operand::Flag(0x01),
+            "z" => Operand::Flag(0x02),
+            "i" => Operand::Flag(0x04),
+            "d" => Operand::Flag(0x08),
+            "b" => Operand::Flag(0x10),
+            "v" => Operand::Flag(0x40),
+            "n" => Operand::Flag(0x80),
+            "scanline" => Operand::Scanline,
+            "dot" => Operand::Dot,
+            "frame" => Operand::Frame,
+            "cycle" => Operand::Cycle,
+            _ => {
+                return match (self.labels)(name) {
+                    Some(addr) => Ok(Node::Number(addr as i64)),
+                    None => Err(format!("Unknown name {}", name)),
+                }
+            }
+        };
+        Ok(Node::Operand(operand))
+    }
+}
+
+fn eval<B: Bus>(node: &Node, cpu: &CPU<B>) -> i64 {
+    match node {
+        Node::Number(value) => *value,
+        Node::Operand(operand) => {
+            let (frame, scanline, dot) = ppu_position(cpu.cycle_count);
+            match *operand {
+                Operand::A => cpu.a as i64,
+                Operand::X => cpu.x as i64,
+                Operand::Y => cpu.y as i64,
+                Operand::P => cpu.p as i64,
+                Operand::Sp => cpu.sp as i64,
+                Operand::Pc => cpu.pc as i64,
+                Operand::Flag(mask) => (cpu.p & mask != 0) as i64,
+                Operand::Scanline => scanline as i64,
+                Operand::Dot => dot as i64,
+                Operand::Frame => frame as i64,
+                Operand::Cycle => cpu.cycle_count as i64,
+            }
+        }
+        Node::Byte(addr) => cpu.bus.peek(eval(addr, cpu) as u16) as i64,
+        Node::Word(addr) => {
+            let addr = eval(addr, cpu) as u16;
+            u16::from_le_bytes([cpu.bus.peek(addr), cpu.bus.peek(addr.wrapping_add(1))]) as i64
+        }
+        Node::Unary(operator, operand) => {
+            let value = eval(operand, cpu);
+            match *operator {
+                "!" => (value == 0) as i64,
+                "~" => !value,
+                _ => value.wrapping_neg(),
+            }
+        }
+        Node::Binary(operator, left, right) => {
+            let left_value = eval(left, cpu);
+            // && and || don't look at the right side unless they need to.
+            match *operator {
+                "&&" => return (left_value != 0 && eval(right, cpu) != 0) as i64,
+                "||" => return (left_value != 0 || eval(right, cpu) != 0) as i64,
+                _ => {}
+            }
+            let (l, r) = (left_value, eval(right, cpu));
+            let shift = u32::try_from(r).ok();
+            match *operator {
+                "*" => l.wrapping_mul(r),
+                "/" => l.checked_div(r).unwrap_or(0),
+                "%" => l.checked_rem(r).unwrap_or(0),
+                "+" => l.wrapping_add(r),
+                "-" => l.wrapping_sub(r),
+                "<<" => shift.and_then(|shift| l.checked_shl(shift)).unwrap_or(0),
+                ">>" => shift.and_then(|shift| l.checked_shr(shift)).unwrap_or(0),
+                "<" => (l < r) as i64,
+                "<=" => (l <= r) as i64,
+                ">" => (l > r) as i64,
+                ">=" => (l >= r) as i64,
+                "==" => (l == r) as i64,
+                "!=" => (l != r) as i64,
+                "&" => l & r,
+                "^" => l ^ r,
+                _ => l | r,
+            }
+        }
+    }
+}
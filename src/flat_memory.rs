@@ -0,0 +1,44 @@
+use crate::cpu::Bus;
+
+const MEM_SIZE: usize = 0x10000;
+
+// 64KB of RAM and nothing else, for running the CPU on its own (Klaus Dormann's
+// functional tests and the like).
+pub struct FlatMemory {
+    pub memory: Vec<u8>,
+}
+
+impl FlatMemory {
+    pub fn new() -> Self {
+        FlatMemory {
+            memory: vec![0; MEM_SIZE],
+        }
+    }
+
+    // Copies a binary image into memory starting at addr.
+    pub fn load(&mut self, addr: u16, data: &[u8]) {
+        let start = addr as usize;
+        let end = (start + data.len()).min(MEM_SIZE);
+        self.memory[start..end].copy_from_slice(&data[..end - start]);
+    }
+}
+
+impl Default for FlatMemory {
+    fn default() -> Self {
+        FlatMemory::new()
+    }
+}
+
+impl Bus for FlatMemory {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.memory[addr as usize] = data;
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+}
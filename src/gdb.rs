@@ -0,0 +1,319 @@
+use crate::bus::{AddressSpace, BUS};
+use crate::cpu::{Bus, CPU};
+use crate::debugger::{Debugger, Halt, Stop, WatchKind};
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+// Instructions run between looks at the socket for GDB's interrupt byte.
+const INTERRUPT_CHECK_INTERVAL: usize = 4096;
+const INTERRUPT: u8 = 0x03;
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const TARGET_XML: &str = include_str!("target.xml");
+
+// GDB remote serial protocol stub. Registers go over the wire as a, x, y, p,
+// sp (a byte each) and pc (2 bytes, little endian), in that order, which is
+// also their number for p/P and how target.xml describes them. Breakpoints
+// and watchpoints reuse the command-line debugger's.
+struct GdbStub {
+    debugger: Debugger,
+    stream: TcpStream,
+    // Bytes that came in while the CPU ran, read before the socket.
+    pending: VecDeque<u8>,
+}
+
+// Serves the first client to connect to listener until it detaches, kills
+// the session or goes away.
+pub fn serve(cpu: &mut CPU<BUS>, listener: &TcpListener) -> io::Result<()> {
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    let mut stub = GdbStub {
+        debugger: Debugger::new(None),
+        stream,
+        pending: VecDeque::new(),
+    };
+    while let Some(packet) = stub.read_packet()? {
+        let reply = match stub.handle(cpu, &packet) {
+            Some(reply) => reply,
+            None => break,
+        };
+        stub.send_packet(&reply)?;
+    }
+    Ok(())
+}
+
+impl GdbStub {
+    // The reply to one packet, None to end the session.
+    fn handle(&mut self, cpu: &mut CPU<BUS>, packet: &str) -> Option<String> {
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        let reply = match command {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "\x03" => format!("S{:02x}", SIGINT),
+            "g" => {
+                let [low, high] = cpu.pc.to_le_bytes();
+                hex(&[cpu.a, cpu.x, cpu.y, cpu.p, cpu.sp, low, high])
+            }
+            "G" => match unhex(args) {
+                Some(bytes) if bytes.len() == 7 => {
+                    cpu.a = bytes[0];
+                    cpu.x = bytes[1];
+                    cpu.y = bytes[2];
+                    cpu.p = bytes[3];
+                    cpu.sp = bytes[4];
+                    cpu.pc = u16::from_le_bytes([bytes[5], bytes[6]]);
+                    String::from("OK")
+                }
+                _ => String::from("E01"),
+            },
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(0) => hex(&[cpu.a]),
+                Ok(1) => hex(&[cpu.x]),
+                Ok(2) => hex(&[cpu.y]),
+                Ok(3) => hex(&[cpu.p]),
+                Ok(4) => hex(&[cpu.sp]),
+                Ok(5) => hex(&cpu.pc.to_le_bytes()),
+                _ => String::from("E01"),
+            },
+            "P" => self.write_register(cpu, args).unwrap_or_else(|| String::from("E01")),
+            "m" => match parse_range(args) {
+                Some((addr, length)) => {
+                    let bytes: Vec<u8> = (0..length).map(|i| cpu.bus.peek(addr.wrapping_add(i))).collect();
+                    hex(&bytes)
+                }
+                None => String::from("E01"),
+            },
+            "M" => {
+                let written = args.split_once(':').and_then(|(range, data)| {
+                    let (addr, length) = parse_range(range)?;
+                    let bytes = unhex(data).filter(|bytes| bytes.len() == length as usize)?;
+                    for (i, byte) in bytes.into_iter().enumerate() {
+                        cpu.bus.write(addr.wrapping_add(i as u16), byte);
+                    }
+                    Some(())
+                });
+                if written.is_some() { String::from("OK") } else { String::from("E01") }
+            }
+            "c" | "s" => {
+                if !args.is_empty() {
+                    match u16::from_str_radix(args, 16) {
+                        Ok(addr) => cpu.pc = addr,
+                        Err(_) => return Some(String::from("E01")),
+                    }
+                }
+                if command == "s" {
+                    let halt = self.debugger.run(cpu, |_, _| true);
+                    stop_reply(&self.debugger, halt, SIGTRAP)
+                } else {
+                    let (stream, pending) = (&self.stream, &mut self.pending);
+                    let mut count = 0;
+                    let halt = self.debugger.run(cpu, |_, _| {
+                        count += 1;
+                        count % INTERRUPT_CHECK_INTERVAL == 0 && interrupted(stream, pending)
+                    });
+                    stop_reply(&self.debugger, halt, SIGINT)
+                }
+            }
+            "Z" | "z" => match parse_stop(args) {
+                Some(stop) => {
+                    if command == "Z" {
+                        self.debugger.stops.push(stop);
+                    } else if let Some(i) = self.debugger.stops.iter().position(|s| *s == stop) {
+                        self.debugger.stops.remove(i);
+                    }
+                    String::from("OK")
+                }
+                // Not a kind this stub has, GDB falls back to something else.
+                None => String::new(),
+            },
+            "q" if args.starts_with("Supported") => String::from("PacketSize=1000;qXfer:features:read+"),
+            "q" if args.starts_with("Xfer:features:read:") => read_features(&args["Xfer:features:read:".len()..]),
+            "q" if args == "Attached" => String::from("1"),
+            "H" => String::from("OK"),
+            "D" => {
+                let _ = self.send_packet("OK");
+                return None;
+            }
+            "k" => return None,
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+
+    fn write_register(&mut self, cpu: &mut CPU<BUS>, args: &str) -> Option<String> {
+        let (number, value) = args.split_once('=')?;
+        let bytes = unhex(value)?;
+        let byte = *bytes.first()?;
+        match usize::from_str_radix(number, 16).ok()? {
+            0 => cpu.a = byte,
+            1 => cpu.x = byte,
+            2 => cpu.y = byte,
+            3 => cpu.p = byte,
+            4 => cpu.sp = byte,
+            5 => cpu.pc = u16::from_le_bytes([byte, *bytes.get(1)?]),
+            _ => return None,
+        }
+        Some(String::from("OK"))
+    }
+
+    // Next packet's payload, with its checksum checked and acknowledged. An
+    // interrupt byte outside a packet comes back as "\x03", None at the end
+    // of the connection.
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'$') => {}
+                Some(INTERRUPT) => return Ok(Some(String::from("\x03"))),
+                // Acks and noise between packets.
+                Some(_) => continue,
+            }
+            let mut payload = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(byte) => payload.push(byte),
+                }
+            }
+            let mut checksum = [0u8; 2];
+            for digit in checksum.iter_mut() {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(byte) => *digit = byte,
+                }
+            }
+            let expected = std::str::from_utf8(&checksum).ok().and_then(|text| u8::from_str_radix(text, 16).ok());
+            if expected == Some(checksum_of(&payload)) {
+                self.stream.write_all(b"+")?;
+                return Ok(Some(String::from_utf8_lossy(&payload).into_owned()));
+            }
+            self.stream.write_all(b"-")?;
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        if let Some(byte) = self.pending.pop_front() {
+            return Ok(Some(byte));
+        }
+        let mut byte = [0u8];
+        match self.stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    fn send_packet(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.stream.write_all(packet.as_bytes())
+    }
+}
+
+// T05watch:0200; and the like for watchpoints, just the signal otherwise.
+// signal is what a run ending on its own condition means.
+fn stop_reply(debugger: &Debugger, halt: Halt, signal: u8) -> String {
+    match halt {
+        Halt::Done => format!("S{:02x}", signal),
+        Halt::Stop(i, Some(access)) => {
+            let kind = match debugger.stops[i] {
+                Stop::Watchpoint { kind: WatchKind::Read, .. } => "rwatch",
+                Stop::Watchpoint { kind: WatchKind::ReadWrite, .. } => "awatch",
+                _ => "watch",
+            };
+            format!("T{:02x}{}:{:04x};", SIGTRAP, kind, access.addr)
+        }
+        Halt::Stop(_, None) => format!("S{:02x}", SIGTRAP),
+        Halt::Jammed => format!("S{:02x}", SIGILL),
+    }
+}
+
+// Whether GDB sent its interrupt byte, without waiting for anything. Any
+// other bytes are kept in pending for read_packet.
+fn interrupted(stream: &TcpStream, pending: &mut VecDeque<u8>) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let mut buffer = [0u8; 256];
+    let result = (&*stream).read(&mut buffer);
+    let _ = stream.set_nonblocking(false);
+    match result {
+        Ok(0) => true,
+        Ok(length) => {
+            let bytes = &buffer[..length];
+            match bytes.iter().position(|&byte| byte == INTERRUPT) {
+                Some(i) => {
+                    pending.extend(&bytes[..i]);
+                    pending.extend(&bytes[i + 1..]);
+                    true
+                }
+                None => {
+                    pending.extend(bytes);
+                    false
+                }
+            }
+        }
+        Err(_) => false,
+    }
+}
+
+// "annex:offset,length" of qXfer:features:read. Only target.xml is there,
+// sent in pieces starting with m, and l for the last one.
+fn read_features(args: &str) -> String {
+    let range = args.split_once(':').filter(|(annex, _)| *annex == "target.xml").and_then(|(_, range)| {
+        let (offset, length) = range.split_once(',')?;
+        Some((usize::from_str_radix(offset, 16).ok()?, usize::from_str_radix(length, 16).ok()?))
+    });
+    match range {
+        Some((offset, length)) => {
+            let start = offset.min(TARGET_XML.len());
+            let end = offset.saturating_add(length).min(TARGET_XML.len());
+            let more = if end < TARGET_XML.len() { 'm' } else { 'l' };
+            format!("{}{}", more, &TARGET_XML[start..end])
+        }
+        None => String::from("E00"),
+    }
+}
+
+// "type,addr,kind" of Z/z packets. 0 and 1 are breakpoints, 2 to 4 write,
+// read and access watchpoints over kind bytes.
+fn parse_stop(args: &str) -> Option<Stop> {
+    let mut fields = args.split(',');
+    let kind = fields.next()?;
+    let addr = u16::from_str_radix(fields.next()?, 16).ok()?;
+    let length = u16::from_str_radix(fields.next()?, 16).ok()?.max(1);
+    let watch = |kind| Stop::Watchpoint {
+        space: AddressSpace::Cpu,
+        kind,
+        first: addr,
+        last: addr.saturating_add(length - 1),
+    };
+    match kind {
+        "0" | "1" => Some(Stop::Breakpoint(addr)),
+        "2" => Some(watch(WatchKind::Write)),
+        "3" => Some(watch(WatchKind::Read)),
+        "4" => Some(watch(WatchKind::ReadWrite)),
+        _ => None,
+    }
+}
+
+// "addr,length" in hex.
+fn parse_range(args: &str) -> Option<(u16, u16)> {
+    let (addr, length) = args.split_once(',')?;
+    Some((u16::from_str_radix(addr, 16).ok()?, u16::from_str_radix(length, 16).ok()?))
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok()).collect()
+}
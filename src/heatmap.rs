@@ -0,0 +1,137 @@
+use std::collections::BTreeMap;
+
+const ADDRESSES: usize = 0x10000;
+const RAM_SIZE: usize = 0x800;
+const RAM_END: u16 = 0x1FFF;
+// Bytes of RAM per row of the report's maps.
+const MAP_WIDTH: usize = 64;
+// Blank for no accesses, then one step every 4x more.
+const HEAT: &[u8] = b" .:-=+*#%@";
+
+// An instruction used a byte of RAM nothing had written since power on.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct UninitializedRead {
+    // In $0000-$07FF, whichever mirror it went through.
+    pub addr: u16,
+    // Where the instruction starts.
+    pub pc: u16,
+    pub count: u64,
+}
+
+// Per address counts of bus reads, writes and opcode fetches, and the reads of
+// RAM that came before anything was written there. Kept by BUS while it is in
+// BUS::heatmap.
+//
+// Reads count every read cycle, dummy reads and instruction fetches included.
+// Uninitialized reads only count values the CPU used: operands, pointers,
+// pulls and code, not the dummy reads of indexing and stack cycles.
+pub struct Heatmap {
+    pub reads: Vec<u64>,
+    pub writes: Vec<u64>,
+    pub executes: Vec<u64>,
+    written: Vec<bool>,
+    // Where the instruction running right now starts.
+    pc: u16,
+    uninitialized: BTreeMap<(u16, u16), u64>,
+}
+
+impl Default for Heatmap {
+    fn default() -> Self {
+        Heatmap::new()
+    }
+}
+
+impl Heatmap {
+    pub fn new() -> Self {
+        Heatmap {
+            reads: vec![0; ADDRESSES],
+            writes: vec![0; ADDRESSES],
+            executes: vec![0; ADDRESSES],
+            written: vec![false; RAM_SIZE],
+            pc: 0,
+            uninitialized: BTreeMap::new(),
+        }
+    }
+
+    // RAM is garbage again, whatever the power on pattern filled it with.
+    pub fn power_on(&mut self) {
+        self.written.fill(false);
+    }
+
+    pub fn read(&mut self, addr: u16) {
+        self.reads[addr as usize] += 1;
+    }
+
+    pub fn write(&mut self, addr: u16) {
+        self.writes[addr as usize] += 1;
+        if addr <= RAM_END {
+            self.written[addr as usize % RAM_SIZE] = true;
+        }
+    }
+
+    // The CPU is fetching the opcode at addr.
+    pub fn execute(&mut self, addr: u16) {
+        self.executes[addr as usize] += 1;
+        self.pc = addr;
+    }
+
+    // The CPU used the value at addr, as data or code.
+    pub fn used(&mut self, addr: u16) {
+        if addr > RAM_END {
+            return;
+        }
+        let offset = addr as usize % RAM_SIZE;
+        if !self.written[offset] {
+            *self.uninitialized.entry((offset as u16, self.pc)).or_insert(0) += 1;
+        }
+    }
+
+    // By address, then by instruction.
+    pub fn uninitialized_reads(&self) -> Vec<UninitializedRead> {
+        self.uninitialized.iter()
+            .map(|(&(addr, pc), &count)| UninitializedRead { addr, pc, count })
+            .collect()
+    }
+
+    // Uninitialized reads, accesses per 256 byte page and maps of RAM reads and writes.
+    pub fn report(&self) -> String {
+        let uninitialized = self.uninitialized_reads();
+        let mut lines = vec![format!("{} uninitialized RAM reads", uninitialized.len())];
+        for read in &uninitialized {
+            lines.push(format!("${:04X} read by the instruction at ${:04X} ({}x)", read.addr, read.pc, read.count));
+        }
+
+        lines.push(String::new());
+        lines.push(format!("{:<6} {:>12} {:>12} {:>12}", "page", "reads", "writes", "executes"));
+        for page in 0..ADDRESSES / 0x100 {
+            let range = page * 0x100..(page + 1) * 0x100;
+            let sum = |counts: &[u64]| counts[range.clone()].iter().sum::<u64>();
+            let (reads, writes, executes) = (sum(&self.reads), sum(&self.writes), sum(&self.executes));
+            if reads + writes + executes > 0 {
+                lines.push(format!("${:02X}xx  {:>12} {:>12} {:>12}", page, reads, writes, executes));
+            }
+        }
+
+        for (name, counts) in [("reads", &self.reads), ("writes", &self.writes)] {
+            lines.push(String::new());
+            lines.push(format!("RAM {}, '{}' from 1 up to '{}' from {}",
+                               name, HEAT[1] as char, HEAT[HEAT.len() - 1] as char, 1u64 << (2 * (HEAT.len() - 2))));
+            for row in (0..RAM_SIZE).step_by(MAP_WIDTH) {
+                // With the mirrors added in.
+                let map: String = (row..row + MAP_WIDTH)
+                    .map(|offset| heat((offset..=RAM_END as usize).step_by(RAM_SIZE).map(|addr| counts[addr]).sum()))
+                    .collect();
+                lines.push(format!("${:04X} {}", row, map.trim_end()));
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+fn heat(count: u64) -> char {
+    if count == 0 {
+        return HEAT[0] as char;
+    }
+    let level = 1 + count.ilog2() as usize / 2;
+    HEAT[level.min(HEAT.len() - 1)] as char
+}
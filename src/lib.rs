@@ -0,0 +1,15 @@
+pub mod apu;
+pub mod bus;
+pub mod cdl;
+pub mod cheats;
+pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod expr;
+pub mod flat_memory;
+pub mod gdb;
+pub mod heatmap;
+pub mod nsf_player;
+pub mod profiler;
+pub mod test_rom;
+pub mod trace;
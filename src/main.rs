@@ -1,22 +1,261 @@
-mod cpu;
-mod bus;
-use bus::BUS;
-use cpu::CPU;
+use rust_nes::bus::{ppu_position, RamInit, BUS};
+use rust_nes::cdl::CodeDataLog;
+use rust_nes::cheats::{Cheat, Cheats};
+use rust_nes::bus::cartridge::game_db::GameDb;
+use rust_nes::bus::cartridge::nsf::Nsf;
+use rust_nes::cpu::CPU;
+use rust_nes::debugger::Debugger;
+use rust_nes::gdb;
+use rust_nes::heatmap::Heatmap;
+use rust_nes::disasm::Symbols;
+use rust_nes::trace::{FileSink, StdoutSink, TraceCondition, TraceFormat, TraceLogger, TraceSink, Tracer};
+use rust_nes::nsf_player::{self, NsfPlayer};
+use rust_nes::profiler::Profiler;
+use std::env;
+use std::fs;
+use std::net::TcpListener;
+
+const WAV_SAMPLE_RATE: u32 = 44100;
+// Ten seconds of NTSC video.
+const PROFILE_FRAMES: usize = 600;
+// How often --cdl and --heatmap write their files while the game runs, a second of video.
+const SAVE_FRAMES: usize = 60;
+const USAGE: &str = "\
+Usage: rust-nes rom.nes | rom.unf [--patch fix.ips]... [--gamedb extra.txt] | disk.fds [disksys.rom]
+                [--ram zeros|ff|random[:seed]|hardware]
+                [--trace log.txt|- [--trace-format nestest|mesen|json] [--trace-start COND] [--trace-stop COND]]
+                where COND is pc:C000, pc:C000-C0FF or frame:N
+                [--symbols labels.txt|game.dbg|game.fns|game.mlb] [--debug | --gdb PORT]
+                [--profile report.txt] [--profile-folded stacks.txt] [--profile-frames N] [--cdl game.cdl]
+                [--heatmap report.txt] [--cheats game.cht] [--cheat SXIOPO|00AAAAVV|AAAA[?CC]:VV]...
+       rust-nes --nsf music.nsf [--track N] [--seconds S] [--out track.wav]";
 
 fn main() {
     println!("NES Started!");
+    let mut args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("--nsf") {
+        play_nsf(&args[2..]);
+        return;
+    }
+    let mut patches = Vec::new();
+    while let Some(patch) = take_option(&mut args, "--patch") {
+        patches.push(patch);
+    }
+    // Entries from --gamedb come before the built-in ones, so they win.
+    let mut game_db = take_option(&mut args, "--gamedb").map_or(GameDb::parse(""), |path| GameDb::load(&path));
+    game_db.extend(GameDb::builtin());
+    let ram_init = take_option(&mut args, "--ram").map_or(RamInit::Zeros, |name| {
+        RamInit::parse(&name).unwrap_or_else(|| panic!("Error: Unknown RAM pattern {}", name))
+    });
+    if let RamInit::Random(seed) = ram_init {
+        println!("RAM filled with --ram random:{}", seed);
+    }
+    let symbols = take_option(&mut args, "--symbols").map(|path| Symbols::load(&path));
+    let tracer = take_tracer(&mut args, symbols.clone());
+    let debug = take_flag(&mut args, "--debug");
+    let gdb_port = take_option(&mut args, "--gdb");
+    let profile = take_option(&mut args, "--profile");
+    let profile_folded = take_option(&mut args, "--profile-folded");
+    let profile_frames = take_option(&mut args, "--profile-frames")
+        .map_or(PROFILE_FRAMES, |frames| frames.parse().expect("Error: --profile-frames needs a number"));
+    let profiling = profile.is_some() || profile_folded.is_some();
+    let cdl_path = take_option(&mut args, "--cdl");
+    let heatmap_path = take_option(&mut args, "--heatmap");
+    let cheats_path = take_option(&mut args, "--cheats");
+    let mut codes = Vec::new();
+    while let Some(code) = take_option(&mut args, "--cheat") {
+        codes.push(code);
+    }
+
     let mut bus = BUS::new();
-    bus.load_cart(String::from("./src/nestest.nes"));
-    let mut cpu: CPU = CPU::new(bus);
-    //////FOR TESTING///////
-    cpu.pc = 0xc000;
+    bus.ram_init = ram_init;
+    match args.get(1) {
+        Some(name) if name.ends_with(".fds") || name.ends_with(".qd") => {
+            let bios = args.get(2).cloned().unwrap_or(String::from("./disksys.rom"));
+            bus.load_disk(name.clone(), bios);
+        }
+        Some(name) => bus.load_cart(name.clone(), &patches, &game_db),
+        None => {
+            println!("{}", USAGE);
+            return;
+        }
+    }
+    if let Some(fix) = bus.cartridge.as_ref().and_then(|c| c.header_override.as_ref()) {
+        println!("Game database: {} ({:08X}), header corrected: {}", fix.name, fix.crc32, fix.changes.join(", "));
+    }
+    if let (Some(path), Some(cartridge)) = (cdl_path.as_ref(), bus.cartridge.as_ref()) {
+        // Carries on from an existing log for the same ROM, like FCEUX. One for
+        // another ROM is left alone rather than written over.
+        let fresh = CodeDataLog::for_cartridge(cartridge);
+        let log = match fs::read(path) {
+            Ok(data) => CodeDataLog::parse(&data, fresh.prg.len(), fresh.chr.len())
+                .unwrap_or_else(|| panic!("Error: {} is a code/data log for a different ROM", path)),
+            Err(_) => fresh,
+        };
+        bus.code_data_log = Some(log);
+    }
+    if heatmap_path.is_some() {
+        bus.heatmap = Some(Heatmap::new());
+    }
+    bus.cheats = take_cheats(cheats_path.as_deref(), &codes);
+    let mut cpu: CPU<BUS> = CPU::new(bus);
+    cpu.tracer = tracer;
+    if profiling {
+        let mut profiler = Profiler::new();
+        profiler.symbols = symbols.clone();
+        cpu.profiler = Some(profiler);
+    }
+    cpu.power_on();
+    if debug || gdb_port.is_some() {
+        // Through the reset sequence first so debuggers start at the reset vector.
+        cpu.clock();
+        while !cpu.complete() {
+            cpu.clock();
+        }
+    }
+    if debug {
+        Debugger::new(symbols).repl(&mut cpu);
+        save_logs(&cpu, cdl_path.as_deref(), heatmap_path.as_deref());
+        return;
+    }
+    if let Some(port) = gdb_port {
+        let listener = TcpListener::bind(format!("127.0.0.1:{}", port)).expect("Error: Cannot listen for GDB");
+        println!("Waiting for GDB on 127.0.0.1:{}", port);
+        gdb::serve(&mut cpu, &listener).expect("Error: GDB connection failed");
+        save_logs(&cpu, cdl_path.as_deref(), heatmap_path.as_deref());
+        return;
+    }
+    let mut last_save = 0;
     loop {
         cpu.bus.system_clock_count += 1;
 
         //Clock ppu
 
-        if cpu.bus.system_clock_count % 3 == 0 {
-            cpu.clock(true);
+        if cpu.bus.system_clock_count.is_multiple_of(3) {
+            cpu.clock();
+            if cpu.halted() {
+                println!("CPU jammed at ${:04X}", cpu.pc.wrapping_sub(1));
+                break;
+            }
+            if profiling && ppu_position(cpu.cycle_count).0 >= profile_frames {
+                break;
+            }
+            if (cdl_path.is_some() || heatmap_path.is_some()) && cpu.complete() {
+                let frame = ppu_position(cpu.cycle_count).0;
+                if frame >= last_save + SAVE_FRAMES {
+                    last_save = frame;
+                    save_logs(&cpu, cdl_path.as_deref(), heatmap_path.as_deref());
+                }
+            }
+        }
+    }
+    save_logs(&cpu, cdl_path.as_deref(), heatmap_path.as_deref());
+    if let Some(log) = cpu.bus.code_data_log.as_ref() {
+        println!("{}", log.summary());
+    }
+    if let Some(heatmap) = cpu.bus.heatmap.as_ref() {
+        println!("{} uninitialized RAM reads", heatmap.uninitialized_reads().len());
+    }
+    if let Some(profiler) = cpu.profiler.as_ref() {
+        if let Some(path) = profile {
+            fs::write(&path, profiler.report() + "\n").unwrap_or_else(|e| panic!("Error: Cannot write {}: {}", path, e));
+        }
+        if let Some(path) = profile_folded {
+            fs::write(&path, profiler.folded() + "\n").unwrap_or_else(|e| panic!("Error: Cannot write {}: {}", path, e));
+        }
+    }
+}
+
+fn save_logs(cpu: &CPU<BUS>, cdl_path: Option<&str>, heatmap_path: Option<&str>) {
+    if let (Some(path), Some(log)) = (cdl_path, cpu.bus.code_data_log.as_ref()) {
+        log.save(path).unwrap_or_else(|e| panic!("Error: Cannot write {}: {}", path, e));
+    }
+    if let (Some(path), Some(heatmap)) = (heatmap_path, cpu.bus.heatmap.as_ref()) {
+        fs::write(path, heatmap.report() + "\n").unwrap_or_else(|e| panic!("Error: Cannot write {}: {}", path, e));
+    }
+}
+
+// The cheats in path, if it exists, with codes added. They're saved back to
+// path when there are any, so codes given once stay on for the next run.
+fn take_cheats(path: Option<&str>, codes: &[String]) -> Cheats {
+    let mut cheats = match path {
+        Some(path) if fs::metadata(path).is_ok() => Cheats::load(path),
+        _ => Cheats::new(),
+    };
+    for code in codes {
+        cheats.list.push(Cheat::parse(code).unwrap_or_else(|e| panic!("Error: {}", e)));
+    }
+    if let (Some(path), false) = (path, codes.is_empty()) {
+        cheats.save(path).unwrap_or_else(|e| panic!("Error: Cannot write {}: {}", path, e));
+    }
+    cheats
+}
+
+// Removes "flag value" from args and returns the value.
+fn take_option(args: &mut Vec<String>, flag: &str) -> Option<String> {
+    let i = args.iter().position(|arg| arg == flag)?;
+    args.remove(i);
+    if i >= args.len() {
+        panic!("Error: {} needs a value", flag);
+    }
+    Some(args.remove(i))
+}
+
+// Removes flag from args, true if it was there.
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    match args.iter().position(|arg| arg == flag) {
+        Some(i) => {
+            args.remove(i);
+            true
+        }
+        None => false,
+    }
+}
+
+fn take_tracer(args: &mut Vec<String>, symbols: Option<Symbols>) -> Option<Box<dyn Tracer>> {
+    let format = take_option(args, "--trace-format").map_or(TraceFormat::Nestest, |name| {
+        TraceFormat::parse(&name).unwrap_or_else(|| panic!("Error: Unknown trace format {}", name))
+    });
+    let condition = |text: String| TraceCondition::parse(&text).unwrap_or_else(|| panic!("Error: Bad trace condition {}", text));
+    let start = take_option(args, "--trace-start").map(condition);
+    let stop = take_option(args, "--trace-stop").map(condition);
+
+    let sink: Box<dyn TraceSink> = match take_option(args, "--trace")?.as_str() {
+        "-" => Box::new(StdoutSink),
+        path => Box::new(FileSink::create(path).unwrap_or_else(|e| panic!("Error: Cannot create {}: {}", path, e))),
+    };
+    let mut tracer = TraceLogger::new(sink, format);
+    tracer.start = start;
+    tracer.stop = stop;
+    tracer.symbols = symbols;
+    Some(Box::new(tracer))
+}
+
+fn play_nsf(args: &[String]) {
+    let name = args.first().expect("Error: --nsf needs a file");
+    let mut track: Option<u8> = None;
+    let mut seconds = 60.0;
+    let mut out = String::from("track.wav");
+    let mut i = 1;
+    while i < args.len() {
+        let value = args.get(i + 1).unwrap_or_else(|| panic!("Error: {} needs a value", args[i]));
+        match args[i].as_str() {
+            "--track" => track = Some(value.parse().expect("Error: --track needs a number")),
+            "--seconds" => seconds = value.parse().expect("Error: --seconds needs a number"),
+            "--out" => out = value.clone(),
+            flag => panic!("Error: Unknown option {}", flag),
         }
+        i += 2;
     }
-}
\ No newline at end of file
+
+    let file = fs::read(name).expect("Error: Cannot read NSF");
+    let nsf = Nsf::new(&file);
+    println!("{} - {} ({})", nsf.name, nsf.artist, nsf.copyright);
+
+    let track = track.unwrap_or(nsf.starting_song);
+    println!("Rendering track {}/{} for {} seconds to {}", track, nsf.songs, seconds, out);
+    let mut player = NsfPlayer::new(nsf);
+    player.start_track(track);
+    let samples = player.render(seconds, WAV_SAMPLE_RATE);
+    nsf_player::write_wav(&out, &samples, WAV_SAMPLE_RATE);
+}
@@ -0,0 +1,149 @@
+use crate::bus::BUS;
+use crate::bus::cartridge::nsf::{self, Nsf};
+use crate::cpu::{Bus, CPU};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+const NTSC_CPU_RATE: f64 = 1_789_773.0;
+const PAL_CPU_RATE: f64 = 1_662_607.0;
+
+// Runs an NSF through the CPU/APU: INIT once per track, then PLAY every
+// play speed microseconds, both called through the driver in NsfMapper.
+pub struct NsfPlayer {
+    pub nsf: Nsf,
+    pub cpu: CPU<BUS>,
+    cpu_rate: f64,
+    play_period: f64,
+    play_timer: f64,
+}
+
+impl NsfPlayer {
+    pub fn new(nsf: Nsf) -> Self {
+        let unsupported = nsf.unsupported_chips();
+        if !unsupported.is_empty() {
+            panic!("Error: {} expansion sound isn't emulated, only 2A03, FDS, VRC6, MMC5 and Namco 163 tunes can be played",
+                   unsupported.join(", "));
+        }
+        let mut bus = BUS::new();
+        bus.load_nsf(&nsf);
+        let cpu_rate = if nsf.pal { PAL_CPU_RATE } else { NTSC_CPU_RATE };
+        let play_period = nsf.play_speed() as f64 * cpu_rate / 1_000_000.0;
+        NsfPlayer {
+            nsf,
+            cpu: CPU::new(bus),
+            cpu_rate,
+            play_period,
+            play_timer: 0.0,
+        }
+    }
+
+    // Resets RAM and sound, then calls INIT for track (1 based).
+    pub fn start_track(&mut self, track: u8) {
+        if track == 0 || track > self.nsf.songs {
+            panic!("Error: Track {} out of range 1..={}", track, self.nsf.songs);
+        }
+        let fds = self.nsf.chips & nsf::CHIP_FDS != 0;
+        let bus = &mut self.cpu.bus;
+
+        bus.memory = [0; 2048];
+        if !fds {
+            for addr in 0x6000..0x8000 {
+                bus.write(addr, 0);
+            }
+        }
+        for addr in 0x4000..=0x4013 {
+            bus.write(addr, 0);
+        }
+        bus.write(0x4015, 0x00);
+        bus.write(0x4015, 0x0F);
+        bus.write(0x4017, 0x40);
+        if fds {
+            bus.write(0x4089, 0x80);
+            bus.write(0x408A, 0xE8);
+        }
+        // FDS tunes get their banks copied into RAM again, over what the last track left.
+        for (i, &bank) in self.nsf.initial_banks().iter().enumerate() {
+            if fds || i >= 2 {
+                bus.write(0x5FF6 + i as u16, bank);
+            }
+        }
+
+        self.cpu.a = track - 1;
+        self.cpu.x = self.nsf.pal as u8;
+        self.cpu.y = 0;
+        self.cpu.sp = 0xFD;
+        self.cpu.set_interrupt_disable(true);
+        self.cpu.pc = nsf::DRIVER_INIT;
+        self.play_timer = 0.0;
+    }
+
+    // One CPU cycle.
+    pub fn clock(&mut self) {
+        self.cpu.clock();
+
+        self.play_timer += 1.0;
+        // PLAY is only called once the previous INIT/PLAY returned to the idle loop.
+        if self.play_timer >= self.play_period && self.cpu.complete() && self.cpu.pc == nsf::DRIVER_IDLE {
+            self.play_timer = (self.play_timer - self.play_period).min(self.play_period);
+            self.cpu.pc = nsf::DRIVER_PLAY;
+        }
+    }
+
+    // Renders the current track to signed 16 bit mono samples.
+    pub fn render(&mut self, seconds: f64, sample_rate: u32) -> Vec<i16> {
+        let total = (seconds * sample_rate as f64) as usize;
+        let mut samples = Vec::with_capacity(total);
+        let mut sum = 0.0;
+        let mut count = 0;
+        let mut sample_clock = 0.0;
+        // DC blocking filter, the APU output sits between 0 and 1.
+        let mut previous_in = 0.0;
+        let mut previous_out = 0.0;
+
+        while samples.len() < total {
+            self.clock();
+            sum += self.cpu.bus.audio_output();
+            count += 1;
+            sample_clock += sample_rate as f64;
+            if sample_clock >= self.cpu_rate {
+                sample_clock -= self.cpu_rate;
+                let input = sum / count as f32;
+                let output = input - previous_in + 0.995 * previous_out;
+                previous_in = input;
+                previous_out = output;
+                samples.push((output * 32767.0).clamp(-32768.0, 32767.0) as i16);
+                sum = 0.0;
+                count = 0;
+            }
+        }
+        samples
+    }
+}
+
+// 16 bit mono PCM WAV.
+pub fn write_wav(name: &str, samples: &[i16], sample_rate: u32) {
+    let file = File::create(name).expect("Error: Cannot create WAV file");
+    let mut out = BufWriter::new(file);
+    let data_size = (samples.len() * 2) as u32;
+
+    let mut header = Vec::with_capacity(44);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&(36 + data_size).to_le_bytes());
+    header.extend_from_slice(b"WAVE");
+    header.extend_from_slice(b"fmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    header.extend_from_slice(&1u16.to_le_bytes()); // Mono
+    header.extend_from_slice(&sample_rate.to_le_bytes());
+    header.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+    header.extend_from_slice(&2u16.to_le_bytes());
+    header.extend_from_slice(&16u16.to_le_bytes());
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&data_size.to_le_bytes());
+
+    out.write_all(&header).expect("Error: Cannot write WAV file");
+    for sample in samples {
+        out.write_all(&sample.to_le_bytes()).expect("Error: Cannot write WAV file");
+    }
+    out.flush().expect("Error: Cannot write WAV file");
+}
@@ -0,0 +1,319 @@
+use crate::bus::ppu_position;
+use crate::cpu::{Bus, Interrupt};
+use crate::disasm::Symbols;
+use std::collections::HashMap;
+
+// Stack pointer the bottom of the shadow stack counts as, above anything the
+// real one can hold so nothing ever unwinds it.
+const STACK_BOTTOM: u16 = 0x1FF;
+
+// What the instruction (or interrupt sequence) that just ended did to the flow of control.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Flow {
+    Next,
+    // JSR
+    Call,
+    // RTS or RTI
+    Return,
+    // Also BRK, as Interrupt::Break.
+    Interrupt(Interrupt),
+}
+
+// Cycles spent in one routine, counting every call to it wherever it came from.
+#[derive(Clone, Default, Debug)]
+pub struct RoutineStats {
+    pub calls: usize,
+    // In the routine itself.
+    pub self_cycles: usize,
+    // In the routine and everything it called, recursion counted once.
+    pub total_cycles: usize,
+    // The most total cycles it took in one video frame, and which frame that was.
+    pub max_frame_cycles: usize,
+    pub max_frame: usize,
+    frame_cycles: usize,
+}
+
+// A place in the call tree, one per path of calls that reached it.
+struct Node {
+    name: String,
+    parent: usize,
+    children: HashMap<String, usize>,
+    calls: usize,
+    self_cycles: usize,
+    total_cycles: usize,
+}
+
+// An entry on the shadow stack, with SP right after the call pushed its return address.
+struct Call {
+    node: usize,
+    sp: u16,
+}
+
+// Follows JSR, RTS, RTI and interrupts to keep a shadow of the call stack and
+// charges every instruction's cycles to the routines on it. CPU::clock feeds
+// it through step() when it's set as CPU::profiler.
+//
+// Returns unwind the shadow stack by SP rather than one call at a time, so
+// routines that drop their return address or push one to RTS to (jump tables)
+// don't throw it off. Routines are named by label, or by address, with
+// (NMI), (IRQ) or (BRK) after interrupt handlers.
+pub struct Profiler {
+    pub symbols: Option<Symbols>,
+    // nodes[0] is the root above the routines the CPU starts in.
+    nodes: Vec<Node>,
+    stack: Vec<Call>,
+    routines: HashMap<String, RoutineStats>,
+    // Routines that got cycles in the current frame.
+    touched: Vec<String>,
+    // Where the instruction before this step started.
+    last_pc: u16,
+    last_cycle: Option<usize>,
+    first_frame: Option<usize>,
+    frame: usize,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        let root = Node {
+            name: String::new(),
+            parent: 0,
+            children: HashMap::new(),
+            calls: 0,
+            self_cycles: 0,
+            total_cycles: 0,
+        };
+        Profiler {
+            symbols: None,
+            nodes: vec![root],
+            stack: Vec::new(),
+            routines: HashMap::new(),
+            touched: Vec::new(),
+            last_pc: 0,
+            last_cycle: None,
+            first_frame: None,
+            frame: 0,
+        }
+    }
+
+    // Called before every instruction with what the last one did, where the
+    // CPU is now and its cycle count.
+    pub fn step<B: Bus>(&mut self, bus: &B, flow: Flow, pc: u16, sp: u8, cycles: usize) {
+        let elapsed = self.last_cycle.map_or(0, |last| cycles - last);
+        self.last_cycle = Some(cycles);
+        self.start_frame(ppu_position(cycles - elapsed).0);
+        let sp = sp as u16;
+        let last_pc = std::mem::replace(&mut self.last_pc, pc);
+
+        // The cycles of a JSR count for the caller and of an RTS for the
+        // routine returning, those of an interrupt sequence for the handler.
+        match flow {
+            Flow::Next => {}
+            Flow::Call => {
+                self.charge(bus, last_pc, elapsed);
+                self.unwind(sp + 2);
+                self.enter(bus, pc, "", sp);
+                return;
+            }
+            Flow::Return => {
+                self.charge(bus, last_pc, elapsed);
+                self.unwind(sp);
+                return;
+            }
+            Flow::Interrupt(Interrupt::Reset) => {
+                self.stack.clear();
+                self.enter(bus, pc, "", STACK_BOTTOM);
+            }
+            Flow::Interrupt(interrupt) => {
+                let tag = match interrupt {
+                    Interrupt::Nmi => "(NMI)",
+                    Interrupt::Irq => "(IRQ)",
+                    _ => "(BRK)",
+                };
+                self.unwind(sp + 3);
+                self.enter(bus, pc, tag, sp);
+            }
+        }
+        self.charge(bus, last_pc, elapsed);
+    }
+
+    // Video frames seen so far, the one in progress included.
+    pub fn frames(&self) -> usize {
+        self.first_frame.map_or(0, |first| self.frame - first + 1)
+    }
+
+    pub fn routine(&self, name: &str) -> Option<RoutineStats> {
+        self.routines.get(name).map(|stats| self.with_current_frame(stats))
+    }
+
+    // Routines by total cycles, most first.
+    pub fn flat(&self) -> String {
+        let mut routines: Vec<(&String, RoutineStats)> =
+            self.routines.iter().map(|(name, stats)| (name, self.with_current_frame(stats))).collect();
+        routines.sort_by(|a, b| b.1.total_cycles.cmp(&a.1.total_cycles).then(a.0.cmp(b.0)));
+        let all = self.nodes[0].total_cycles.max(1);
+        let frames = self.frames().max(1);
+
+        let mut lines = vec![format!("{} cycles over {} frames", self.nodes[0].total_cycles, self.frames())];
+        lines.push(format!("{:<24} {:>8} {:>12} {:>12} {:>7} {:>10} {:>10} {:>7}",
+                           "routine", "calls", "self", "total", "total%", "per frame", "max frame", "frame"));
+        for (name, stats) in routines {
+            lines.push(format!("{:<24} {:>8} {:>12} {:>12} {:>6.1}% {:>10} {:>10} {:>7}",
+                               name, stats.calls, stats.self_cycles, stats.total_cycles,
+                               stats.total_cycles as f64 * 100.0 / all as f64,
+                               stats.total_cycles / frames, stats.max_frame_cycles, stats.max_frame));
+        }
+        lines.join("\n")
+    }
+
+    // Every path of calls with its total and self cycles and calls, callees
+    // indented under their callers.
+    pub fn call_tree(&self) -> String {
+        let mut lines = Vec::new();
+        self.tree_lines(0, 0, &mut lines);
+        lines.join("\n")
+    }
+
+    // Folded stacks, "reset;main;update_player 1234" per path with self
+    // cycles, as flamegraph.pl and inferno take them.
+    pub fn folded(&self) -> String {
+        let mut lines: Vec<String> = (1..self.nodes.len())
+            .filter(|&i| self.nodes[i].self_cycles > 0)
+            .map(|i| {
+                let mut path = vec![self.nodes[i].name.as_str()];
+                let mut node = self.nodes[i].parent;
+                while node != 0 {
+                    path.push(&self.nodes[node].name);
+                    node = self.nodes[node].parent;
+                }
+                path.reverse();
+                format!("{} {}", path.join(";"), self.nodes[i].self_cycles)
+            })
+            .collect();
+        lines.sort();
+        lines.join("\n")
+    }
+
+    // Flat report and call tree.
+    pub fn report(&self) -> String {
+        format!("{}\n\n{}", self.flat(), self.call_tree())
+    }
+
+    fn tree_lines(&self, node: usize, depth: usize, lines: &mut Vec<String>) {
+        let mut children: Vec<usize> = self.nodes[node].children.values().copied().collect();
+        children.sort_by(|&a, &b| self.nodes[b].total_cycles.cmp(&self.nodes[a].total_cycles).then(a.cmp(&b)));
+        for child in children {
+            let child_node = &self.nodes[child];
+            let indent = "  ".repeat(depth);
+            lines.push(format!("{:<40} total {:>12} self {:>12} calls {:>8}",
+                               indent + &child_node.name, child_node.total_cycles, child_node.self_cycles, child_node.calls));
+            self.tree_lines(child, depth + 1, lines);
+        }
+    }
+
+    fn name<B: Bus>(&self, bus: &B, addr: u16) -> String {
+        match self.symbols.as_ref().and_then(|symbols| symbols.label(bus, addr)) {
+            Some(label) => label.to_string(),
+            None => format!("${:04X}", addr),
+        }
+    }
+
+    fn enter<B: Bus>(&mut self, bus: &B, addr: u16, tag: &str, sp: u16) {
+        let name = self.name(bus, addr) + tag;
+        let parent = self.stack.last().map_or(0, |call| call.node);
+        let node = match self.nodes[parent].children.get(&name) {
+            Some(&node) => node,
+            None => {
+                self.nodes.push(Node {
+                    name: name.clone(),
+                    parent,
+                    children: HashMap::new(),
+                    calls: 0,
+                    self_cycles: 0,
+                    total_cycles: 0,
+                });
+                let node = self.nodes.len() - 1;
+                self.nodes[parent].children.insert(name.clone(), node);
+                node
+            }
+        };
+        self.nodes[node].calls += 1;
+        self.routines.entry(name).or_default().calls += 1;
+        self.stack.push(Call { node, sp });
+    }
+
+    // Drops the calls whose stack space is given back with SP at sp.
+    fn unwind(&mut self, sp: u16) {
+        while self.stack.last().is_some_and(|call| call.sp < sp) {
+            self.stack.pop();
+        }
+    }
+
+    // pc is where the instruction that took cycles started.
+    fn charge<B: Bus>(&mut self, bus: &B, pc: u16, cycles: usize) {
+        if cycles == 0 {
+            return;
+        }
+        // Started mid-run, whatever ran is the bottom of the stack.
+        if self.stack.is_empty() {
+            self.enter(bus, pc, "", STACK_BOTTOM);
+        }
+        self.nodes[0].total_cycles += cycles;
+        let top = self.stack.len() - 1;
+        for i in 0..self.stack.len() {
+            let node = self.stack[i].node;
+            self.nodes[node].total_cycles += cycles;
+            if i == top {
+                self.nodes[node].self_cycles += cycles;
+            }
+            let name = &self.nodes[node].name;
+            // Recursion only counts the outermost call.
+            let recursive = self.stack[..i].iter().any(|outer| self.nodes[outer.node].name == *name);
+            let stats = self.routines.get_mut(name).unwrap();
+            if i == top {
+                stats.self_cycles += cycles;
+            }
+            if !recursive {
+                stats.total_cycles += cycles;
+                if stats.frame_cycles == 0 {
+                    self.touched.push(name.clone());
+                }
+                stats.frame_cycles += cycles;
+            }
+        }
+    }
+
+    fn start_frame(&mut self, frame: usize) {
+        if self.first_frame.is_none() {
+            self.first_frame = Some(frame);
+            self.frame = frame;
+        }
+        if frame == self.frame {
+            return;
+        }
+        for name in self.touched.drain(..) {
+            let stats = self.routines.get_mut(&name).unwrap();
+            if stats.frame_cycles > stats.max_frame_cycles {
+                stats.max_frame_cycles = stats.frame_cycles;
+                stats.max_frame = self.frame;
+            }
+            stats.frame_cycles = 0;
+        }
+        self.frame = frame;
+    }
+
+    // stats with the frame in progress taken into account for the maximum.
+    fn with_current_frame(&self, stats: &RoutineStats) -> RoutineStats {
+        let mut stats = stats.clone();
+        if stats.frame_cycles > stats.max_frame_cycles {
+            stats.max_frame_cycles = stats.frame_cycles;
+            stats.max_frame = self.frame;
+        }
+        stats
+    }
+}
@@ -0,0 +1,117 @@
+use crate::bus::cartridge::game_db::GameDb;
+use crate::bus::cartridge::Cartridge;
+use crate::bus::BUS;
+use crate::cpu::{Bus, CPU};
+
+// 341 dots * 262 scanlines / 3 dots per CPU cycle, rounded up.
+pub const CPU_CYCLES_PER_FRAME: usize = 29781;
+// blargg's ROMs ask for the reset button to be held down for at least 100ms.
+const RESET_DELAY_FRAMES: usize = 6;
+const MESSAGE_ADDRESS: u16 = 0x6004;
+const MESSAGE_MAX_LENGTH: u16 = 0x1000;
+
+// Values of $6000 once the signature is in place, anything below $80 is the
+// final result.
+const STATUS_RUNNING: u8 = 0x80;
+const STATUS_NEEDS_RESET: u8 = 0x81;
+const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum TestStatus {
+    Passed,
+    // The result code, 1 and up.
+    Failed(u8),
+    TimedOut,
+    // Address of the JAM opcode.
+    Jammed(u16),
+    // The ROM can't be loaded, the message says why.
+    Skipped,
+}
+
+#[derive(Clone, Debug)]
+pub struct TestRomResult {
+    pub status: TestStatus,
+    // Text from $6004, what the ROM would have printed on screen.
+    pub message: String,
+    pub frames: usize,
+}
+
+// Runs one of blargg's test ROMs headless, following the $6000 protocol:
+// $6001-$6003 hold DE B0 61 once $6000 is valid, $6000 is $80 while running,
+// $81 when the reset button should be pressed and the result code after that.
+pub fn run(rom: &[u8], timeout_frames: usize) -> TestRomResult {
+    let cartridge = match Cartridge::parse(rom, &GameDb::builtin()) {
+        Ok(cartridge) => cartridge,
+        Err(e) => return TestRomResult { status: TestStatus::Skipped, message: e, frames: 0 },
+    };
+    let mut bus = BUS::new();
+    bus.cartridge = Some(cartridge);
+    let mut cpu: CPU<BUS> = CPU::new(bus);
+    cpu.power_on();
+
+    let mut reset_at = None;
+    let mut frame = 0;
+    let status = loop {
+        for _ in 0..CPU_CYCLES_PER_FRAME {
+            cpu.clock();
+        }
+        frame += 1;
+        if cpu.halted() {
+            break TestStatus::Jammed(cpu.pc.wrapping_sub(1));
+        }
+
+        let signature = [cpu.bus.peek(0x6001), cpu.bus.peek(0x6002), cpu.bus.peek(0x6003)];
+        match cpu.bus.peek(0x6000) {
+            _ if signature != SIGNATURE => {}
+            STATUS_RUNNING => {}
+            STATUS_NEEDS_RESET => match reset_at {
+                None => reset_at = Some(frame + RESET_DELAY_FRAMES),
+                Some(at) if frame >= at => {
+                    reset_at = None;
+                    cpu.soft_reset();
+                }
+                Some(_) => {}
+            },
+            0 => break TestStatus::Passed,
+            code if code < STATUS_RUNNING => break TestStatus::Failed(code),
+            _ => {}
+        }
+        if frame >= timeout_frames {
+            break TestStatus::TimedOut;
+        }
+    };
+
+    TestRomResult {
+        status,
+        message: read_message(&cpu.bus),
+        frames: frame,
+    }
+}
+
+fn read_message(bus: &BUS) -> String {
+    let bytes: Vec<u8> = (MESSAGE_ADDRESS..MESSAGE_ADDRESS + MESSAGE_MAX_LENGTH)
+        .map(|addr| bus.peek(addr))
+        .take_while(|&byte| byte != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).trim().to_string()
+}
+
+// One line per ROM, with the first line of its message.
+pub fn format_table(results: &[(String, TestRomResult)]) -> String {
+    let width = results.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    let mut table = String::new();
+    for (name, result) in results {
+        let status = match result.status {
+            TestStatus::Passed => String::from("pass"),
+            TestStatus::Failed(code) => format!("FAIL #{}", code),
+            TestStatus::TimedOut => String::from("TIMEOUT"),
+            TestStatus::Jammed(addr) => format!("JAM ${:04X}", addr),
+            TestStatus::Skipped => String::from("SKIP"),
+        };
+        let message = result.message.lines().next().unwrap_or("");
+        table += &format!("{:<width$}  {:<10} {:>5} frames  {}\n", name, status, result.frames, message, width = width);
+    }
+    let count = |status: TestStatus| results.iter().filter(|(_, r)| r.status == status).count();
+    table += &format!("{}/{} passed, {} skipped\n", count(TestStatus::Passed), results.len(), count(TestStatus::Skipped));
+    table
+}
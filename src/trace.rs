@@ -0,0 +1,370 @@
+use crate::bus::ppu_position;
+use crate::cpu::{Bus, CpuVariant, Mode, CPU};
+use crate::disasm::{self, Symbols};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::rc::Rc;
+
+// Gets a look at every instruction before it runs, see CPU::tracer.
+pub trait Tracer {
+    // Asked before every instruction, the entry is only built when it says yes.
+    fn wants(&mut self, pc: u16, cycles: usize) -> bool;
+    fn trace(&mut self, entry: &TraceEntry);
+    // Labels to show in the disassembly.
+    fn symbols(&self) -> Option<&Symbols> {
+        None
+    }
+}
+
+// CPU state at an opcode fetch, before the instruction runs.
+#[derive(Clone, Debug)]
+pub struct TraceEntry {
+    pub pc: u16,
+    pub bytes: Vec<u8>,
+    pub mnemonic: &'static str,
+    pub mode: Mode,
+    pub official: bool,
+    pub disassembly: String,
+    // Memory the instruction works on (the target for JMP indirect) and what
+    // is there right now.
+    pub effective_address: Option<u16>,
+    pub value: Option<u8>,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub sp: u8,
+    pub cycles: usize,
+}
+
+impl TraceEntry {
+    pub fn capture<B: Bus>(cpu: &CPU<B>, symbols: Option<&Symbols>) -> TraceEntry {
+        let info = &cpu.opcodes()[cpu.bus.peek(cpu.pc) as usize];
+        let bytes: Vec<u8> = (0..info.bytes as u16).map(|i| cpu.bus.peek(cpu.pc.wrapping_add(i))).collect();
+        let effective_address = effective_address(cpu, info.mnemonic, info.mode);
+        let value = match info.mode {
+            Mode::Indirect => None,
+            _ => effective_address.map(|addr| cpu.bus.peek(addr)),
+        };
+        TraceEntry {
+            pc: cpu.pc,
+            bytes,
+            mnemonic: info.mnemonic,
+            mode: info.mode,
+            official: info.official,
+            disassembly: disasm::disassemble(&cpu.bus, cpu.opcodes(), cpu.pc, symbols).text,
+            effective_address,
+            value,
+            a: cpu.a,
+            x: cpu.x,
+            y: cpu.y,
+            p: cpu.p,
+            sp: cpu.sp,
+            cycles: cpu.cycle_count,
+        }
+    }
+
+    // Frame, scanline and dot.
+    pub fn ppu_position(&self) -> (usize, usize, usize) {
+        ppu_position(self.cycles)
+    }
+}
+
+// Where the operand ends up, peeking at pointers with the registers as they
+// are before the instruction. Jumps and branches have none except JMP indirect.
+fn effective_address<B: Bus>(cpu: &CPU<B>, mnemonic: &str, mode: Mode) -> Option<u16> {
+    let bus = &cpu.bus;
+    let low = bus.peek(cpu.pc.wrapping_add(1));
+    let word = u16::from_le_bytes([low, bus.peek(cpu.pc.wrapping_add(2))]);
+    let zero_page_word = |addr: u8| u16::from_le_bytes([bus.peek(addr as u16), bus.peek(addr.wrapping_add(1) as u16)]);
+    match mode {
+        Mode::ZeroPage => Some(low as u16),
+        Mode::ZeroPageX => Some(low.wrapping_add(cpu.x) as u16),
+        Mode::ZeroPageY => Some(low.wrapping_add(cpu.y) as u16),
+        Mode::Absolute if mnemonic == "JMP" || mnemonic == "JSR" => None,
+        Mode::Absolute => Some(word),
+        Mode::AbsoluteX => Some(word.wrapping_add(cpu.x as u16)),
+        Mode::AbsoluteY => Some(word.wrapping_add(cpu.y as u16)),
+        Mode::Indirect => {
+            // The NMOS chips don't carry into the high byte of the pointer.
+            let high_pointer = if cpu.variant() == CpuVariant::Cmos65C02 {
+                word.wrapping_add(1)
+            } else {
+                (word & 0xFF00) | (word.wrapping_add(1) & 0xFF)
+            };
+            Some(u16::from_le_bytes([bus.peek(word), bus.peek(high_pointer)]))
+        }
+        Mode::IndirectX => Some(zero_page_word(low.wrapping_add(cpu.x))),
+        Mode::IndirectY => Some(zero_page_word(low).wrapping_add(cpu.y as u16)),
+        Mode::ZeroPageIndirect => Some(zero_page_word(low)),
+        Mode::AbsoluteIndirectX => {
+            let pointer = word.wrapping_add(cpu.x as u16);
+            Some(u16::from_le_bytes([bus.peek(pointer), bus.peek(pointer.wrapping_add(1))]))
+        }
+        Mode::Immediate | Mode::Relative | Mode::Implied | Mode::Accumulator => None,
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum TraceFormat {
+    // Same columns as nestest.log.
+    Nestest,
+    // Close to Mesen's default trace logger row.
+    Mesen,
+    // One JSON object per instruction.
+    JsonLines,
+}
+
+impl TraceFormat {
+    // "nestest", "mesen" or "json".
+    pub fn parse(name: &str) -> Option<TraceFormat> {
+        match name {
+            "nestest" => Some(TraceFormat::Nestest),
+            "mesen" => Some(TraceFormat::Mesen),
+            "json" => Some(TraceFormat::JsonLines),
+            _ => None,
+        }
+    }
+
+    pub fn format(self, entry: &TraceEntry) -> String {
+        match self {
+            TraceFormat::Nestest => format_nestest(entry),
+            TraceFormat::Mesen => format_mesen(entry),
+            TraceFormat::JsonLines => format_json(entry),
+        }
+    }
+}
+
+// C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+fn format_nestest(entry: &TraceEntry) -> String {
+    let bytes: Vec<String> = entry.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    // Unofficial opcodes are marked with a * like nestest.log does.
+    let mark = if entry.official { ' ' } else { '*' };
+    let low = entry.bytes.get(1).copied().unwrap_or(0);
+    let mut asm = entry.disassembly.clone();
+    if let (Some(addr), value) = (entry.effective_address, entry.value) {
+        let value = value.unwrap_or(0);
+        asm += &match entry.mode {
+            Mode::Indirect => format!(" = {:04X}", addr),
+            Mode::ZeroPageX | Mode::ZeroPageY => format!(" @ {:02X} = {:02X}", addr, value),
+            Mode::AbsoluteX | Mode::AbsoluteY => format!(" @ {:04X} = {:02X}", addr, value),
+            Mode::IndirectX => format!(" @ {:02X} = {:04X} = {:02X}", low.wrapping_add(entry.x), addr, value),
+            Mode::IndirectY => format!(" = {:04X} @ {:04X} = {:02X}", addr.wrapping_sub(entry.y as u16), addr, value),
+            _ => format!(" = {:02X}", value),
+        };
+    }
+    let (_, scanline, dot) = entry.ppu_position();
+    format!("{:04X}  {:<8} {}{:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+            entry.pc, bytes.join(" "), mark, asm, entry.a, entry.x, entry.y, entry.p, entry.sp, scanline, dot, entry.cycles)
+}
+
+// C000  JMP $C5F5                      A:00 X:00 Y:00 S:FD P:nvUbdIzc  V:0   H:21  Fr:0 Cycle:7
+fn format_mesen(entry: &TraceEntry) -> String {
+    let mut asm = entry.disassembly.clone();
+    if let Some(addr) = entry.effective_address {
+        match entry.value {
+            Some(value) => asm += &format!(" [${:04X}] = ${:02X}", addr, value),
+            None => asm += &format!(" [${:04X}]", addr),
+        }
+    }
+    let flags: String = "NVUBDIZC".chars().enumerate()
+        .map(|(i, flag)| if entry.p & (0x80 >> i) != 0 { flag } else { flag.to_ascii_lowercase() })
+        .collect();
+    let (frame, scanline, dot) = entry.ppu_position();
+    format!("{:04X}  {:<30} A:{:02X} X:{:02X} Y:{:02X} S:{:02X} P:{}  V:{:<3} H:{:<3} Fr:{} Cycle:{}",
+            entry.pc, asm, entry.a, entry.x, entry.y, entry.sp, flags, scanline, dot, frame, entry.cycles)
+}
+
+fn format_json(entry: &TraceEntry) -> String {
+    let optional = |value: Option<u16>| value.map_or(String::from("null"), |v| v.to_string());
+    let bytes: Vec<String> = entry.bytes.iter().map(|b| b.to_string()).collect();
+    let asm: String = entry.disassembly.chars().flat_map(|c| match c {
+        '"' | '\\' => vec!['\\', c],
+        _ => vec![c],
+    }).collect();
+    let (frame, scanline, dot) = entry.ppu_position();
+    format!("{{\"pc\":{},\"bytes\":[{}],\"asm\":\"{}\",\"official\":{},\"addr\":{},\"value\":{},\"a\":{},\"x\":{},\"y\":{},\"p\":{},\"sp\":{},\"cycles\":{},\"frame\":{},\"scanline\":{},\"dot\":{}}}",
+            entry.pc, bytes.join(","), asm, entry.official, optional(entry.effective_address), optional(entry.value.map(u16::from)),
+            entry.a, entry.x, entry.y, entry.p, entry.sp, entry.cycles, frame, scanline, dot)
+}
+
+// Where formatted lines go.
+pub trait TraceSink {
+    fn write_line(&mut self, line: &str);
+    fn flush(&mut self) {}
+}
+
+pub struct StdoutSink;
+
+impl TraceSink for StdoutSink {
+    fn write_line(&mut self, line: &str) {
+        // A closed pipe just means nobody is reading any more.
+        let _ = writeln!(io::stdout().lock(), "{}", line);
+    }
+}
+
+pub struct FileSink {
+    writer: BufWriter<File>,
+    // The first write error, nothing more is written after one.
+    pub error: Option<io::Error>,
+}
+
+impl FileSink {
+    pub fn create(path: &str) -> io::Result<FileSink> {
+        Ok(FileSink {
+            writer: BufWriter::new(File::create(path)?),
+            error: None,
+        })
+    }
+}
+
+impl Drop for FileSink {
+    fn drop(&mut self) {
+        self.flush();
+        if let Some(e) = &self.error {
+            println!("Warning: Couldn't write the trace log: {}", e);
+        }
+    }
+}
+
+impl TraceSink for FileSink {
+    fn write_line(&mut self, line: &str) {
+        if self.error.is_none() {
+            if let Err(e) = writeln!(self.writer, "{}", line) {
+                self.error = Some(e);
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Err(e) = self.writer.flush() {
+            self.error.get_or_insert(e);
+        }
+    }
+}
+
+// Keeps the last capacity lines in memory. Clones share the same buffer, so
+// one can go into a TraceLogger and another be kept to read the lines back.
+#[derive(Clone)]
+pub struct RingBuffer {
+    lines: Rc<RefCell<VecDeque<String>>>,
+    capacity: usize,
+}
+
+impl RingBuffer {
+    pub fn new(capacity: usize) -> RingBuffer {
+        RingBuffer {
+            lines: Rc::new(RefCell::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    // Oldest first.
+    pub fn lines(&self) -> Vec<String> {
+        self.lines.borrow().iter().cloned().collect()
+    }
+
+    pub fn clear(&self) {
+        self.lines.borrow_mut().clear();
+    }
+}
+
+impl TraceSink for RingBuffer {
+    fn write_line(&mut self, line: &str) {
+        let mut lines = self.lines.borrow_mut();
+        if lines.len() == self.capacity {
+            lines.pop_front();
+        }
+        if self.capacity > 0 {
+            lines.push_back(line.to_string());
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum TraceCondition {
+    // PC inside first..=last.
+    Pc(u16, u16),
+    // Frame number reached.
+    Frame(usize),
+}
+
+impl TraceCondition {
+    // "pc:C000", "pc:C000-C0FF" or "frame:N".
+    pub fn parse(text: &str) -> Option<TraceCondition> {
+        let (kind, value) = text.split_once(':')?;
+        match kind {
+            "pc" => {
+                let (first, last) = value.split_once('-').unwrap_or((value, value));
+                Some(TraceCondition::Pc(u16::from_str_radix(first, 16).ok()?, u16::from_str_radix(last, 16).ok()?))
+            }
+            "frame" => value.parse().ok().map(TraceCondition::Frame),
+            _ => None,
+        }
+    }
+
+    fn matches(&self, pc: u16, cycles: usize) -> bool {
+        match *self {
+            TraceCondition::Pc(first, last) => (first..=last).contains(&pc),
+            TraceCondition::Frame(frame) => ppu_position(cycles).0 >= frame,
+        }
+    }
+}
+
+// Formats entries into a sink, between the first instruction matching start
+// and the first one matching stop. Without conditions it logs everything.
+pub struct TraceLogger {
+    pub sink: Box<dyn TraceSink>,
+    pub format: TraceFormat,
+    pub start: Option<TraceCondition>,
+    pub stop: Option<TraceCondition>,
+    pub symbols: Option<Symbols>,
+    started: bool,
+    stopped: bool,
+}
+
+impl TraceLogger {
+    pub fn new(sink: Box<dyn TraceSink>, format: TraceFormat) -> TraceLogger {
+        TraceLogger {
+            sink,
+            format,
+            start: None,
+            stop: None,
+            symbols: None,
+            started: false,
+            stopped: false,
+        }
+    }
+}
+
+impl Tracer for TraceLogger {
+    fn wants(&mut self, pc: u16, cycles: usize) -> bool {
+        if self.stopped {
+            return false;
+        }
+        if !self.started {
+            self.started = self.start.as_ref().is_none_or(|start| start.matches(pc, cycles));
+        }
+        if self.started && self.stop.as_ref().is_some_and(|stop| stop.matches(pc, cycles)) {
+            self.stopped = true;
+            self.sink.flush();
+        }
+        self.started && !self.stopped
+    }
+
+    fn trace(&mut self, entry: &TraceEntry) {
+        self.sink.write_line(&self.format.format(entry));
+    }
+
+    fn symbols(&self) -> Option<&Symbols> {
+        self.symbols.as_ref()
+    }
+}
+
+impl Drop for TraceLogger {
+    fn drop(&mut self) {
+        self.sink.flush();
+    }
+}
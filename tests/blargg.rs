@@ -0,0 +1,42 @@
+use rust_nes::test_rom::{self, TestStatus};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// blargg's cpu/ppu/apu/mapper test ROMs from https://github.com/christopherpow/nes-test-roms,
+// any folder layout under here works.
+const ROM_DIR: &str = "tests/roms/blargg";
+// The longest ones finish in well under 30 seconds.
+const TIMEOUT_FRAMES: usize = 30 * 60;
+
+fn find_roms(dir: &Path, roms: &mut Vec<PathBuf>) {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir).expect("Error: Cannot read test ROM folder")
+        .map(|entry| entry.expect("Error: Cannot read test ROM folder").path())
+        .collect();
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            find_roms(&path, roms);
+        } else if path.extension().is_some_and(|ext| ext == "nes") {
+            roms.push(path);
+        }
+    }
+}
+
+#[test]
+#[ignore = "needs blargg's test ROMs in tests/roms/blargg, which aren't in the repo"]
+fn blargg_test_roms() {
+    let mut roms = Vec::new();
+    find_roms(Path::new(ROM_DIR), &mut roms);
+
+    let results: Vec<_> = roms.iter().map(|path| {
+        let rom = fs::read(path).expect("Error: Cannot read test ROM");
+        let name = path.strip_prefix(ROM_DIR).unwrap().display().to_string();
+        (name, test_rom::run(&rom, TIMEOUT_FRAMES))
+    }).collect();
+
+    let table = test_rom::format_table(&results);
+    println!("{}", table);
+    // ROMs for mappers that aren't emulated yet are left out.
+    assert!(results.iter().all(|(_, result)| matches!(result.status, TestStatus::Passed | TestStatus::Skipped)),
+            "Error: Test ROMs failed\n{}", table);
+}
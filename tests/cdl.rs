@@ -0,0 +1,83 @@
+use rust_nes::bus::cartridge::Cartridge;
+use rust_nes::bus::BUS;
+use rust_nes::cdl::CodeDataLog;
+use rust_nes::cpu::CPU;
+
+// NROM image with 16KB of PRG ROM at $C000 and 8KB of CHR ROM, running
+//   C000  LDA #$40 / STA $10 / LDA #$C0 / STA $11
+//   C008  LDY #$01
+//   C00A  LDA ($10),Y    table + 1, through a pointer
+//   C00C  LDA $C040      table
+//   C00F  JMP ($C050)    to C060
+//   C040  table
+//   C050  .word $C060
+//   C060  JMP $C060
+fn cpu() -> CPU<BUS> {
+    let mut prg = vec![0; 0x4000];
+    prg[..0x12].copy_from_slice(&[
+        0xA9, 0x40, 0x85, 0x10, 0xA9, 0xC0, 0x85, 0x11, 0xA0, 0x01,
+        0xB1, 0x10, 0xAD, 0x40, 0xC0, 0x6C, 0x50, 0xC0,
+    ]);
+    prg[0x40..0x43].copy_from_slice(&[0x11, 0x22, 0x33]);
+    prg[0x50..0x52].copy_from_slice(&[0x60, 0xC0]);
+    prg[0x60..0x63].copy_from_slice(&[0x4C, 0x60, 0xC0]);
+    prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0xC0]);
+    let mut rom = b"NES\x1a\x01\x01".to_vec();
+    rom.extend_from_slice(&[0; 10]);
+    rom.extend(prg);
+    rom.extend(vec![0; 0x2000]);
+
+    let mut bus = BUS::new();
+    let cartridge = Cartridge::new(&rom);
+    bus.code_data_log = Some(CodeDataLog::for_cartridge(&cartridge));
+    bus.cartridge = Some(cartridge);
+    let mut cpu = CPU::new(bus);
+    cpu.power_on();
+    cpu
+}
+
+#[test]
+fn prg_bytes_by_use() {
+    let mut cpu = cpu();
+    while cpu.pc != 0xC060 || !cpu.complete() {
+        cpu.clock();
+    }
+    // Fetch the JMP $C060.
+    cpu.clock();
+    let log = cpu.bus.code_data_log.as_ref().unwrap();
+    // Everything here was mapped at $C000-$DFFF, bank bits 10.
+    assert!(log.prg[..0x12].iter().all(|&byte| byte == 0x09), "{:02X?}", &log.prg[..0x12]);
+    assert_eq!(log.prg[0x12], 0x00);
+    // Data, data read through a pointer, untouched.
+    assert_eq!(log.prg[0x40..0x43], [0x0A, 0x2A, 0x00]);
+    assert_eq!(log.prg[0x50..0x52], [0x0A, 0x0A]);
+    // Code reached through JMP ($xxxx).
+    assert_eq!(log.prg[0x60..0x63], [0x19, 0x09, 0x09]);
+    // The reset vector, mapped at $E000-$FFFF.
+    assert_eq!(log.prg[0x3FFC..0x3FFE], [0x0E, 0x0E]);
+    assert!(log.summary().starts_with("PRG: 21 code, 6 data"), "{}", log.summary());
+}
+
+#[test]
+fn chr_bytes_by_use() {
+    let mut cpu = cpu();
+    cpu.bus.ppu_read(0x0010);
+    cpu.bus.ppu_read_port(0x1000);
+    cpu.bus.ppu_read_port(0x0010);
+    let log = cpu.bus.code_data_log.as_ref().unwrap();
+    assert_eq!((log.chr[0x0010], log.chr[0x1000], log.chr[0x0011]), (0x03, 0x02, 0x00));
+}
+
+#[test]
+fn fceux_file_layout() {
+    let mut log = CodeDataLog::new(0x4000, 0x2000);
+    log.log_prg(0x0123, 0x8123, rust_nes::cdl::CODE);
+    log.log_chr(0x0456, rust_nes::cdl::RENDERED);
+    let bytes = log.to_bytes();
+    assert_eq!(bytes.len(), 0x6000);
+    assert_eq!((bytes[0x0123], bytes[0x4456]), (0x01, 0x01));
+    assert_eq!(CodeDataLog::parse(&bytes, 0x4000, 0x2000), Some(log));
+    assert_eq!(CodeDataLog::parse(&bytes, 0x8000, 0x2000), None);
+    // Without its CHR flags it isn't taken either, saving it would lose them.
+    assert_eq!(CodeDataLog::parse(&bytes[..0x4000], 0x4000, 0x2000), None);
+}
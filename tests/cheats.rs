@@ -0,0 +1,88 @@
+use rust_nes::bus::cartridge::Cartridge;
+use rust_nes::bus::BUS;
+use rust_nes::cheats::{Cheat, CheatKind, Cheats};
+use rust_nes::cpu::Bus;
+use rust_nes::heatmap::Heatmap;
+
+fn cheat(kind: CheatKind, addr: u16, value: u8, compare: Option<u8>, name: &str) -> Cheat {
+    Cheat { kind, addr, value, compare, enabled: true, name: name.to_string() }
+}
+
+// NROM image with 16KB of PRG ROM at $C000, $C000-$C001 holding $A9 $05.
+fn bus() -> BUS {
+    let mut prg = vec![0; 0x4000];
+    prg[..2].copy_from_slice(&[0xA9, 0x05]);
+    let mut rom = b"NES\x1a\x01\x00".to_vec();
+    rom.extend_from_slice(&[0; 10]);
+    rom.extend(prg);
+    let mut bus = BUS::new();
+    bus.cartridge = Some(Cartridge::new(&rom));
+    bus
+}
+
+#[test]
+fn codes() {
+    assert_eq!(Cheat::parse("SXIOPO"), Ok(cheat(CheatKind::Read, 0x91D9, 0xAD, None, "SXIOPO")));
+    assert_eq!(Cheat::parse("yeuzugsx"), Ok(cheat(CheatKind::Read, 0xACB3, 0x0F, Some(0xA5), "YEUZUGSX")));
+    assert_eq!(Cheat::parse("00075A09"), Ok(cheat(CheatKind::Freeze, 0x075A, 0x09, None, "00075A09")));
+    assert_eq!(Cheat::parse("0300:63"), Ok(cheat(CheatKind::Freeze, 0x0300, 0x63, None, "")));
+    assert_eq!(Cheat::parse("6000?12:34"), Ok(cheat(CheatKind::Read, 0x6000, 0x34, Some(0x12), "")));
+    // Too short, and a Pro Action Replay code outside RAM.
+    assert!(Cheat::parse("SXIOP").is_err());
+    assert!(Cheat::parse("00C00009").is_err());
+    // The third letter says 6 letters or 8, and doesn't agree with the length.
+    assert!(Cheat::parse("SXUOPO").is_err());
+    assert!(Cheat::parse("YEIZUGSX").is_err());
+}
+
+#[test]
+fn read_cheats_and_freezes() {
+    let mut bus = bus();
+    bus.cheats.list = vec![
+        cheat(CheatKind::Read, 0xC000, 0xAD, Some(0xA9), "matches"),
+        cheat(CheatKind::Read, 0xC001, 0x63, Some(0x99), "doesn't match"),
+        cheat(CheatKind::Freeze, 0x0810, 0x09, None, "through a mirror"),
+    ];
+    assert_eq!((bus.read(0xC000), bus.read(0xC001)), (0xAD, 0x05));
+    assert_eq!(bus.peek(0xC000), 0xAD);
+
+    bus.heatmap = Some(Heatmap::new());
+    bus.power_on();
+    bus.tick();
+    assert_eq!(bus.memory[0x10], 0x00);
+    // The first frame ends 29781 cycles in.
+    while bus.memory[0x10] == 0x00 {
+        bus.tick();
+    }
+    // The frozen byte counts as written, reading it isn't uninitialized.
+    let heatmap = bus.heatmap.as_mut().unwrap();
+    assert_eq!(heatmap.writes[0x0810], 1);
+    heatmap.used(0x0010);
+    assert!(heatmap.uninitialized_reads().is_empty());
+    bus.write(0x0010, 0x01);
+    assert_eq!(bus.read(0x0010), 0x01);
+
+    bus.cheats.list[0].enabled = false;
+    assert_eq!(bus.read(0xC000), 0xA9);
+}
+
+#[test]
+fn fceux_cht_files() {
+    // As FCEUX writes them: S for a substitute (Read) cheat, C for a compare
+    // value, then ':' only when the cheat is off.
+    let text = "\
+075a:09:Infinite lives
+SC91d9:ad:a9:Infinite lives: SXIOPO
+:0300:63:
+S:c001:ea:Disabled
+";
+    let cheats = Cheats::parse(text).unwrap();
+    assert_eq!(cheats.list, [
+        cheat(CheatKind::Freeze, 0x075A, 0x09, None, "Infinite lives"),
+        cheat(CheatKind::Read, 0x91D9, 0xAD, Some(0xA9), "Infinite lives: SXIOPO"),
+        Cheat { enabled: false, ..cheat(CheatKind::Freeze, 0x0300, 0x63, None, "") },
+        Cheat { enabled: false, ..cheat(CheatKind::Read, 0xC001, 0xEA, None, "Disabled") },
+    ]);
+    assert_eq!(cheats.to_text(), text);
+    assert!(Cheats::parse("Szzzz:00:Bad").is_err());
+}
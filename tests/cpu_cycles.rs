@@ -0,0 +1,193 @@
+// Every CPU cycle does exactly one bus access, the one the 6502 does on it.
+use rust_nes::cpu::{Bus, CPU};
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Access {
+    Read(u16),
+    Write(u16, u8),
+}
+use Access::{Read, Write};
+
+// Bytes to load and where.
+type Code<'a> = &'a [(u16, &'a [u8])];
+
+// 64KB of RAM that logs every access, with the NMI line asserted from a cycle on.
+struct Recorder {
+    memory: Vec<u8>,
+    accesses: Vec<Access>,
+    cycles: usize,
+    nmi_from: usize,
+}
+
+impl Bus for Recorder {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.accesses.push(Read(addr));
+        self.memory[addr as usize]
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.accesses.push(Write(addr, data));
+        self.memory[addr as usize] = data;
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+
+    fn tick(&mut self) {
+        self.cycles += 1;
+    }
+
+    fn nmi_line(&self) -> bool {
+        self.cycles >= self.nmi_from
+    }
+}
+
+fn cpu_with(code: Code) -> CPU<Recorder> {
+    let mut memory = vec![0; 0x10000];
+    for (addr, bytes) in code {
+        memory[*addr as usize..*addr as usize + bytes.len()].copy_from_slice(bytes);
+    }
+    let mut cpu = CPU::new(Recorder { memory, accesses: Vec::new(), cycles: 0, nmi_from: usize::MAX });
+    cpu.pc = code[0].0;
+    cpu
+}
+
+// Runs one instruction, returns how many cycles it took.
+fn run_instruction(cpu: &mut CPU<Recorder>) -> usize {
+    let start = cpu.cycle_count;
+    cpu.clock();
+    while !cpu.complete() {
+        cpu.clock();
+    }
+    cpu.cycle_count - start
+}
+
+#[test]
+fn cycle_counts() {
+    // Code, X and Y, cycles
+    let cases: &[(Code, u8, usize)] = &[
+        (&[(0x0200, &[0xA9, 0x01])], 0, 2),                         // LDA #$01
+        (&[(0x0200, &[0xAD, 0x00, 0x03])], 0, 4),                   // LDA $0300
+        (&[(0x0200, &[0xBD, 0x00, 0x03])], 1, 4),                   // LDA $0300,X
+        (&[(0x0200, &[0xBD, 0xFF, 0x03])], 1, 5),                   // LDA $03FF,X, page crossed
+        (&[(0x0200, &[0xB1, 0x10]), (0x0010, &[0x00, 0x03])], 1, 5), // LDA ($10),Y
+        (&[(0x0200, &[0xB1, 0x10]), (0x0010, &[0xFF, 0x03])], 1, 6), // LDA ($10),Y, page crossed
+        (&[(0x0200, &[0x9D, 0x00, 0x03])], 1, 5),                   // STA $0300,X
+        (&[(0x0200, &[0xFE, 0x00, 0x03])], 1, 7),                   // INC $0300,X
+        (&[(0x0200, &[0xD3, 0x10])], 1, 8),                         // DCP ($10),Y
+        (&[(0x0200, &[0xB0, 0x02])], 0, 2),                         // BCS, not taken
+        (&[(0x0200, &[0x90, 0x02])], 0, 3),                         // BCC, taken
+        (&[(0x02F0, &[0x90, 0x20])], 0, 4),                         // BCC, taken to the next page
+        (&[(0x0200, &[0x48])], 0, 3),                               // PHA
+        (&[(0x0200, &[0x68])], 0, 4),                               // PLA
+        (&[(0x0200, &[0x6C, 0x00, 0x03])], 0, 5),                   // JMP ($0300)
+        (&[(0x0200, &[0x20, 0x00, 0x03])], 0, 6),                   // JSR $0300
+        (&[(0x0200, &[0x60])], 0, 6),                               // RTS
+        (&[(0x0200, &[0x40])], 0, 6),                               // RTI
+        (&[(0x0200, &[0x00])], 0, 7),                               // BRK
+    ];
+    for (code, index, cycles) in cases {
+        let mut cpu = cpu_with(code);
+        cpu.x = *index;
+        cpu.y = *index;
+        let opcode = code[0].1[0];
+        assert_eq!(run_instruction(&mut cpu), *cycles, "Opcode {:02X}", opcode);
+        assert_eq!(cpu.bus.accesses.len(), *cycles, "Opcode {:02X} bus accesses", opcode);
+    }
+}
+
+#[test]
+fn page_crossing_dummy_reads() {
+    // LDA $03FF,X reads $0301 before the carry gets into the high byte.
+    let mut cpu = cpu_with(&[(0x0200, &[0xBD, 0xFF, 0x03])]);
+    cpu.x = 2;
+    run_instruction(&mut cpu);
+    assert_eq!(cpu.bus.accesses, [Read(0x0200), Read(0x0201), Read(0x0202), Read(0x0301), Read(0x0401)]);
+
+    // Writes always spend that cycle, even when no page is crossed.
+    let mut cpu = cpu_with(&[(0x0200, &[0x9D, 0x00, 0x03])]);
+    cpu.x = 1;
+    cpu.a = 0x42;
+    run_instruction(&mut cpu);
+    assert_eq!(cpu.bus.accesses, [Read(0x0200), Read(0x0201), Read(0x0202), Read(0x0301), Write(0x0301, 0x42)]);
+}
+
+#[test]
+fn branch_dummy_reads() {
+    // BCC +1 from $02FD lands on $0300, reading $02FF and then $0200 while PCH is fixed up.
+    let mut cpu = cpu_with(&[(0x02FD, &[0x90, 0x01])]);
+    run_instruction(&mut cpu);
+    assert_eq!(cpu.bus.accesses, [Read(0x02FD), Read(0x02FE), Read(0x02FF), Read(0x0200)]);
+    assert_eq!(cpu.pc, 0x0300);
+}
+
+#[test]
+fn read_modify_write_dummy_write() {
+    // INC $0300 writes the old value back before the new one.
+    let mut cpu = cpu_with(&[(0x0200, &[0xEE, 0x00, 0x03]), (0x0300, &[0x05])]);
+    run_instruction(&mut cpu);
+    assert_eq!(cpu.bus.accesses,
+               [Read(0x0200), Read(0x0201), Read(0x0202), Read(0x0300), Write(0x0300, 0x05), Write(0x0300, 0x06)]);
+}
+
+#[test]
+fn nmi_hijacks_brk() {
+    // BRK, with the IRQ/BRK vector at $0400 and the NMI one at $0500.
+    let mut cpu = cpu_with(&[(0x0200, &[0x00]), (0xFFFA, &[0x00, 0x05, 0x00, 0x00, 0x00, 0x04]), (0x0500, &[0xEA])]);
+    cpu.bus.nmi_from = 2;
+    assert_eq!(run_instruction(&mut cpu), 7);
+
+    assert_eq!(cpu.pc, 0x0500);
+    assert_eq!(&cpu.bus.accesses[5..], [Read(0xFFFA), Read(0xFFFB)]);
+    // Still pushed as a BRK, with B set, returning past its padding byte.
+    assert_eq!(cpu.bus.accesses[2..5], [Write(0x01FD, 0x02), Write(0x01FC, 0x02), Write(0x01FB, 0x34)]);
+
+    // The NMI was taken, it doesn't run again after the handler's first instruction.
+    run_instruction(&mut cpu);
+    assert_eq!(cpu.pc, 0x0501);
+}
+
+#[test]
+fn jam_stops_bus_accesses() {
+    let mut cpu = cpu_with(&[(0x0200, &[0x02])]);
+    for _ in 0..10 {
+        cpu.clock();
+    }
+    assert!(cpu.halted());
+    assert_eq!(cpu.cycle_count, 7 + 10);
+    assert_eq!(cpu.bus.accesses, [Read(0x0200), Read(0x0201)]);
+}
+
+// A bus only has to outlive its CPU.
+struct Borrowed<'a>(&'a mut [u8]);
+
+impl Bus for Borrowed<'_> {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.0[addr as usize]
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.0[addr as usize] = data;
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.0[addr as usize]
+    }
+}
+
+#[test]
+fn borrowed_bus() {
+    let mut memory = vec![0; 0x10000];
+    // LDA #$42, STA $10
+    memory[0x0200..0x0204].copy_from_slice(&[0xA9, 0x42, 0x85, 0x10]);
+    let mut cpu = CPU::new(Borrowed(&mut memory));
+    cpu.pc = 0x0200;
+    for _ in 0..5 {
+        cpu.clock();
+    }
+    assert!(cpu.complete());
+    assert_eq!(cpu.opcodes()[0x85].mnemonic, "STA");
+    drop(cpu);
+    assert_eq!(memory[0x10], 0x42);
+}
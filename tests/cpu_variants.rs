@@ -0,0 +1,108 @@
+// What differs between the 2A03, the NMOS 6502 and the 65C02.
+use rust_nes::cpu::{CpuVariant, CPU};
+use rust_nes::flat_memory::FlatMemory;
+
+const VARIANTS: [CpuVariant; 3] = [CpuVariant::Ricoh2A03, CpuVariant::Nmos6502, CpuVariant::Cmos65C02];
+const DECIMAL: u8 = 0b1000;
+const ZERO: u8 = 0b10;
+
+// Runs code from $0200, instructions at a time.
+fn run(variant: CpuVariant, code: &[u8], instructions: usize) -> CPU<FlatMemory> {
+    run_with(variant, code, &[], instructions)
+}
+
+// With data loaded as well. The IRQ/BRK vector points at $0400.
+fn run_with(variant: CpuVariant, code: &[u8], data: &[(u16, &[u8])], instructions: usize) -> CPU<FlatMemory> {
+    let mut memory = FlatMemory::new();
+    memory.load(0xFFFE, &[0x00, 0x04]);
+    for (addr, bytes) in data {
+        memory.load(*addr, bytes);
+    }
+    memory.load(0x0200, code);
+    let mut cpu = CPU::with_variant(memory, variant);
+    cpu.pc = 0x0200;
+    for _ in 0..instructions {
+        cpu.clock();
+        while !cpu.complete() {
+            cpu.clock();
+        }
+    }
+    cpu
+}
+
+#[test]
+fn decimal_mode() {
+    // SED, CLC, LDA #$19, ADC #$28
+    let adc = [0xF8, 0x18, 0xA9, 0x19, 0x69, 0x28];
+    // SED, SEC, LDA #$42, SBC #$13
+    let sbc = [0xF8, 0x38, 0xA9, 0x42, 0xE9, 0x13];
+    for (variant, sum, difference) in [(CpuVariant::Ricoh2A03, 0x41, 0x2F),
+                                       (CpuVariant::Nmos6502, 0x47, 0x29),
+                                       (CpuVariant::Cmos65C02, 0x47, 0x29)] {
+        assert_eq!(run(variant, &adc, 4).a, sum, "{:?}", variant);
+        assert_eq!(run(variant, &sbc, 4).a, difference, "{:?}", variant);
+    }
+
+    // SED, CLC, LDA #$99, ADC #$01 is 00 and a carry. The NMOS chip takes Z
+    // from the binary sum, $9A, the 65C02 from the result.
+    let wrap = [0xF8, 0x18, 0xA9, 0x99, 0x69, 0x01];
+    let nmos = run(CpuVariant::Nmos6502, &wrap, 4);
+    assert_eq!((nmos.a, nmos.p & ZERO), (0x00, 0));
+    let cmos = run(CpuVariant::Cmos65C02, &wrap, 4);
+    assert_eq!((cmos.a, cmos.p & ZERO), (0x00, ZERO));
+
+    // And it spends a cycle more on the decimal adjust.
+    let start = 7 + 2 + 2 + 2;
+    assert_eq!(nmos.cycle_count - start, 2);
+    assert_eq!(cmos.cycle_count - start, 3);
+}
+
+#[test]
+fn jmp_indirect_page_wrap() {
+    // JMP ($02FF), with $34 at $02FF, $12 at $0200 and $56 at $0300.
+    for variant in VARIANTS {
+        let cpu = run_with(variant, &[0x6C, 0xFF, 0x02], &[(0x02FF, &[0x34, 0x56])], 1);
+        // The NMOS chips read the high byte from the start of the same page.
+        let (target, cycles) = if variant == CpuVariant::Cmos65C02 { (0x5634, 6) } else { (0x6C34, 5) };
+        assert_eq!(cpu.pc, target, "{:?}", variant);
+        assert_eq!(cpu.cycle_count - 7, cycles, "{:?}", variant);
+    }
+}
+
+#[test]
+fn cmos_opcodes() {
+    // LDX #$11, STZ $10, PHX, INC A, BRA +1, (skipped), LDA ($20), TSB $10
+    let code = [0xA2, 0x11, 0x64, 0x10, 0xDA, 0x1A, 0x80, 0x01, 0xEA, 0xB2, 0x20, 0x04, 0x10];
+    let data: &[(u16, &[u8])] = &[(0x0010, &[0xFF]), (0x0020, &[0x00, 0x03]), (0x0300, &[0x81])];
+    let cpu = run_with(CpuVariant::Cmos65C02, &code, data, 7);
+    assert_eq!(cpu.bus.memory[0x01FD], 0x11);
+    assert_eq!(cpu.a, 0x81);
+    assert_eq!(cpu.bus.memory[0x0010], 0x81);
+    assert_eq!(cpu.pc, 0x020D);
+
+    // On the NMOS chips STZ $10 is a NOP and LDA ($20) a JAM.
+    let mut nmos = run(CpuVariant::Nmos6502, &[0x64, 0x10, 0xB2, 0x20], 1);
+    assert_eq!(nmos.bus.memory[0x0010], 0x00);
+    for _ in 0..3 {
+        nmos.clock();
+    }
+    assert!(nmos.halted());
+
+    // The undefined opcodes are all NOPs on the 65C02: $02 skips its operand.
+    let cmos = run(CpuVariant::Cmos65C02, &[0x02, 0xFF, 0xA9, 0x01], 2);
+    assert!(!cmos.halted());
+    assert_eq!(cmos.a, 0x01);
+}
+
+#[test]
+fn brk_clears_decimal_on_cmos() {
+    // SED, BRK
+    for variant in VARIANTS {
+        let cpu = run(variant, &[0xF8, 0x00], 2);
+        assert_eq!(cpu.pc, 0x0400, "{:?}", variant);
+        let decimal = if variant == CpuVariant::Cmos65C02 { 0 } else { DECIMAL };
+        assert_eq!(cpu.p & DECIMAL, decimal, "{:?}", variant);
+        // Pushed with D still set either way.
+        assert_eq!(cpu.bus.memory[0x01FB] & DECIMAL, DECIMAL, "{:?}", variant);
+    }
+}
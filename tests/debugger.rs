@@ -0,0 +1,62 @@
+use rust_nes::bus::cartridge::game_db::GameDb;
+use rust_nes::bus::cartridge::Cartridge;
+use rust_nes::bus::BUS;
+use rust_nes::cpu::CPU;
+use rust_nes::debugger::Debugger;
+
+// NROM with code at $C000, then NOPs. CHR-RAM, so $2007 writes stick.
+fn cpu_with(code: &[u8]) -> CPU<BUS> {
+    let mut image = b"NES\x1a\x01\x00".to_vec();
+    image.resize(16, 0);
+    let mut prg = vec![0xEA; 0x4000];
+    prg[..code.len()].copy_from_slice(code);
+    prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0xC0]);
+    image.extend_from_slice(&prg);
+
+    let mut bus = BUS::new();
+    bus.cartridge = Some(Cartridge::parse(&image, &GameDb::parse("")).unwrap());
+    let mut cpu = CPU::new(bus);
+    cpu.pc = 0xC000;
+    cpu
+}
+
+// LDA #$42, STA $0300
+fn cpu() -> CPU<BUS> {
+    cpu_with(&[0xA9, 0x42, 0x8D, 0x00, 0x03])
+}
+
+#[test]
+fn cpu_watchpoint() {
+    let mut cpu = cpu();
+    let mut debugger = Debugger::new(None);
+    debugger.command(&mut cpu, "w w 0300").unwrap();
+    let output = debugger.command(&mut cpu, "c").unwrap();
+    assert!(output.contains("watch w $0300-$0300"), "{}", output);
+    assert_eq!(cpu.bus.memory[0x0300], 0x42);
+}
+
+#[test]
+fn ppu_watchpoints() {
+    // $1234 into PPUADDR, STA $2007 of $42 there, $1234 again, then two
+    // LDA $2007: the first only fills the read buffer.
+    let mut cpu = cpu_with(&[
+        0xA9, 0x12, 0x8D, 0x06, 0x20, 0xA9, 0x34, 0x8D, 0x06, 0x20, 0xA9, 0x42, 0x8D, 0x07, 0x20,
+        0xA9, 0x12, 0x8D, 0x06, 0x20, 0xA9, 0x34, 0x8D, 0x06, 0x20, 0xAD, 0x07, 0x20, 0xAD, 0x07, 0x20,
+    ]);
+    let mut debugger = Debugger::new(None);
+    assert_eq!(debugger.command(&mut cpu, "w ppu w 1234").unwrap(), "1 watch ppu w $1234-$1234");
+    let output = debugger.command(&mut cpu, "c").unwrap();
+    assert!(output.starts_with("1 watch ppu w $1234-$1234: write $42 at $1234"), "{}", output);
+    assert_eq!(cpu.pc, 0xC00F);
+
+    debugger.command(&mut cpu, "del 1").unwrap();
+    debugger.command(&mut cpu, "w ppu r 1234").unwrap();
+    let output = debugger.command(&mut cpu, "c").unwrap();
+    assert!(output.starts_with("1 watch ppu r $1234-$1234: read $42 at $1234"), "{}", output);
+    assert_eq!(cpu.pc, 0xC01C);
+    debugger.command(&mut cpu, "s").unwrap();
+    assert_eq!(cpu.a, 0x42);
+
+    // Nothing executes from PPU memory.
+    assert_eq!(debugger.command(&mut cpu, "w ppu x 0000").unwrap(), "Bad watch kind x");
+}
@@ -0,0 +1,58 @@
+use rust_nes::cpu::CPU;
+use rust_nes::disasm::Symbols;
+use rust_nes::expr::Expr;
+use rust_nes::flat_memory::FlatMemory;
+
+fn cpu() -> CPU<FlatMemory> {
+    let mut memory = FlatMemory::new();
+    memory.load(0x00FE, &[0x03, 0x12]);
+    let mut cpu = CPU::new(memory);
+    cpu.a = 0x20;
+    cpu.x = 0x05;
+    cpu.p = 0x25;
+    cpu.pc = 0xC123;
+    cpu
+}
+
+fn eval(text: &str) -> i64 {
+    let mut symbols = Symbols::new();
+    symbols.insert(0x00FE, "lives");
+    let cpu = cpu();
+    Expr::parse(text, &|label| symbols.address(&cpu.bus, label)).unwrap_or_else(|error| panic!("{}: {}", text, error)).eval(&cpu)
+}
+
+#[test]
+fn operands() {
+    assert_eq!(eval("A"), 0x20);
+    assert_eq!(eval("x + y + sp"), 0x05 + 0xFD);
+    assert_eq!(eval("PC"), 0xC123);
+    assert_eq!(eval("c + i * 2 + z * 4"), 3);
+    assert_eq!(eval("[$00FE]"), 3);
+    assert_eq!(eval("{$FE}"), 0x1203);
+    assert_eq!(eval("[lives + 1]"), 0x12);
+    assert_eq!(eval("$10 + 0x10 + 10"), 42);
+    // CPU::new leaves the CPU 7 cycles in, 21 dots into the first scanline.
+    assert_eq!(eval("cycle"), 7);
+    assert_eq!(eval("frame * 1000 + scanline * 100 + dot"), 21);
+}
+
+#[test]
+fn operators() {
+    assert_eq!(eval("[$00FE] == 3 && A > $10"), 1);
+    assert_eq!(eval("[$00FE] == 3 && A > $20"), 0);
+    assert_eq!(eval("1 + 2 * 3"), 7);
+    assert_eq!(eval("(1 + 2) * 3"), 9);
+    assert_eq!(eval("1 << 4 | 1"), 17);
+    assert_eq!(eval("a & $F0 ^ $30"), 0x10);
+    assert_eq!(eval("-x + 10"), 5);
+    assert_eq!(eval("!a || ~0 == -1"), 1);
+    assert_eq!(eval("7 / 2 + 7 % 2"), 4);
+    assert_eq!(eval("1 / 0"), 0);
+}
+
+#[test]
+fn errors() {
+    for text in ["", "[$FE", "1 +", "A B", "nowhere", "$G1", "1 ? 2"] {
+        assert!(Expr::parse(text, &|_| None).is_err(), "{} parsed", text);
+    }
+}
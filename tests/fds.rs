@@ -0,0 +1,145 @@
+use rust_nes::bus::cartridge::Cartridge;
+
+const SIDE_SIZE: usize = 65500;
+
+// A side with just the disk info block, "*NINTENDO-HVC*" after the block code,
+// then the file amount block.
+fn side() -> Vec<u8> {
+    let mut side = vec![0; SIDE_SIZE];
+    side[0] = 0x01;
+    side[1..15].copy_from_slice(b"*NINTENDO-HVC*");
+    side[56] = 0x02;
+    side
+}
+
+fn fwnes(sides: usize) -> Vec<u8> {
+    let mut image = b"FDS\x1a".to_vec();
+    image.push(sides as u8);
+    image.extend_from_slice(&[0; 11]);
+    for _ in 0..sides {
+        image.extend(side());
+    }
+    image
+}
+
+fn disk(image: &[u8]) -> Cartridge {
+    Cartridge::new_disk(image, &[0; 0x2000])
+}
+
+// Clocks until the drive raises its IRQ for the next byte and reads it.
+fn next_byte(cartridge: &mut Cartridge) -> u8 {
+    let mut cycles = 0;
+    while !cartridge.irq() {
+        cartridge.clock();
+        cycles += 1;
+        assert!(cycles < 1_000_000, "No byte from the drive");
+    }
+    cartridge.cpu_read(0x4031)
+}
+
+#[test]
+fn fwnes_and_raw_images() {
+    assert_eq!(disk(&fwnes(2)).disk_side_count(), 2);
+    let mut raw = side();
+    raw.extend(side());
+    assert_eq!(disk(&raw).disk_side_count(), 2);
+    // QD images have 64KB sides with the block CRCs in them.
+    let mut qd = vec![0; 0x10000];
+    qd[0] = 0x01;
+    assert_eq!(disk(&qd).disk_side_count(), 1);
+}
+
+#[test]
+fn drive_reads_blocks_after_the_gap() {
+    let mut cartridge = disk(&fwnes(1));
+    // Motor on, read mode, ready, disk IRQ on.
+    cartridge.cpu_write(0x4025, 0xC5);
+    // The 0x80 start mark isn't handed over, the block code is the first byte.
+    assert_eq!(next_byte(&mut cartridge), 0x01);
+    let text: Vec<u8> = (0..14).map(|_| next_byte(&mut cartridge)).collect();
+    assert_eq!(text, b"*NINTENDO-HVC*");
+}
+
+#[test]
+fn side_switching() {
+    let mut cartridge = disk(&fwnes(2));
+    assert_eq!(cartridge.disk_side(), Some(0));
+    assert_eq!(cartridge.cpu_read(0x4032) & 0x01, 0x00);
+    cartridge.insert_disk_side(None);
+    assert_eq!(cartridge.disk_side(), None);
+    // No disk, write protected.
+    assert_eq!(cartridge.cpu_read(0x4032) & 0x05, 0x05);
+    cartridge.insert_disk_side(Some(1));
+    assert_eq!(cartridge.disk_side(), Some(1));
+    cartridge.cpu_write(0x4025, 0xC5);
+    assert_eq!(next_byte(&mut cartridge), 0x01);
+}
+
+#[test]
+#[should_panic(expected = "Disk has 2 sides")]
+fn missing_side() {
+    disk(&fwnes(2)).insert_disk_side(Some(2));
+}
+
+#[test]
+#[should_panic(expected = "fwNES header is too short")]
+fn short_fwnes_header() {
+    disk(b"FDS\x1a\x01");
+}
+
+#[test]
+fn timer_irq_reloads() {
+    let mut cartridge = disk(&fwnes(1));
+    cartridge.cpu_write(0x4020, 3);
+    cartridge.cpu_write(0x4021, 0);
+    // Enabled, repeating.
+    cartridge.cpu_write(0x4022, 0x03);
+    for _ in 0..2 {
+        for _ in 0..3 {
+            cartridge.clock();
+        }
+        assert!(!cartridge.irq());
+        cartridge.clock();
+        assert!(cartridge.irq());
+        // Acknowledged by reading $4030, which has it in bit 0.
+        assert_eq!(cartridge.cpu_read(0x4030) & 0x01, 0x01);
+        assert!(!cartridge.irq());
+    }
+
+    // Without repeat it fires once.
+    cartridge.cpu_write(0x4022, 0x02);
+    for _ in 0..4 {
+        cartridge.clock();
+    }
+    assert!(cartridge.irq());
+    cartridge.cpu_read(0x4030);
+    for _ in 0..100 {
+        cartridge.clock();
+    }
+    assert!(!cartridge.irq());
+}
+
+#[test]
+fn status_register() {
+    let mut cartridge = disk(&fwnes(1));
+    // The head starts at the end of the disk.
+    assert_eq!(cartridge.cpu_read(0x4030), 0x40);
+    cartridge.cpu_write(0x4025, 0xC5);
+    next_byte(&mut cartridge);
+    next_byte(&mut cartridge);
+    // Byte transferred, then acknowledged by the read.
+    while !cartridge.irq() {
+        cartridge.clock();
+    }
+    assert_eq!(cartridge.cpu_read(0x4030), 0x02);
+    assert!(!cartridge.irq());
+    assert_eq!(cartridge.cpu_read(0x4030), 0x00);
+
+    // Disk registers read 0 with disk I/O off.
+    cartridge.cpu_write(0x4020, 0);
+    cartridge.cpu_write(0x4022, 0x02);
+    cartridge.clock();
+    cartridge.cpu_write(0x4023, 0x00);
+    assert!(!cartridge.irq());
+    assert_eq!(cartridge.cpu_read(0x4030), 0x00);
+}
@@ -0,0 +1,183 @@
+use rust_nes::bus::cartridge::Cartridge;
+use rust_nes::bus::BUS;
+use rust_nes::cpu::CPU;
+use rust_nes::gdb;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// NROM image running
+//   C000  LDA #$10
+//   C002  STA $0200
+//   C005  INX
+//   C006  JMP $C005
+fn rom() -> Vec<u8> {
+    let mut prg = vec![0xEA; 0x4000];
+    prg[..9].copy_from_slice(&[0xA9, 0x10, 0x8D, 0x00, 0x02, 0xE8, 0x4C, 0x05, 0xC0]);
+    prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0xC0]);
+    let mut rom = b"NES\x1a\x01\x00".to_vec();
+    rom.extend_from_slice(&[0; 10]);
+    rom.extend(prg);
+    rom
+}
+
+// A stub on a free port with the CPU through its reset sequence, and a client connected to it.
+fn start() -> (TcpStream, JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        let mut bus = BUS::new();
+        bus.cartridge = Some(Cartridge::new(&rom()));
+        let mut cpu = CPU::new(bus);
+        cpu.power_on();
+        cpu.clock();
+        while !cpu.complete() {
+            cpu.clock();
+        }
+        gdb::serve(&mut cpu, &listener).unwrap();
+    });
+    let client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    client.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    (client, server)
+}
+
+fn send(client: &mut TcpStream, payload: &str) {
+    let checksum = payload.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+    write!(client, "${}#{:02x}", payload, checksum).unwrap();
+}
+
+fn read_byte(client: &mut TcpStream) -> u8 {
+    let mut byte = [0u8];
+    client.read_exact(&mut byte).unwrap();
+    byte[0]
+}
+
+fn read_reply(client: &mut TcpStream) -> String {
+    // Skip the ack for the request.
+    let mut byte = read_byte(client);
+    while byte == b'+' {
+        byte = read_byte(client);
+    }
+    assert_eq!(byte, b'$');
+    let mut payload = Vec::new();
+    loop {
+        match read_byte(client) {
+            b'#' => break,
+            byte => payload.push(byte),
+        }
+    }
+    let checksum = String::from_utf8(vec![read_byte(client), read_byte(client)]).unwrap();
+    let expected = payload.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+    assert_eq!(checksum, format!("{:02x}", expected));
+    client.write_all(b"+").unwrap();
+    String::from_utf8(payload).unwrap()
+}
+
+fn request(client: &mut TcpStream, payload: &str) -> String {
+    send(client, payload);
+    read_reply(client)
+}
+
+fn finish(mut client: TcpStream, server: JoinHandle<()>) {
+    send(&mut client, "k");
+    server.join().unwrap();
+}
+
+#[test]
+fn registers_and_memory() {
+    let (mut client, server) = start();
+    assert_eq!(request(&mut client, "qSupported:multiprocess+"), "PacketSize=1000;qXfer:features:read+");
+    assert_eq!(request(&mut client, "?"), "S05");
+    // a, x, y, p, sp, pc
+    assert_eq!(request(&mut client, "g"), "00000024fd00c0");
+    assert_eq!(request(&mut client, "mc000,3"), "a9108d");
+
+    assert_eq!(request(&mut client, "M0300,2:beef"), "OK");
+    assert_eq!(request(&mut client, "m300,2"), "beef");
+    assert_eq!(request(&mut client, "P0=7f"), "OK");
+    assert_eq!(request(&mut client, "p0"), "7f");
+    assert_eq!(request(&mut client, "G01020324fe0080"), "OK");
+    assert_eq!(request(&mut client, "p5"), "0080");
+    assert_eq!(request(&mut client, "p9"), "E01");
+    finish(client, server);
+}
+
+#[test]
+fn target_description() {
+    let (mut client, server) = start();
+    let mut xml = String::new();
+    loop {
+        let reply = request(&mut client, &format!("qXfer:features:read:target.xml:{:x},40", xml.len()));
+        let (more, data) = reply.split_at(1);
+        xml.push_str(data);
+        if more == "l" {
+            break;
+        }
+        assert_eq!(more, "m");
+    }
+    assert!(xml.contains(r#"<reg name="a" bitsize="8""#));
+    assert!(xml.contains(r#"<reg name="sp" bitsize="8""#));
+    assert!(xml.contains(r#"<reg name="pc" bitsize="16""#));
+    assert!(xml.trim_end().ends_with("</target>"));
+    assert_eq!(request(&mut client, "qXfer:features:read:other.xml:0,40"), "E00");
+    finish(client, server);
+}
+
+#[test]
+fn breakpoints_and_single_step() {
+    let (mut client, server) = start();
+    assert_eq!(request(&mut client, "Z0,c005,1"), "OK");
+    assert_eq!(request(&mut client, "c"), "S05");
+    assert_eq!(request(&mut client, "p5"), "05c0");
+    assert_eq!(request(&mut client, "p0"), "10");
+
+    assert_eq!(request(&mut client, "s"), "S05");
+    assert_eq!(request(&mut client, "p5"), "06c0");
+    assert_eq!(request(&mut client, "p1"), "01");
+
+    // Around the loop once and back onto the breakpoint.
+    assert_eq!(request(&mut client, "c"), "S05");
+    assert_eq!(request(&mut client, "p5"), "05c0");
+    assert_eq!(request(&mut client, "z0,c005,1"), "OK");
+    finish(client, server);
+}
+
+#[test]
+fn watchpoints() {
+    let (mut client, server) = start();
+    assert_eq!(request(&mut client, "Z2,200,1"), "OK");
+    assert_eq!(request(&mut client, "c"), "T05watch:0200;");
+    assert_eq!(request(&mut client, "p5"), "05c0");
+    assert_eq!(request(&mut client, "m200,1"), "10");
+    assert_eq!(request(&mut client, "z2,200,1"), "OK");
+    finish(client, server);
+}
+
+#[test]
+fn interrupt_while_running() {
+    let (mut client, server) = start();
+    send(&mut client, "c");
+    thread::sleep(Duration::from_millis(50));
+    client.write_all(&[0x03]).unwrap();
+    assert_eq!(read_reply(&mut client), "S02");
+    // Still in the INX/JMP loop.
+    let pc = request(&mut client, "p5");
+    assert!(pc == "05c0" || pc == "06c0", "pc {}", pc);
+    finish(client, server);
+}
+
+#[test]
+fn packet_sent_while_running() {
+    let (mut client, server) = start();
+    send(&mut client, "c");
+    thread::sleep(Duration::from_millis(50));
+    // Sent while the CPU runs, it's answered once the interrupt stops it.
+    send(&mut client, "M0300,1:42");
+    thread::sleep(Duration::from_millis(50));
+    client.write_all(&[0x03]).unwrap();
+    assert_eq!(read_reply(&mut client), "S02");
+    assert_eq!(read_reply(&mut client), "OK");
+    assert_eq!(request(&mut client, "m300,1"), "42");
+    finish(client, server);
+}
@@ -0,0 +1,76 @@
+use rust_nes::bus::cartridge::Cartridge;
+use rust_nes::bus::BUS;
+use rust_nes::cpu::CPU;
+use rust_nes::heatmap::{Heatmap, UninitializedRead};
+
+// NROM image with 16KB of PRG ROM at $C000, running
+//   C000  LDA #$01 / STA $10 / LDA $10
+//   C006  LDA $0300          uninitialized
+//   C009  LDX #$01
+//   C00B  LDA $02FF,X        uninitialized, the dummy read of $0200 isn't
+//   C00E  PLA                uninitialized, nothing was pushed
+//   C00F  JSR $C020          pushed, then pulled by the RTS
+//   C012  JMP $C012
+//   C020  RTS
+fn cpu() -> CPU<BUS> {
+    let mut prg = vec![0; 0x4000];
+    prg[..0x15].copy_from_slice(&[
+        0xA9, 0x01, 0x85, 0x10, 0xA5, 0x10, 0xAD, 0x00, 0x03, 0xA2, 0x01,
+        0xBD, 0xFF, 0x02, 0x68, 0x20, 0x20, 0xC0, 0x4C, 0x12, 0xC0,
+    ]);
+    prg[0x20] = 0x60;
+    prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0xC0]);
+    let mut rom = b"NES\x1a\x01\x00".to_vec();
+    rom.extend_from_slice(&[0; 10]);
+    rom.extend(prg);
+
+    let mut bus = BUS::new();
+    bus.cartridge = Some(Cartridge::new(&rom));
+    bus.heatmap = Some(Heatmap::new());
+    let mut cpu = CPU::new(bus);
+    cpu.power_on();
+    cpu
+}
+
+#[test]
+fn uninitialized_reads_of_a_program() {
+    let mut cpu = cpu();
+    while cpu.pc != 0xC012 || !cpu.complete() {
+        cpu.clock();
+    }
+    let heatmap = cpu.bus.heatmap.as_ref().unwrap();
+    assert_eq!(heatmap.uninitialized_reads(), [
+        UninitializedRead { addr: 0x01FE, pc: 0xC00E, count: 1 },
+        UninitializedRead { addr: 0x0300, pc: 0xC006, count: 1 },
+        UninitializedRead { addr: 0x0300, pc: 0xC00B, count: 1 },
+    ]);
+    assert_eq!((heatmap.executes[0xC000], heatmap.executes[0xC001], heatmap.executes[0xC020]), (1, 0, 1));
+    assert_eq!((heatmap.writes[0x0010], heatmap.reads[0x0010]), (1, 1));
+    // Dummy reads still count as reads.
+    assert_eq!((heatmap.reads[0x0200], heatmap.reads[0x0300]), (1, 2));
+}
+
+#[test]
+fn mirrors_and_power_on() {
+    let mut heatmap = Heatmap::new();
+    heatmap.execute(0x8000);
+    heatmap.write(0x0810);
+    heatmap.used(0x0010);
+    heatmap.used(0x1811);
+    // Not RAM.
+    heatmap.used(0x6000);
+    assert_eq!(heatmap.uninitialized_reads(), [UninitializedRead { addr: 0x0011, pc: 0x8000, count: 1 }]);
+
+    heatmap.power_on();
+    heatmap.used(0x0010);
+    heatmap.used(0x0010);
+    let report = heatmap.report();
+    assert!(report.starts_with("\
+2 uninitialized RAM reads
+$0010 read by the instruction at $8000 (2x)
+$0011 read by the instruction at $8000 (1x)
+"), "{}", report);
+    assert!(report.contains("$08xx             0            1            0"), "{}", report);
+    // The write through the mirror, in the map of RAM writes.
+    assert!(report.contains(&format!("\n$0000 {}.", " ".repeat(16))), "{}", report);
+}
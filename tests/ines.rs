@@ -0,0 +1,152 @@
+use rust_nes::bus::cartridge::game_db::GameDb;
+use rust_nes::bus::cartridge::{Cartridge, Mirroring};
+
+// A 32KB PRG, 8KB CHR image after the header, filled with zeros.
+fn ines(header: [u8; 16]) -> Vec<u8> {
+    let mut image = header.to_vec();
+    image.resize(16 + 0x8000 + 0x2000, 0);
+    image
+}
+
+fn header(flags6: u8, flags7: u8) -> [u8; 16] {
+    let mut header = [0u8; 16];
+    header[..4].copy_from_slice(b"NES\x1a");
+    header[4] = 2;
+    header[5] = 1;
+    header[6] = flags6;
+    header[7] = flags7;
+    header
+}
+
+fn parse(image: &[u8]) -> Result<Cartridge, String> {
+    Cartridge::parse(image, &GameDb::parse(""))
+}
+
+#[test]
+fn ines_header() {
+    let cartridge = parse(&ines(header(0b0011, 0))).unwrap();
+    assert_eq!(cartridge.header.mapper_number, 0);
+    assert_eq!(cartridge.header.mirroring, Mirroring::Vertical);
+    assert!(cartridge.header.battery);
+    assert_eq!((cartridge.header.prg_rom_pages, cartridge.header.chr_rom_pages), (2, 1));
+    assert_eq!(cartridge.header.prg_ram_pages, 1);
+
+    // The mapper's high nibble comes from byte 7.
+    assert_eq!(parse(&ines(header(0, 0x40))).err().unwrap(), "Mapper 64 isn't supported");
+    assert_eq!(parse(&ines(header(0x10, 0))).err().unwrap(), "Mapper 1 isn't supported");
+}
+
+#[test]
+fn diskdude_header() {
+    let mut dirty = header(0, 0);
+    dirty[7..16].copy_from_slice(b"DiskDude!");
+    // 'D' in byte 7 would make it mapper 64.
+    let cartridge = parse(&ines(dirty)).unwrap();
+    assert_eq!(cartridge.header.mapper_number, 0);
+    assert_eq!(cartridge.header.prg_ram_pages, 1);
+}
+
+#[test]
+fn nes2_header() {
+    // Byte 8 holds mapper bits 8-11.
+    let mut nes2 = header(0x10, 0x48);
+    nes2[8] = 0x01;
+    assert_eq!(parse(&ines(nes2)).err().unwrap(), "Mapper 321 isn't supported");
+
+    // 8KB of PRG-RAM and 8KB of battery backed PRG-RAM, with bytes 12-15 set,
+    // which isn't taken for a DiskDude header.
+    let mut nes2 = header(0b0010, 0x08);
+    nes2[10] = 0x77;
+    nes2[12..16].copy_from_slice(&[1, 1, 1, 1]);
+    let cartridge = parse(&ines(nes2)).unwrap();
+    assert_eq!(cartridge.header.mapper_number, 0);
+    assert_eq!(cartridge.header.prg_ram_pages, 2);
+
+    let mut nes2 = header(0, 0x08);
+    nes2[9] = 0x0f;
+    assert!(parse(&ines(nes2)).is_err());
+}
+
+#[test]
+fn short_rom() {
+    let mut image = ines(header(0, 0));
+    image.truncate(16 + 0x8000);
+    assert_eq!(parse(&image).err().unwrap(), "ROM is shorter than its header says");
+    assert!(parse(b"NES\x1a").is_err());
+}
+
+#[test]
+fn builtin_game_db_fixes_mirroring() {
+    // These last bytes give the image the CRC32 of Super Mario Bros., 3337EC46,
+    // which is in the built-in database as vertically mirrored.
+    let mut image = ines(header(0, 0));
+    let end = image.len();
+    image[end - 4..].copy_from_slice(&[0xC0, 0xDB, 0x28, 0xBD]);
+
+    let cartridge = Cartridge::new(&image);
+    assert_eq!(cartridge.header.mirroring, Mirroring::Vertical);
+    let header_override = cartridge.header_override.unwrap();
+    assert_eq!(header_override.crc32, 0x3337EC46);
+    assert_eq!(header_override.changes, vec!["mirroring Horizontal -> Vertical"]);
+
+    // Without the database the header is taken as it is.
+    assert_eq!(parse(&image).unwrap().header.mirroring, Mirroring::Horizontal);
+
+    // The hash leaves out a trainer, which isn't loaded.
+    let mut trained = image[..16].to_vec();
+    trained[6] |= 0b100;
+    trained.extend_from_slice(&[0xFF; 512]);
+    trained.extend_from_slice(&image[16..]);
+    let cartridge = Cartridge::new(&trained);
+    assert_eq!(cartridge.header.mirroring, Mirroring::Vertical);
+    assert_eq!(cartridge.header_override.unwrap().crc32, 0x3337EC46);
+    trained.truncate(trained.len() - 1);
+    assert_eq!(parse(&trained).err().unwrap(), "ROM is shorter than its header says");
+
+    // NES 2.0 headers are trusted.
+    image[7] = 0x08;
+    let cartridge = Cartridge::new(&image);
+    assert_eq!(cartridge.header.mirroring, Mirroring::Horizontal);
+    assert!(cartridge.header_override.is_none());
+}
+
+#[test]
+fn game_db_entries() {
+    assert!(!GameDb::builtin().is_empty());
+
+    // Entries first in the database win, like --gamedb's over the built-in ones.
+    let mut game_db = GameDb::parse("3337EC46;;;h;1;8;Override\n");
+    game_db.extend(GameDb::builtin());
+    let mut image = ines(header(0, 0));
+    let end = image.len();
+    image[end - 4..].copy_from_slice(&[0xC0, 0xDB, 0x28, 0xBD]);
+    let cartridge = Cartridge::parse(&image, &game_db).unwrap();
+    assert_eq!(cartridge.header.mirroring, Mirroring::Horizontal);
+    assert!(cartridge.header.battery);
+    assert_eq!(cartridge.header_override.unwrap().name, "Override");
+
+    // A SHA-1 that doesn't match leaves the header alone.
+    let game_db = GameDb::parse(&format!("3337EC46;{};;v;;;Wrong", "0".repeat(40)));
+    let cartridge = Cartridge::parse(&image, &game_db).unwrap();
+    assert_eq!(cartridge.header.mirroring, Mirroring::Horizontal);
+    assert!(cartridge.header_override.is_none());
+}
+
+#[test]
+fn chr_writes() {
+    // CHR-ROM ignores them.
+    let mut image = ines(header(0, 0));
+    image[16 + 0x8000 + 0x10] = 0x77;
+    let mut cartridge = parse(&image).unwrap();
+    cartridge.ppu_write(0x0010, 0x11);
+    assert_eq!(cartridge.ppu_read(0x0010), 0x77);
+
+    // Without CHR-ROM there's 8KB of CHR-RAM.
+    let mut no_chr = header(0, 0);
+    no_chr[5] = 0;
+    let mut image = no_chr.to_vec();
+    image.resize(16 + 0x8000, 0);
+    let mut cartridge = parse(&image).unwrap();
+    cartridge.ppu_write(0x1FFF, 0x11);
+    assert_eq!(cartridge.ppu_read(0x1FFF), 0x11);
+}
@@ -0,0 +1,171 @@
+// When IRQs and NMIs get taken, down to the cycle. Cycles are counted from 0,
+// a line "from" cycle N is first seen by the poll at the end of cycle N - 1.
+use rust_nes::cpu::{Bus, IrqSource, CPU};
+use std::ops::Range;
+
+const NMI_VECTOR: u16 = 0xFFFA;
+const IRQ_VECTOR: u16 = 0xFFFE;
+
+struct Lines {
+    memory: Vec<u8>,
+    cycles: usize,
+    irq: Range<usize>,
+    nmi_from: usize,
+    // Cycles the low bytes of the vectors were read on.
+    vector_reads: Vec<(usize, u16)>,
+}
+
+impl Bus for Lines {
+    fn read(&mut self, addr: u16) -> u8 {
+        if addr == NMI_VECTOR || addr == IRQ_VECTOR {
+            self.vector_reads.push((self.cycles, addr));
+        }
+        self.memory[addr as usize]
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.memory[addr as usize] = data;
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+
+    fn tick(&mut self) {
+        self.cycles += 1;
+    }
+
+    fn irq_lines(&self) -> u8 {
+        if self.irq.contains(&self.cycles) { IrqSource::Mapper as u8 } else { 0 }
+    }
+
+    fn nmi_line(&self) -> bool {
+        self.cycles >= self.nmi_from
+    }
+}
+
+// code at $0200, NOPs everywhere else. The NMI handler is at $0500, the IRQ one at $0400.
+fn cpu_with(code: &[u8], irq: Range<usize>, nmi_from: usize) -> CPU<Lines> {
+    let mut memory = vec![0xEA; 0x10000];
+    memory[0x0200..0x0200 + code.len()].copy_from_slice(code);
+    memory[0xFFFA..].copy_from_slice(&[0x00, 0x05, 0x00, 0x00, 0x00, 0x04]);
+    let mut cpu = CPU::new(Lines { memory, cycles: 0, irq, nmi_from, vector_reads: Vec::new() });
+    cpu.pc = 0x0200;
+    cpu.p &= !0b100;
+    cpu
+}
+
+fn run(cpu: &mut CPU<Lines>, cycles: usize) -> &[(usize, u16)] {
+    for _ in 0..cycles {
+        cpu.clock();
+    }
+    &cpu.bus.vector_reads
+}
+
+// The return address an interrupt pushed.
+fn pushed_pc(cpu: &CPU<Lines>) -> u16 {
+    u16::from_le_bytes([cpu.bus.memory[0x01FC], cpu.bus.memory[0x01FD]])
+}
+
+#[test]
+fn polled_on_the_second_to_last_cycle() {
+    // NOP on cycles 0-1, NOP on 2-3. The interrupt sequence takes 7 cycles
+    // and reads the vector on its sixth.
+    let mut cpu = cpu_with(&[], 1..usize::MAX, usize::MAX);
+    assert_eq!(run(&mut cpu, 20)[0], (7, IRQ_VECTOR));
+    assert_eq!(pushed_pc(&cpu), 0x0201);
+
+    // Too late for the first NOP, its last cycle isn't polled.
+    let mut cpu = cpu_with(&[], 2..usize::MAX, usize::MAX);
+    assert_eq!(run(&mut cpu, 20)[0], (9, IRQ_VECTOR));
+    assert_eq!(pushed_pc(&cpu), 0x0202);
+
+    let mut cpu = cpu_with(&[], 1..usize::MAX, usize::MAX);
+    cpu.bus.nmi_from = 1;
+    cpu.bus.irq = 0..0;
+    assert_eq!(run(&mut cpu, 20)[0], (7, NMI_VECTOR));
+}
+
+#[test]
+fn irq_is_a_level() {
+    // Gone again before the next poll that would have acted on it.
+    let mut cpu = cpu_with(&[], 2..3, usize::MAX);
+    assert!(run(&mut cpu, 20).is_empty());
+
+    // Held, but with I set.
+    let mut cpu = cpu_with(&[], 0..usize::MAX, usize::MAX);
+    cpu.p |= 0b100;
+    assert!(run(&mut cpu, 20).is_empty());
+}
+
+#[test]
+fn cli_sei_and_plp_take_effect_an_instruction_late() {
+    // CLI, the IRQ comes after the NOP that follows it.
+    let mut cpu = cpu_with(&[0x58], 0..usize::MAX, usize::MAX);
+    cpu.p |= 0b100;
+    assert_eq!(run(&mut cpu, 20)[0], (9, IRQ_VECTOR));
+    assert_eq!(pushed_pc(&cpu), 0x0202);
+
+    // SEI, still interrupted right after it, pushing I set.
+    let mut cpu = cpu_with(&[0x78], 0..usize::MAX, usize::MAX);
+    assert_eq!(run(&mut cpu, 20)[0], (7, IRQ_VECTOR));
+    assert_eq!(pushed_pc(&cpu), 0x0201);
+    assert_eq!(cpu.bus.memory[0x01FB] & 0b100, 0b100);
+
+    // PLP of a clear I on cycles 0-3, then a NOP.
+    let mut cpu = cpu_with(&[0x28], 0..usize::MAX, usize::MAX);
+    cpu.p |= 0b100;
+    cpu.sp = 0xFC;
+    cpu.bus.memory[0x01FD] = 0x20;
+    assert_eq!(run(&mut cpu, 20)[0], (11, IRQ_VECTOR));
+    assert_eq!(pushed_pc(&cpu), 0x0202);
+}
+
+#[test]
+fn taken_branch_without_page_crossing_polls_early() {
+    // BCC +0 on cycles 0-2, then NOPs. Seen on cycle 0, taken after the branch.
+    let mut cpu = cpu_with(&[0x90, 0x00], 1..usize::MAX, usize::MAX);
+    assert_eq!(run(&mut cpu, 20)[0], (8, IRQ_VECTOR));
+    assert_eq!(pushed_pc(&cpu), 0x0202);
+
+    // Seen on its second cycle, the second-to-last, it waits for the NOP.
+    let mut cpu = cpu_with(&[0x90, 0x00], 2..usize::MAX, usize::MAX);
+    assert_eq!(run(&mut cpu, 20)[0], (10, IRQ_VECTOR));
+    assert_eq!(pushed_pc(&cpu), 0x0203);
+
+    let mut cpu = cpu_with(&[0x90, 0x00], 0..0, 2);
+    assert_eq!(run(&mut cpu, 20)[0], (10, NMI_VECTOR));
+
+    // Crossing a page it polls on its third cycle like anything else:
+    // BCC from $02FD to $0300 on cycles 0-3.
+    let mut cpu = cpu_with(&[], 3..usize::MAX, usize::MAX);
+    cpu.pc = 0x02FD;
+    cpu.bus.memory[0x02FD..0x02FF].copy_from_slice(&[0x90, 0x01]);
+    assert_eq!(run(&mut cpu, 20)[0], (9, IRQ_VECTOR));
+    assert_eq!(pushed_pc(&cpu), 0x0300);
+}
+
+#[test]
+fn nmi_hijacks_irq() {
+    // The IRQ sequence runs on cycles 2-8, an NMI seen by the end of its fourth
+    // cycle makes it use the NMI vector.
+    let mut cpu = cpu_with(&[], 1..usize::MAX, 6);
+    assert_eq!(run(&mut cpu, 20), [(7, NMI_VECTOR)]);
+    assert_eq!(cpu.bus.memory[0x01FB] & 0b10000, 0);
+
+    // Any later and the IRQ handler's first instruction runs before the NMI.
+    let mut cpu = cpu_with(&[], 1..usize::MAX, 7);
+    assert_eq!(run(&mut cpu, 20), [(7, IRQ_VECTOR), (16, NMI_VECTOR)]);
+}
+
+#[test]
+fn nmi_hijacks_brk() {
+    // BRK on cycles 0-6, the pushed flags keep B set either way.
+    let mut cpu = cpu_with(&[0x00], 0..0, 4);
+    assert_eq!(run(&mut cpu, 7), [(5, NMI_VECTOR)]);
+    assert_eq!(cpu.bus.memory[0x01FB] & 0b10000, 0b10000);
+    assert_eq!(pushed_pc(&cpu), 0x0202);
+
+    let mut cpu = cpu_with(&[0x00], 0..0, 5);
+    assert_eq!(run(&mut cpu, 20), [(5, IRQ_VECTOR), (14, NMI_VECTOR)]);
+}
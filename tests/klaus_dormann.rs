@@ -0,0 +1,35 @@
+use rust_nes::cpu::{CpuVariant, CPU};
+use rust_nes::flat_memory::FlatMemory;
+
+// Binary from https://github.com/Klaus2m5/6502_65C02_functional_tests, assembled
+// with the default options (decimal tests on, loaded at $0000, code at $0400).
+const ROM_PATH: &str = "tests/roms/6502_functional_test.bin";
+const START: u16 = 0x0400;
+const SUCCESS: u16 = 0x3469;
+
+#[test]
+#[ignore = "needs tests/roms/6502_functional_test.bin, which isn't in the repo"]
+fn functional_test() {
+    let rom = std::fs::read(ROM_PATH).unwrap_or_else(|e| panic!("Error: Cannot read {}: {}", ROM_PATH, e));
+
+    let mut memory = FlatMemory::new();
+    memory.load(0x0000, &rom);
+    let mut cpu = CPU::with_variant(memory, CpuVariant::Nmos6502);
+    cpu.pc = START;
+
+    // Every failed check in the test is a branch to itself, and so is success.
+    let mut last_pc = None;
+    loop {
+        cpu.clock();
+        assert!(!cpu.halted(), "Error: jammed at ${:04X}", cpu.pc);
+        if !cpu.complete() {
+            continue;
+        }
+        if last_pc == Some(cpu.pc) {
+            break;
+        }
+        last_pc = Some(cpu.pc);
+    }
+
+    assert_eq!(cpu.pc, SUCCESS, "Error: trapped at ${:04X}", cpu.pc);
+}
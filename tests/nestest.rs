@@ -0,0 +1,95 @@
+use rust_nes::bus::BUS;
+use rust_nes::cpu::{Bus, CPU};
+use rust_nes::trace::{TraceEntry, TraceFormat};
+
+// nestest.nes and its reference log from https://www.qmtpro.com/~nes/misc/,
+// next to the other test ROMs.
+const ROM_PATH: &str = "tests/roms/nestest.nes";
+const LOG_PATH: &str = "tests/roms/nestest.log";
+// Lines of the log shown before the first one that differs.
+const CONTEXT_LINES: usize = 5;
+
+// The parts of a log line that don't depend on how the disassembly is written.
+#[derive(PartialEq, Debug)]
+struct TraceLine {
+    pc: u16,
+    bytes: Vec<u8>,
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    sp: u8,
+    scanline: usize,
+    dot: usize,
+    cycles: usize,
+}
+
+impl TraceLine {
+    // C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+    fn parse(line: &str) -> TraceLine {
+        let field = |name: &str| {
+            let start = line.find(name).unwrap_or_else(|| panic!("Error: No {} in {}", name, line)) + name.len();
+            line[start..].split(' ').next().unwrap()
+        };
+        let hex = |name: &str| u8::from_str_radix(field(name), 16).expect("Error: Bad register in log");
+        let ppu_start = line.find("PPU:").expect("Error: No PPU in log") + 4;
+        let mut ppu = line[ppu_start..].split(',').map(|s| s.trim_start());
+        let scanline = ppu.next().unwrap().parse().expect("Error: Bad scanline in log");
+        let dot = ppu.next().unwrap().split(' ').next().unwrap().parse().expect("Error: Bad dot in log");
+
+        TraceLine {
+            pc: u16::from_str_radix(&line[0..4], 16).expect("Error: Bad PC in log"),
+            bytes: line[6..14].split_whitespace().map(|b| u8::from_str_radix(b, 16).unwrap()).collect(),
+            a: hex("A:"),
+            x: hex("X:"),
+            y: hex("Y:"),
+            p: hex("P:"),
+            sp: hex("SP:"),
+            scanline,
+            dot,
+            cycles: field("CYC:").parse().expect("Error: Bad cycle count in log"),
+        }
+    }
+}
+
+#[test]
+#[ignore = "needs tests/roms/nestest.nes and tests/roms/nestest.log, which aren't in the repo"]
+fn nestest_matches_reference_log() {
+    let rom = std::fs::read(ROM_PATH).unwrap_or_else(|e| panic!("Error: Cannot read {}: {}", ROM_PATH, e));
+    let log = std::fs::read_to_string(LOG_PATH).unwrap_or_else(|e| panic!("Error: Cannot read {}: {}", LOG_PATH, e));
+    let expected: Vec<&str> = log.lines().filter(|line| !line.is_empty()).collect();
+
+    let mut bus = BUS::new();
+    bus.cartridge = Some(rust_nes::bus::cartridge::Cartridge::new(&rom));
+    let mut cpu = CPU::new(bus);
+    cpu.power_on();
+    cpu.clock();
+    while !cpu.complete() {
+        cpu.clock();
+    }
+    // Automation mode starts at $C000 instead of the reset vector and needs no PPU.
+    cpu.pc = 0xC000;
+
+    for (i, line) in expected.iter().enumerate() {
+        // There is no PPU yet, the tracer works its position out from the cycle
+        // count. That holds for nestest since rendering stays off throughout.
+        let actual = TraceFormat::Nestest.format(&TraceEntry::capture(&cpu, None));
+        if TraceLine::parse(line) != TraceLine::parse(&actual) {
+            let mut report = format!("Error: nestest diverges at line {}\n", i + 1);
+            for previous in &expected[i.saturating_sub(CONTEXT_LINES)..i] {
+                report += &format!("           {}\n", previous);
+            }
+            report += &format!("expected > {}\n", line);
+            report += &format!("actual   > {}\n", actual);
+            panic!("{}", report);
+        }
+
+        cpu.clock();
+        while !cpu.complete() {
+            cpu.clock();
+        }
+    }
+
+    // nestest leaves its error codes in $02 and $03.
+    assert_eq!((cpu.bus.peek(0x02), cpu.bus.peek(0x03)), (0, 0), "Error: nestest reported a failure");
+}
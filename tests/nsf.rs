@@ -0,0 +1,182 @@
+use rust_nes::bus::cartridge::nsf::{self, Nsf};
+use rust_nes::bus::cartridge::Cartridge;
+use rust_nes::cpu::Bus;
+use rust_nes::nsf_player::NsfPlayer;
+
+// init: STA $00 / STX $02 / RTS, play: INC $01 / RTS, both at $8000.
+const CODE: [u8; 8] = [0x85, 0x00, 0x86, 0x02, 0x60, 0xE6, 0x01, 0x60];
+
+fn nsf_file(banks: [u8; 8], chips: u8, data: &[u8]) -> Vec<u8> {
+    let mut file = vec![0; 0x80];
+    file[..5].copy_from_slice(b"NESM\x1a");
+    file[0x05] = 1;
+    file[0x06] = 3;
+    file[0x07] = 2;
+    file[0x08..0x0E].copy_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x05, 0x80]);
+    file[0x0E..0x13].copy_from_slice(b"Title");
+    file[0x2E..0x34].copy_from_slice(b"Artist");
+    file[0x4E..0x52].copy_from_slice(b"1986");
+    file[0x70..0x78].copy_from_slice(&banks);
+    file[0x7B] = chips;
+    file.extend_from_slice(data);
+    file
+}
+
+#[test]
+fn nsf_header() {
+    let nsf = Nsf::new(&nsf_file([0; 8], 0, &CODE));
+    assert_eq!((nsf.songs, nsf.starting_song), (3, 2));
+    assert_eq!((nsf.load_address, nsf.init_address, nsf.play_address), (0x8000, 0x8000, 0x8005));
+    assert_eq!((nsf.name.as_str(), nsf.artist.as_str(), nsf.copyright.as_str()), ("Title", "Artist", "1986"));
+    // No speed given, so the usual 60Hz.
+    assert_eq!(nsf.play_speed(), 16639);
+    assert!(!nsf.pal);
+    assert!(!nsf.bankswitched());
+    assert_eq!(nsf.data, CODE);
+}
+
+#[test]
+fn nsfe_chunks() {
+    let mut file = b"NSFE".to_vec();
+    let mut chunk = |id: &[u8; 4], body: &[u8]| {
+        file.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        file.extend_from_slice(id);
+        file.extend_from_slice(body);
+    };
+    chunk(b"INFO", &[0x00, 0x80, 0x00, 0x80, 0x05, 0x80, 0x00, 0x00, 4, 1]);
+    chunk(b"DATA", &CODE);
+    chunk(b"BANK", &[0, 1]);
+    chunk(b"RATE", &[0x10, 0x27]);
+    chunk(b"auth", b"Title\0Artist\0Copyright\0Ripper\0");
+    chunk(b"NEND", &[]);
+    let nsf = Nsf::new(&file);
+    // The starting song is 0 based in NSFe.
+    assert_eq!((nsf.songs, nsf.starting_song), (4, 2));
+    assert_eq!(nsf.play_address, 0x8005);
+    assert_eq!(nsf.banks, [0, 1, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(nsf.play_speed(), 10000);
+    assert_eq!(nsf.copyright, "Copyright");
+    assert_eq!(nsf.data, CODE);
+}
+
+#[test]
+fn bankswitching() {
+    // 8 banks of 4KB, each filled with its number.
+    let data: Vec<u8> = (0..8).flat_map(|bank| vec![bank; 0x1000]).collect();
+    let nsf = Nsf::new(&nsf_file([7, 6, 5, 4, 3, 2, 1, 0], 0, &data));
+    assert!(nsf.bankswitched());
+    let mut cartridge = Cartridge::new_nsf(&nsf);
+    assert_eq!((cartridge.cpu_read(0x8000), cartridge.cpu_read(0xFFFF)), (7, 0));
+    cartridge.cpu_write(0x5FF8, 3);
+    cartridge.cpu_write(0x5FFF, 5);
+    assert_eq!((cartridge.cpu_read(0x8FFF), cartridge.cpu_read(0x9000), cartridge.cpu_read(0xF000)), (3, 6, 5));
+}
+
+#[test]
+fn init_then_play_every_frame() {
+    let mut player = NsfPlayer::new(Nsf::new(&nsf_file([0; 8], 0, &CODE)));
+    player.start_track(3);
+    // INIT gets the track in A and the region in X, then returns to the driver.
+    while !(player.cpu.complete() && player.cpu.pc == nsf::DRIVER_IDLE) {
+        player.clock();
+    }
+    assert_eq!((player.cpu.bus.memory[0x00], player.cpu.bus.memory[0x01], player.cpu.bus.memory[0x02]), (2, 0, 0));
+    // PLAY every 16639us, 29780 cycles.
+    for _ in 0..10 * 29781 {
+        player.clock();
+    }
+    assert_eq!(player.cpu.bus.memory[0x01], 10);
+}
+
+#[test]
+fn fds_banks_are_copied_into_ram() {
+    let data: Vec<u8> = (0..8).flat_map(|bank| vec![bank; 0x1000]).collect();
+    let nsf = Nsf::new(&nsf_file([7, 6, 5, 4, 3, 2, 1, 0], nsf::CHIP_FDS, &data));
+    let mut cartridge = Cartridge::new_nsf(&nsf);
+    // 0x6000-0x7FFF get the 0xE000 and 0xF000 banks.
+    assert_eq!((cartridge.cpu_read(0x6000), cartridge.cpu_read(0x7000), cartridge.cpu_read(0x8000)), (1, 0, 7));
+    // It's RAM, until a bank is copied over it.
+    cartridge.cpu_write(0x8000, 0x55);
+    assert_eq!(cartridge.cpu_read(0x8000), 0x55);
+    cartridge.cpu_write(0x5FF8, 3);
+    assert_eq!((cartridge.cpu_read(0x8000), cartridge.cpu_read(0x8FFF)), (3, 3));
+
+    // And each track starts from the banks again.
+    let mut player = NsfPlayer::new(nsf);
+    player.start_track(1);
+    player.cpu.bus.write(0x9000, 0x55);
+    player.start_track(2);
+    assert_eq!(player.cpu.bus.read(0x9000), 6);
+}
+
+#[test]
+#[should_panic(expected = "Error: NSF load address $7000 is below $8000")]
+fn load_address_below_rom() {
+    let mut file = nsf_file([0; 8], 0, &CODE);
+    file[0x08..0x0A].copy_from_slice(&[0x00, 0x70]);
+    Nsf::new(&file);
+}
+
+#[test]
+fn vrc6_audio() {
+    let mut cartridge = Cartridge::new_nsf(&Nsf::new(&nsf_file([0; 8], nsf::CHIP_VRC6, &CODE)));
+    // Pulse 1 at volume 15, on 1/16 of the time with duty 0.
+    cartridge.cpu_write(0x9000, 0x0F);
+    cartridge.cpu_write(0x9001, 0x10);
+    cartridge.cpu_write(0x9002, 0x80);
+    let on = (0..4 * 16 * 17).filter(|_| {
+        cartridge.clock();
+        cartridge.audio_output() > 0.0
+    }).count();
+    assert_eq!(on, 4 * 17);
+    // Or all of it, ignoring the duty.
+    cartridge.cpu_write(0x9000, 0x8F);
+    assert_eq!(cartridge.audio_output(), 15.0 / 61.0);
+    // The registers are written through, ROM is still read.
+    assert_eq!(cartridge.cpu_read(0x9000), CODE[0]);
+}
+
+#[test]
+fn mmc5_audio() {
+    let mut cartridge = Cartridge::new_nsf(&Nsf::new(&nsf_file([0; 8], nsf::CHIP_MMC5, &CODE)));
+    cartridge.cpu_write(0x5011, 0xFF);
+    assert_eq!(cartridge.audio_output(), 0.5);
+    // Pulse 1 at 50% duty and a constant 15, its period too short for the APU's.
+    cartridge.cpu_write(0x5015, 0x01);
+    cartridge.cpu_write(0x5000, 0xBF);
+    cartridge.cpu_write(0x5003, 0x08);
+    assert_eq!(cartridge.cpu_read(0x5015), 0x01);
+    let loudest = (0..32).map(|_| {
+        cartridge.clock();
+        cartridge.audio_output()
+    }).fold(0.0, f32::max);
+    assert_eq!(loudest, 0.75);
+
+    cartridge.cpu_write(0x5205, 0x12);
+    cartridge.cpu_write(0x5206, 0x34);
+    assert_eq!((cartridge.cpu_read(0x5205), cartridge.cpu_read(0x5206)), (0xA8, 0x03));
+}
+
+#[test]
+fn n163_audio() {
+    let mut cartridge = Cartridge::new_nsf(&Nsf::new(&nsf_file([0; 8], nsf::CHIP_N163, &CODE)));
+    // A 4 sample wave starting with a 15, played by channel 7 alone at volume 15.
+    cartridge.cpu_write(0xF800, 0x80);
+    cartridge.cpu_write(0x4800, 0x0F);
+    cartridge.cpu_write(0xF800, 0x80 | 0x7C);
+    for data in [0xFC, 0x00, 0x00, 0x0F] {
+        cartridge.cpu_write(0x4800, data);
+    }
+    for _ in 0..15 {
+        cartridge.clock();
+    }
+    assert_eq!(cartridge.audio_output(), 1.0);
+    cartridge.cpu_write(0xF800, 0x7F);
+    assert_eq!(cartridge.cpu_read(0x4800), 0x0F);
+}
+
+#[test]
+#[should_panic(expected = "VRC7, Sunsoft 5B expansion sound isn't emulated")]
+fn unsupported_expansion_chips() {
+    NsfPlayer::new(Nsf::new(&nsf_file([0; 8], nsf::CHIP_VRC7 | nsf::CHIP_5B, &CODE)));
+}
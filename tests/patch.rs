@@ -0,0 +1,146 @@
+use rust_nes::bus::cartridge::patch;
+
+const SOURCE: &[u8] = b"ABCDEFGH";
+
+// Bit by bit, so it doesn't share the emulator's table.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn varint(out: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let bits = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(0x80 | bits);
+            return;
+        }
+        out.push(bits);
+        value -= 1;
+    }
+}
+
+// Input/source, output/target and patch CRC32s.
+fn footer(mut patch: Vec<u8>, input: &[u8], output: &[u8]) -> Vec<u8> {
+    patch.extend_from_slice(&crc32(input).to_le_bytes());
+    patch.extend_from_slice(&crc32(output).to_le_bytes());
+    let patch_crc = crc32(&patch);
+    patch.extend_from_slice(&patch_crc.to_le_bytes());
+    patch
+}
+
+#[test]
+fn ips_records_rle_and_truncate() {
+    let mut ips = b"PATCH".to_vec();
+    ips.extend_from_slice(&[0x00, 0x00, 0x02, 0x00, 0x02, 0xAA, 0xBB]);
+    // RLE past the end of the ROM grows it.
+    ips.extend_from_slice(&[0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x04, 0xCC]);
+    ips.extend_from_slice(b"EOF");
+    assert_eq!(patch::apply(&[0; 8], &ips), [0, 0, 0xAA, 0xBB, 0, 0, 0xCC, 0xCC, 0xCC, 0xCC]);
+
+    ips.extend_from_slice(&[0x00, 0x00, 0x09]);
+    assert_eq!(patch::apply(&[0; 8], &ips), [0, 0, 0xAA, 0xBB, 0, 0, 0xCC, 0xCC, 0xCC]);
+}
+
+fn bps(target: &[u8]) -> Vec<u8> {
+    let mut bps = b"BPS1".to_vec();
+    varint(&mut bps, SOURCE.len());
+    varint(&mut bps, target.len());
+    varint(&mut bps, 4);
+    bps.extend_from_slice(b"meta");
+    let action = |bps: &mut Vec<u8>, kind: usize, length: usize| varint(bps, (length - 1) << 2 | kind);
+    // SourceRead "AB"
+    action(&mut bps, 0, 2);
+    // TargetRead "xyz"
+    action(&mut bps, 1, 3);
+    bps.extend_from_slice(b"xyz");
+    // SourceCopy "GH" from +6
+    action(&mut bps, 2, 2);
+    varint(&mut bps, 6 << 1);
+    // TargetCopy "GHGH" from +5, overlapping what it writes
+    action(&mut bps, 3, 4);
+    varint(&mut bps, 5 << 1);
+    // SourceCopy "A" from -8, back to the start
+    action(&mut bps, 2, 1);
+    varint(&mut bps, 8 << 1 | 1);
+    footer(bps, SOURCE, target)
+}
+
+#[test]
+fn bps_actions() {
+    assert_eq!(patch::apply(SOURCE, &bps(b"ABxyzGHGHGHA")), b"ABxyzGHGHGHA");
+}
+
+#[test]
+#[should_panic(expected = "source checksum mismatch")]
+fn bps_source_checksum() {
+    patch::apply(b"ABCDEFGX", &bps(b"ABxyzGHGHGHA"));
+}
+
+#[test]
+#[should_panic(expected = "target checksum mismatch")]
+fn bps_target_checksum() {
+    patch::apply(SOURCE, &bps(b"ABxyzGHGHGHB"));
+}
+
+#[test]
+#[should_panic(expected = "patch checksum mismatch")]
+fn bps_patch_checksum() {
+    let mut bps = bps(b"ABxyzGHGHGHA");
+    bps[8] ^= 1;
+    patch::apply(SOURCE, &bps);
+}
+
+fn ups(input: &[u8], output: &[u8]) -> Vec<u8> {
+    let mut ups = b"UPS1".to_vec();
+    varint(&mut ups, input.len());
+    varint(&mut ups, output.len());
+    // 'B' -> 'b' at 1
+    varint(&mut ups, 1);
+    ups.extend_from_slice(&[b'B' ^ b'b', 0]);
+    // "IJ" past the input's end at 8, after skipping 3-7
+    varint(&mut ups, 5);
+    ups.extend_from_slice(&[b'I', b'J', 0]);
+    footer(ups, input, output)
+}
+
+#[test]
+fn ups_xor_and_size_change() {
+    let ups = ups(SOURCE, b"AbCDEFGHIJ");
+    assert_eq!(patch::apply(SOURCE, &ups), b"AbCDEFGHIJ");
+    // Applied to the output it gives back the input.
+    assert_eq!(patch::apply(b"AbCDEFGHIJ", &ups), SOURCE);
+}
+
+#[test]
+#[should_panic(expected = "input checksum mismatch")]
+fn ups_input_checksum() {
+    patch::apply(b"ABCDEFGX", &ups(SOURCE, b"AbCDEFGHIJ"));
+}
+
+#[test]
+#[should_panic(expected = "output checksum mismatch")]
+fn ups_output_checksum() {
+    patch::apply(SOURCE, &ups(SOURCE, b"AbCDEFGHIK"));
+}
+
+#[test]
+#[should_panic(expected = "patch checksum mismatch")]
+fn ups_patch_checksum() {
+    let mut ups = ups(SOURCE, b"AbCDEFGHIJ");
+    ups[6] ^= 1;
+    patch::apply(SOURCE, &ups);
+}
+
+#[test]
+#[should_panic(expected = "Unknown patch format")]
+fn unknown_format() {
+    patch::apply(SOURCE, b"NOPE");
+}
@@ -0,0 +1,83 @@
+use rust_nes::cpu::CPU;
+use rust_nes::disasm::Symbols;
+use rust_nes::flat_memory::FlatMemory;
+use rust_nes::profiler::Profiler;
+
+fn cpu_with(code: &[(u16, &[u8])]) -> CPU<FlatMemory> {
+    let mut memory = FlatMemory::new();
+    for (addr, bytes) in code {
+        memory.load(*addr, bytes);
+    }
+    let mut cpu = CPU::new(memory);
+    cpu.pc = 0x0200;
+    cpu.profiler = Some(Profiler::new());
+    cpu
+}
+
+// Runs instructions until PC gets to addr, then starts the next one so the
+// profiler sees the last one end.
+fn run_to(cpu: &mut CPU<FlatMemory>, addr: u16) {
+    while cpu.pc != addr {
+        cpu.clock();
+        while !cpu.complete() {
+            cpu.clock();
+        }
+    }
+    cpu.clock();
+}
+
+#[test]
+fn calls_interrupts_and_cycles() {
+    let mut cpu = cpu_with(&[
+        // main: JSR sub1, JSR sub2, BRK, done: JMP done
+        (0x0200, &[0x20, 0x00, 0x03, 0x20, 0x10, 0x03, 0x00, 0xEA, 0x4C, 0x08, 0x02]),
+        // sub1: JSR sub2, RTS
+        (0x0300, &[0x20, 0x10, 0x03, 0x60]),
+        // sub2: NOP, NOP, RTS
+        (0x0310, &[0xEA, 0xEA, 0x60]),
+        // RTI
+        (0x0400, &[0x40]),
+        (0xFFFE, &[0x00, 0x04]),
+    ]);
+    let mut symbols = Symbols::new();
+    symbols.insert(0x0300, "sub1");
+    symbols.insert(0x0310, "sub2");
+    cpu.profiler.as_mut().unwrap().symbols = Some(symbols);
+    run_to(&mut cpu, 0x0208);
+    let profiler = cpu.profiler.as_ref().unwrap();
+
+    let sub2 = profiler.routine("sub2").unwrap();
+    assert_eq!((sub2.calls, sub2.self_cycles, sub2.total_cycles), (2, 20, 20));
+    let sub1 = profiler.routine("sub1").unwrap();
+    assert_eq!((sub1.calls, sub1.self_cycles, sub1.total_cycles), (1, 12, 22));
+    // The BRK sequence counts for the handler.
+    let handler = profiler.routine("$0400(BRK)").unwrap();
+    assert_eq!((handler.calls, handler.self_cycles), (1, 13));
+    let main = profiler.routine("$0200").unwrap();
+    assert_eq!((main.self_cycles, main.total_cycles), (12, 57));
+    assert_eq!(main.max_frame_cycles, 57);
+
+    assert_eq!(profiler.folded(), "\
+$0200 12
+$0200;$0400(BRK) 13
+$0200;sub1 12
+$0200;sub1;sub2 10
+$0200;sub2 10");
+    assert!(profiler.call_tree().starts_with("$0200"));
+    assert!(profiler.flat().contains("sub1"));
+}
+
+#[test]
+fn rts_to_a_pushed_address_stays_in_the_routine() {
+    let mut cpu = cpu_with(&[
+        // main: JSR dispatch, done: JMP done
+        (0x0200, &[0x20, 0x00, 0x03, 0x4C, 0x03, 0x02]),
+        // dispatch: push $03FF and RTS to $0400
+        (0x0300, &[0xA9, 0x03, 0x48, 0xA9, 0xFF, 0x48, 0x60]),
+        // RTS back to main
+        (0x0400, &[0x60]),
+    ]);
+    run_to(&mut cpu, 0x0203);
+    let profiler = cpu.profiler.as_ref().unwrap();
+    assert_eq!(profiler.folded(), "$0200 6\n$0200;$0300 22");
+}
@@ -0,0 +1,33 @@
+use rust_nes::bus::RamInit;
+use std::thread;
+use std::time::Duration;
+
+fn filled(init: RamInit) -> Vec<u8> {
+    let mut memory = vec![0x55; 0x800];
+    init.fill(&mut memory);
+    memory
+}
+
+#[test]
+fn patterns() {
+    assert_eq!(filled(RamInit::parse("zeros").unwrap()), vec![0x00; 0x800]);
+    assert_eq!(filled(RamInit::parse("ff").unwrap()), vec![0xFF; 0x800]);
+    assert_eq!(filled(RamInit::parse("hardware").unwrap())[..12], [0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0]);
+    assert_eq!(RamInit::parse("random:x"), None);
+    assert_eq!(RamInit::parse("ones"), None);
+}
+
+#[test]
+fn random_seeds() {
+    // A given seed always fills RAM the same way.
+    assert_eq!(RamInit::parse("random:1234"), Some(RamInit::Random(1234)));
+    assert_eq!(filled(RamInit::Random(1234)), filled(RamInit::Random(1234)));
+    assert_ne!(filled(RamInit::Random(1234)), filled(RamInit::Random(1235)));
+
+    // Without one it comes from the clock.
+    let first = RamInit::parse("random").unwrap();
+    thread::sleep(Duration::from_millis(2));
+    let second = RamInit::parse("random").unwrap();
+    assert!(matches!(first, RamInit::Random(_)));
+    assert_ne!(first, second);
+}
@@ -0,0 +1,129 @@
+use rust_nes::cpu::{Bus, CPU};
+use rust_nes::disasm::{self, Symbols};
+
+// 48KB of PRG ROM in 16KB banks. Writes to ROM pick the first or second one
+// for $8000, the last one is fixed at $C000, like UxROM. Anything below $8000 is RAM.
+struct BankedBus {
+    ram: Vec<u8>,
+    prg: Vec<u8>,
+    bank: usize,
+}
+
+impl BankedBus {
+    fn new() -> Self {
+        let mut prg = vec![0xEA; 0xC000];
+        // JMP $C000 at the start of both banks, JSR $8000 at the start of the fixed one.
+        for bank in 0..2 {
+            prg[bank * 0x4000..bank * 0x4000 + 3].copy_from_slice(&[0x4C, 0x00, 0xC0]);
+        }
+        prg[0x8000..0x8003].copy_from_slice(&[0x20, 0x00, 0x80]);
+        BankedBus { ram: vec![0; 0x8000], prg, bank: 0 }
+    }
+}
+
+impl Bus for BankedBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.peek(addr)
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x8000..=0xFFFF => self.bank = data as usize & 1,
+            _ => self.ram[addr as usize] = data,
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        match self.prg_rom_offset(addr) {
+            Some(offset) => self.prg[offset],
+            None => self.ram[addr as usize],
+        }
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000..=0xBFFF => Some(self.bank * 0x4000 + (addr as usize - 0x8000)),
+            0xC000..=0xFFFF => Some(0x8000 + (addr as usize - 0xC000)),
+            _ => None,
+        }
+    }
+}
+
+fn operand_at(cpu: &CPU<BankedBus>, addr: u16, symbols: &Symbols) -> String {
+    disasm::disassemble(&cpu.bus, cpu.opcodes(), addr, Some(symbols)).text
+}
+
+#[test]
+fn ca65_debug_info() {
+    let dbg = "\
+version\tmajor=2,minor=0
+seg\tid=0,name=\"HEADER\",start=0x000000,size=0x0010,addrsize=absolute,type=ro,oname=\"game, final.nes\",ooffs=0
+seg\tid=1,name=\"BANK0\",start=0x008000,size=0x4000,addrsize=absolute,type=ro,oname=\"game, final.nes\",ooffs=16
+seg\tid=2,name=\"BANK1\",start=0x008000,size=0x4000,addrsize=absolute,type=ro,oname=\"game, final.nes\",ooffs=16400
+seg\tid=3,name=\"FIXED\",start=0x00C000,size=0x4000,addrsize=absolute,type=ro,oname=\"game, final.nes\",ooffs=32784
+seg\tid=4,name=\"ZEROPAGE\",start=0x000000,size=0x0010,addrsize=zeropage,type=rw
+sym\tid=0,name=\"music_init\",addrsize=absolute,scope=0,def=1,ref=9,val=0x8000,seg=1,type=lab
+sym\tid=1,name=\"level_load\",addrsize=absolute,scope=0,def=2,val=0x8000,seg=2,type=lab
+sym\tid=2,name=\"reset\",addrsize=absolute,scope=0,def=3,val=0xC000,seg=3,type=lab
+sym\tid=3,name=\"frame_count\",addrsize=zeropage,scope=0,def=4,val=0x10,seg=4,type=lab
+sym\tid=4,name=\"PPUCTRL\",addrsize=absolute,scope=0,def=5,val=0x2000,type=equ
+";
+    let symbols = Symbols::parse_dbg(dbg);
+    assert_eq!(symbols.len(), 4);
+    let mut cpu = CPU::new(BankedBus::new());
+
+    assert_eq!(operand_at(&cpu, 0x8000, &symbols), "JMP reset");
+    assert_eq!(operand_at(&cpu, 0xC000, &symbols), "JSR music_init");
+    assert_eq!(symbols.label(&cpu.bus, 0x0010), Some("frame_count"));
+    assert_eq!(symbols.label(&cpu.bus, 0x2000), None);
+    assert_eq!(symbols.address(&cpu.bus, "music_init"), Some(0x8000));
+    // Not mapped, so where it was assembled to.
+    assert_eq!(symbols.address(&cpu.bus, "level_load"), Some(0x8000));
+
+    cpu.bus.write(0x8000, 1);
+    assert_eq!(operand_at(&cpu, 0xC000, &symbols), "JSR level_load");
+    assert_eq!(symbols.label(&cpu.bus, 0x8000), Some("level_load"));
+}
+
+#[test]
+fn nesasm_fns() {
+    let fns = "\
+; game.asm
+reset                    = $C000
+music_init               = $8000
+frame_count              = $0010
+";
+    let symbols = Symbols::parse_fns(fns);
+    let mut cpu = CPU::new(BankedBus::new());
+    assert_eq!(operand_at(&cpu, 0x8000, &symbols), "JMP reset");
+    // Without banks, the label stays whichever bank is mapped.
+    cpu.bus.write(0x8000, 1);
+    assert_eq!(operand_at(&cpu, 0xC000, &symbols), "JSR music_init");
+    assert_eq!(symbols.address(&cpu.bus, "frame_count"), Some(0x0010));
+}
+
+#[test]
+fn mesen_labels() {
+    let mlb = "\
+P:0000:music_init
+P:4000:level_load:Loads the level in A
+P:8000:reset
+P:8003::A comment without a label
+R:0010:frame_count
+S:0100-01FF:save_data
+G:2000:PPUCTRL
+";
+    let symbols = Symbols::parse_mlb(mlb);
+    assert_eq!(symbols.len(), 6);
+    let mut cpu = CPU::new(BankedBus::new());
+    assert_eq!(operand_at(&cpu, 0xC000, &symbols), "JSR music_init");
+    assert_eq!(symbols.label(&cpu.bus, 0x6100), Some("save_data"));
+    assert_eq!(symbols.label(&cpu.bus, 0x2000), Some("PPUCTRL"));
+    assert_eq!(symbols.address(&cpu.bus, "reset"), Some(0xC000));
+    // Not mapped and no address to fall back on.
+    assert_eq!(symbols.address(&cpu.bus, "level_load"), None);
+
+    cpu.bus.write(0x8000, 1);
+    assert_eq!(operand_at(&cpu, 0xC000, &symbols), "JSR level_load");
+    assert_eq!(symbols.address(&cpu.bus, "level_load"), Some(0x8000));
+}
@@ -0,0 +1,119 @@
+// The $6000 protocol of blargg's test ROMs, run on NROM images made up here.
+use rust_nes::test_rom::{self, TestStatus};
+
+const ORIGIN: u16 = 0xC000;
+
+// A 16KB NROM image with code at $C000, where all the vectors point.
+fn nrom(code: &[u8], mapper: u8) -> Vec<u8> {
+    let mut image = b"NES\x1a".to_vec();
+    image.extend_from_slice(&[1, 1, mapper << 4, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    let mut prg = vec![0xEA; 0x4000];
+    prg[..code.len()].copy_from_slice(code);
+    prg[0x3FFA..].copy_from_slice(&[0x00, 0xC0, 0x00, 0xC0, 0x00, 0xC0]);
+    image.extend_from_slice(&prg);
+    image.extend_from_slice(&[0; 0x2000]);
+    image
+}
+
+// LDA #value, STA addr
+fn store(code: &mut Vec<u8>, addr: u16, value: u8) {
+    let [low, high] = addr.to_le_bytes();
+    code.extend_from_slice(&[0xA9, value, 0x8D, low, high]);
+}
+
+// JMP to itself
+fn hang(code: &mut Vec<u8>) {
+    let [low, high] = (ORIGIN + code.len() as u16).to_le_bytes();
+    code.extend_from_slice(&[0x4C, low, high]);
+}
+
+// Writes the signature and the text, then status to $6000.
+fn reporting(status: u8, text: &str) -> Vec<u8> {
+    let mut code = Vec::new();
+    for (i, &byte) in text.as_bytes().iter().chain(&[0]).enumerate() {
+        store(&mut code, 0x6004 + i as u16, byte);
+    }
+    for (i, &byte) in [0xDE, 0xB0, 0x61].iter().enumerate() {
+        store(&mut code, 0x6001 + i as u16, byte);
+    }
+    store(&mut code, 0x6000, status);
+    hang(&mut code);
+    code
+}
+
+#[test]
+fn passed() {
+    let result = test_rom::run(&nrom(&reporting(0x00, "All tests passed\n"), 0), 10);
+    assert_eq!(result.status, TestStatus::Passed);
+    assert_eq!(result.message, "All tests passed");
+    assert_eq!(result.frames, 1);
+}
+
+#[test]
+fn failed() {
+    let result = test_rom::run(&nrom(&reporting(0x03, "Wrong timing\n\nFailed #3"), 0), 10);
+    assert_eq!(result.status, TestStatus::Failed(3));
+    assert_eq!(result.message, "Wrong timing\n\nFailed #3");
+}
+
+#[test]
+fn timed_out() {
+    // Running forever.
+    let result = test_rom::run(&nrom(&reporting(0x80, ""), 0), 10);
+    assert_eq!(result.status, TestStatus::TimedOut);
+    assert_eq!(result.frames, 10);
+
+    // Or never writing the signature.
+    let mut code = Vec::new();
+    store(&mut code, 0x6000, 0x00);
+    hang(&mut code);
+    assert_eq!(test_rom::run(&nrom(&code, 0), 10).status, TestStatus::TimedOut);
+}
+
+#[test]
+fn reset_when_asked() {
+    // INC $6100, then $81 the first time round and a pass after the reset,
+    // PRG-RAM keeps the count.
+    let mut code = vec![0xEE, 0x00, 0x61, 0xAD, 0x00, 0x61, 0xC9, 0x02];
+    let ask = reporting(0x81, "");
+    let pass = reporting(0x00, "Passed after a reset");
+    code.extend_from_slice(&[0xF0, ask.len() as u8]);
+    // Both jump to themselves, moved along with the code before them.
+    let relocate = |mut part: Vec<u8>, at: usize| {
+        let end = part.len();
+        let [low, high] = (ORIGIN + (at + end - 3) as u16).to_le_bytes();
+        part[end - 2..].copy_from_slice(&[low, high]);
+        part
+    };
+    let at = code.len();
+    code.extend(relocate(ask, at));
+    let at = code.len();
+    code.extend(relocate(pass, at));
+
+    let result = test_rom::run(&nrom(&code, 0), 30);
+    assert_eq!(result.status, TestStatus::Passed);
+    assert_eq!(result.message, "Passed after a reset");
+    assert!(result.frames > 6);
+}
+
+#[test]
+fn jammed() {
+    let result = test_rom::run(&nrom(&[0xEA, 0x02], 0), 10);
+    assert_eq!(result.status, TestStatus::Jammed(0xC001));
+}
+
+#[test]
+fn unsupported_mapper_is_skipped() {
+    let result = test_rom::run(&nrom(&reporting(0x00, ""), 4), 10);
+    assert_eq!(result.status, TestStatus::Skipped);
+    assert_eq!(result.message, "Mapper 4 isn't supported");
+
+    let results = vec![
+        (String::from("mmc3.nes"), result),
+        (String::from("nrom.nes"), test_rom::run(&nrom(&reporting(0x00, "Passed"), 0), 10)),
+    ];
+    let table = test_rom::format_table(&results);
+    assert!(table.contains("mmc3.nes  SKIP"));
+    assert!(table.contains("nrom.nes  pass"));
+    assert!(table.ends_with("1/2 passed, 1 skipped\n"));
+}
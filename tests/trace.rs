@@ -0,0 +1,112 @@
+use rust_nes::cpu::CPU;
+use rust_nes::flat_memory::FlatMemory;
+use rust_nes::trace::{FileSink, RingBuffer, TraceCondition, TraceEntry, TraceFormat, TraceLogger, TraceSink};
+use std::fs;
+
+// LDA $0300,X with X = 2, then LAX $10 and NOPs, from $C000.
+fn cpu() -> CPU<FlatMemory> {
+    let mut memory = FlatMemory::new();
+    memory.load(0xC000, &[0xBD, 0x00, 0x03, 0xA7, 0x10]);
+    memory.load(0xC005, &[0xEA; 16]);
+    memory.load(0x0302, &[0x5A]);
+    memory.load(0x0010, &[0x99]);
+    let mut cpu = CPU::new(memory);
+    cpu.pc = 0xC000;
+    cpu.x = 2;
+    cpu
+}
+
+fn run_instruction(cpu: &mut CPU<FlatMemory>) {
+    cpu.clock();
+    while !cpu.complete() {
+        cpu.clock();
+    }
+}
+
+#[test]
+fn nestest_line() {
+    let mut cpu = cpu();
+    assert_eq!(TraceFormat::Nestest.format(&TraceEntry::capture(&cpu, None)),
+               "C000  BD 00 03  LDA $0300,X @ 0302 = 5A         A:00 X:02 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7");
+    // Unofficial opcodes get a *.
+    run_instruction(&mut cpu);
+    assert_eq!(TraceFormat::Nestest.format(&TraceEntry::capture(&cpu, None)),
+               "C003  A7 10    *LAX $10 = 99                    A:5A X:02 Y:00 P:24 SP:FD PPU:  0, 33 CYC:11");
+}
+
+#[test]
+fn mesen_line() {
+    let cpu = cpu();
+    assert_eq!(TraceFormat::Mesen.format(&TraceEntry::capture(&cpu, None)),
+               "C000  LDA $0300,X [$0302] = $5A      A:00 X:02 Y:00 S:FD P:nvUbdIzc  V:0   H:21  Fr:0 Cycle:7");
+}
+
+#[test]
+fn json_line() {
+    let mut cpu = cpu();
+    assert_eq!(TraceFormat::JsonLines.format(&TraceEntry::capture(&cpu, None)),
+               "{\"pc\":49152,\"bytes\":[189,0,3],\"asm\":\"LDA $0300,X\",\"official\":true,\"addr\":770,\"value\":90,\
+                \"a\":0,\"x\":2,\"y\":0,\"p\":36,\"sp\":253,\"cycles\":7,\"frame\":0,\"scanline\":0,\"dot\":21}");
+    run_instruction(&mut cpu);
+    run_instruction(&mut cpu);
+    assert_eq!(TraceFormat::JsonLines.format(&TraceEntry::capture(&cpu, None)),
+               "{\"pc\":49157,\"bytes\":[234],\"asm\":\"NOP\",\"official\":true,\"addr\":null,\"value\":null,\
+                \"a\":153,\"x\":153,\"y\":0,\"p\":164,\"sp\":253,\"cycles\":14,\"frame\":0,\"scanline\":0,\"dot\":42}");
+}
+
+#[test]
+fn ring_buffer_wraps_around() {
+    let mut ring = RingBuffer::new(3);
+    let reader = ring.clone();
+    for line in ["1", "2", "3", "4", "5"] {
+        ring.write_line(line);
+    }
+    assert_eq!(reader.lines(), ["3", "4", "5"]);
+    reader.clear();
+    assert!(ring.lines().is_empty());
+
+    let mut empty = RingBuffer::new(0);
+    empty.write_line("1");
+    assert!(empty.lines().is_empty());
+}
+
+#[test]
+fn logger_start_and_stop() {
+    let ring = RingBuffer::new(10);
+    let mut logger = TraceLogger::new(Box::new(ring.clone()), TraceFormat::Nestest);
+    logger.start = TraceCondition::parse("pc:C003");
+    logger.stop = TraceCondition::parse("pc:C007-C008");
+    let mut cpu = cpu();
+    cpu.tracer = Some(Box::new(logger));
+    for _ in 0..8 {
+        run_instruction(&mut cpu);
+    }
+    let pcs: Vec<String> = ring.lines().iter().map(|line| line[..4].to_string()).collect();
+    assert_eq!(pcs, ["C003", "C005", "C006"]);
+}
+
+#[test]
+fn conditions() {
+    assert_eq!(TraceCondition::parse("pc:C000"), Some(TraceCondition::Pc(0xC000, 0xC000)));
+    assert_eq!(TraceCondition::parse("pc:8000-80FF"), Some(TraceCondition::Pc(0x8000, 0x80FF)));
+    assert_eq!(TraceCondition::parse("frame:60"), Some(TraceCondition::Frame(60)));
+    assert_eq!(TraceCondition::parse("pc:xyz"), None);
+    assert_eq!(TraceCondition::parse("line:3"), None);
+    assert_eq!(TraceFormat::parse("json"), Some(TraceFormat::JsonLines));
+    assert_eq!(TraceFormat::parse("fceux"), None);
+}
+
+#[test]
+fn file_sink() {
+    let path = std::env::temp_dir().join(format!("rust-nes-trace-{}.log", std::process::id()));
+    let path = path.to_str().unwrap();
+    {
+        let mut sink = FileSink::create(path).unwrap();
+        sink.write_line("first");
+        sink.write_line("second");
+        assert!(sink.error.is_none());
+    }
+    // Flushed when dropped.
+    assert_eq!(fs::read_to_string(path).unwrap(), "first\nsecond\n");
+    fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,126 @@
+// PC and SP wrap around silently on the 6502. These run in debug builds, where
+// any non-wrapping arithmetic on them would panic with an overflow.
+use rust_nes::cpu::CPU;
+use rust_nes::flat_memory::FlatMemory;
+
+fn cpu_with(code: &[(u16, &[u8])]) -> CPU<FlatMemory> {
+    let mut memory = FlatMemory::new();
+    for (addr, bytes) in code {
+        memory.load(*addr, bytes);
+    }
+    CPU::new(memory)
+}
+
+fn run_instructions(cpu: &mut CPU<FlatMemory>, count: usize) {
+    for _ in 0..count {
+        cpu.clock();
+        while !cpu.complete() {
+            cpu.clock();
+        }
+    }
+}
+
+#[test]
+fn push_wraps_from_0100_to_01ff() {
+    // PHA, PHA
+    let mut cpu = cpu_with(&[(0x0200, &[0x48, 0x48])]);
+    cpu.pc = 0x0200;
+    cpu.sp = 0x00;
+    cpu.a = 0x42;
+    run_instructions(&mut cpu, 2);
+
+    assert_eq!(cpu.sp, 0xFE);
+    assert_eq!(cpu.bus.memory[0x0100], 0x42);
+    assert_eq!(cpu.bus.memory[0x01FF], 0x42);
+}
+
+#[test]
+fn pull_wraps_from_01ff_to_0100() {
+    // PLA
+    let mut cpu = cpu_with(&[(0x0200, &[0x68]), (0x0100, &[0x37])]);
+    cpu.pc = 0x0200;
+    cpu.sp = 0xFF;
+    run_instructions(&mut cpu, 1);
+
+    assert_eq!(cpu.sp, 0x00);
+    assert_eq!(cpu.a, 0x37);
+}
+
+#[test]
+fn jsr_and_rts_across_the_stack_wrap() {
+    // JSR $0300 / RTS
+    let mut cpu = cpu_with(&[(0x0200, &[0x20, 0x00, 0x03]), (0x0300, &[0x60])]);
+    cpu.pc = 0x0200;
+    cpu.sp = 0x00;
+    run_instructions(&mut cpu, 1);
+
+    assert_eq!(cpu.pc, 0x0300);
+    assert_eq!(cpu.sp, 0xFE);
+    assert_eq!(cpu.bus.memory[0x0100], 0x02);
+    assert_eq!(cpu.bus.memory[0x01FF], 0x02);
+
+    run_instructions(&mut cpu, 1);
+    assert_eq!(cpu.pc, 0x0203);
+    assert_eq!(cpu.sp, 0x00);
+}
+
+#[test]
+fn pc_wraps_from_ffff_to_0000() {
+    // NOP at $FFFF, then LDA #$55 at $0000.
+    let mut cpu = cpu_with(&[(0xFFFF, &[0xEA]), (0x0000, &[0xA9, 0x55])]);
+    cpu.pc = 0xFFFF;
+    run_instructions(&mut cpu, 2);
+
+    assert_eq!(cpu.a, 0x55);
+    assert_eq!(cpu.pc, 0x0002);
+}
+
+#[test]
+fn operand_fetch_wraps_past_ffff() {
+    // LDA $0010 split over $FFFE-$0000.
+    let mut cpu = cpu_with(&[(0xFFFE, &[0xAD, 0x10]), (0x0000, &[0x00]), (0x0010, &[0x99])]);
+    cpu.pc = 0xFFFE;
+    run_instructions(&mut cpu, 1);
+
+    assert_eq!(cpu.a, 0x99);
+    assert_eq!(cpu.pc, 0x0001);
+}
+
+#[test]
+fn branch_wraps_in_both_directions() {
+    // BNE +$10 at $FFF0, BNE -$10 at $0000.
+    let mut cpu = cpu_with(&[(0xFFF0, &[0xD0, 0x0E]), (0x0000, &[0xD0, 0xEE])]);
+    cpu.pc = 0xFFF0;
+    run_instructions(&mut cpu, 1);
+    assert_eq!(cpu.pc, 0x0000);
+
+    run_instructions(&mut cpu, 1);
+    assert_eq!(cpu.pc, 0xFFF0);
+}
+
+#[test]
+fn rts_to_ffff_returns_to_0000() {
+    // RTS with $FFFF on the stack, which is where a JSR ending at $FFFF pushes.
+    let mut cpu = cpu_with(&[(0x0200, &[0x60]), (0x01FE, &[0xFF, 0xFF])]);
+    cpu.pc = 0x0200;
+    cpu.sp = 0xFD;
+    run_instructions(&mut cpu, 1);
+
+    assert_eq!(cpu.pc, 0x0000);
+    assert_eq!(cpu.sp, 0xFF);
+}
+
+#[test]
+fn brk_at_ffff_pushes_wrapped_return_address() {
+    // BRK at $FFFF, which is also the high byte of the $0000 IRQ vector.
+    let mut cpu = cpu_with(&[(0xFFFE, &[0x00, 0x00])]);
+    cpu.pc = 0xFFFF;
+    cpu.sp = 0x01;
+    run_instructions(&mut cpu, 1);
+
+    // Return address is $FFFF + 2, pushed across the stack wrap.
+    assert_eq!(cpu.bus.memory[0x0101], 0x00);
+    assert_eq!(cpu.bus.memory[0x0100], 0x01);
+    assert_eq!(cpu.sp, 0xFE);
+    assert_eq!(cpu.pc, 0x0000);
+}